@@ -1,10 +1,22 @@
 mod websocket;
 
-use cadence_core::{Library, LibraryRecord, Player, PlayerMode, TrackInfo, TrackRecord};
+use cadence_core::{
+    AlbumEntry, AlbumSort, ArtistEntry, ArtistSort, EqPreset, EqSettings, GenreEntry, GenreSort, IndexSummary, Library,
+    LibraryEvent, LibraryRecord, LibraryWatcher, Page, PageRequest, PlayContext, PlayQueue, Player, PlayerEvent,
+    PlayerMode, Playlist, PlaylistEvent, PlaylistImport, PlaylistItem, QueueEvent, ReplayGainMode, ScanIssue,
+    SmartPlaylist, SmartPlaylistRules, TrackFilter, TrackInfo, TrackRecord, TrackSort, YearEntry,
+};
+use cadence_core::output::NullOutput;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::Serialize;
-use std::path::PathBuf;
-use std::sync::{mpsc, Arc};
-use tauri::{Manager, State};
+use std::path::{Path, PathBuf};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{Emitter, Manager, State};
+use tokio::sync::broadcast;
 
 pub(crate) enum PlayerMessage {
     Play(PathBuf, mpsc::SyncSender<Result<TrackInfo, String>>),
@@ -15,7 +27,30 @@ pub(crate) enum PlayerMessage {
     Next,
     Seek(u64, mpsc::SyncSender<Result<(), String>>),
     SetMode(PlayerMode),
+    SetCrossfade(u64),
+    SetReplayGain(ReplayGainMode, f32),
+    SetVolume(f32),
+    SetMuted(bool),
+    SetEqualizer(EqSettings),
+    SetEqEnabled(bool),
+    SetOutputDevice(Option<String>, mpsc::SyncSender<Result<(), String>>),
     Status(mpsc::SyncSender<Option<StatusResponse>>),
+    Queue(QueueCommand, mpsc::SyncSender<Result<PlayQueue, String>>),
+}
+
+/// Reads and edits of the play queue. Each is answered with the resulting queue.
+pub(crate) enum QueueCommand {
+    Get,
+    Enqueue(Vec<PathBuf>),
+    PlayNext(Vec<PathBuf>),
+    /// Move an upcoming item (by id) to an index among the upcoming items.
+    Move(i64, usize),
+    Remove(i64),
+    Clear,
+    /// Jump to an upcoming item, sending the ones before it to the history.
+    SkipTo(i64),
+    /// Play a context from the track at an index, followed by the rest of it.
+    PlayContext(PlayContext, usize),
 }
 
 struct PlayerHandle {
@@ -25,111 +60,499 @@ struct PlayerHandle {
 // Safety: Sender<T> is Send+Sync when T: Send, which holds here
 unsafe impl Sync for PlayerHandle {}
 
+/// Wakes the background loudness analysis after the library changed.
+struct LoudnessHandle {
+    tx: mpsc::Sender<()>,
+}
+
+/// Lets a running library scan report progress and be cancelled.
+struct ScanHandle {
+    /// Shared with the WS server
+    scans: RunningScans,
+    events: broadcast::Sender<AppEvent>,
+}
+
+/// The library scans running now, each with a cancel flag of its own, so cancelling
+/// stops them and not a scan started afterwards.
+#[derive(Clone, Default)]
+pub(crate) struct RunningScans(Arc<Mutex<Vec<Arc<AtomicBool>>>>);
+
+impl RunningScans {
+    /// The cancel flag of a scan about to start, registered until the guard is dropped.
+    fn start(&self) -> RunningScan {
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().push(Arc::clone(&cancel));
+        RunningScan { scans: self.clone(), cancel }
+    }
+
+    /// Cancel every scan running now.
+    pub(crate) fn cancel(&self) {
+        for cancel in self.0.lock().unwrap().iter() {
+            cancel.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+struct RunningScan {
+    scans: RunningScans,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Drop for RunningScan {
+    fn drop(&mut self) {
+        self.scans.0.lock().unwrap().retain(|cancel| !Arc::ptr_eq(cancel, &self.cancel));
+    }
+}
+
+/// Lets commands tell the desktop UI and every WS client about changes they made.
+struct EventsHandle {
+    tx: broadcast::Sender<AppEvent>,
+}
+
+/// Follows changes in the library folders; None when the watcher couldn't start.
+struct WatcherHandle {
+    watcher: Mutex<Option<LibraryWatcher>>,
+}
+
+/// Everything pushed to the desktop UI and WS clients without being asked for.
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum AppEvent {
+    Player(PlayerEvent),
+    Library(LibraryEvent),
+    Queue(QueueEvent),
+    Playlist(PlaylistEvent),
+}
+
 #[derive(Serialize)]
 pub(crate) struct StatusResponse {
     pub path: String,
-    pub duration_ms: u64,
+    pub duration_ms: Option<u64>,
+    pub seekable: bool,
     pub position_ms: u64,
+    /// Wall-clock time (ms since the Unix epoch) at which `position_ms` was read
+    pub snapshot_at_ms: u64,
     pub paused: bool,
     pub title: Option<String>,
     pub artist: Option<String>,
+    /// Cover art of the track, fetched with the `artwork` command
+    pub artwork_id: Option<i64>,
     pub mode: PlayerMode,
+    pub crossfade_ms: u64,
+    pub replay_gain_mode: ReplayGainMode,
+    pub preamp_db: f32,
+    pub volume: f32,
+    pub muted: bool,
+    pub equalizer: EqSettings,
+    pub output_device: Option<String>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
 }
 
-fn spawn_player_thread(lib_rx: mpsc::Receiver<Arc<Library>>) -> mpsc::Sender<PlayerMessage> {
+/// How often the player thread wakes up on its own to pick up track transitions.
+const PLAYER_TICK: Duration = Duration::from_millis(50);
+
+// Keys of player settings persisted in the library DB.
+const VOLUME_SETTING: &str = "volume";
+const MUTED_SETTING: &str = "muted";
+const EQUALIZER_SETTING: &str = "equalizer";
+const OUTPUT_DEVICE_SETTING: &str = "output_device";
+
+/// Delay before restarting a player thread that panicked, so a fault that hits
+/// right at startup doesn't spin.
+const PLAYER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Spawn the player thread under a supervisor that restarts it if it panics.
+/// Clients get an error event for each restart; the command being handled at the
+/// time of the panic fails with "Player thread died", later ones go to the new player.
+fn spawn_player_thread(
+    lib_rx: mpsc::Receiver<Arc<Library>>,
+    events: broadcast::Sender<AppEvent>,
+) -> mpsc::Sender<PlayerMessage> {
     let (tx, rx) = mpsc::channel();
 
     std::thread::spawn(move || {
         let library = lib_rx.recv().expect("Library init failed");
-        let mut player = Player::new().expect("Failed to create player");
-        // history[history_pos] is always the currently playing track (when non-empty).
-        let mut history: Vec<PathBuf> = Vec::new();
-        let mut history_pos: usize = 0;
-
-        // Advance to the next track: replay forward history or pick a random one.
-        let advance = |player: &mut Player, history: &mut Vec<PathBuf>, history_pos: &mut usize, library: &Library| {
-            if *history_pos + 1 < history.len() {
-                *history_pos += 1;
-                player.load_and_play(history[*history_pos].clone()).ok();
-            } else {
-                let current = player.current_track().map(|t| t.info.path.clone());
-                if let Ok(paths) = library.all_track_paths() {
-                    use rand::seq::SliceRandom;
-                    let candidates: Vec<&PathBuf> = paths.iter()
-                        .filter(|p| Some(*p) != current.as_ref())
-                        .collect();
-                    if let Some(next_path) = candidates.choose(&mut rand::thread_rng()) {
-                        let next_path = (*next_path).clone();
-                        history.push(next_path.clone());
-                        *history_pos = history.len() - 1;
-                        player.load_and_play(next_path).ok();
-                    }
+        loop {
+            let run = std::panic::catch_unwind(AssertUnwindSafe(|| run_player(&library, &rx, &events)));
+            let Err(panic) = run else { break };
+
+            let reason = panic.downcast_ref::<&str>().copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            let message = format!("Player crashed and was restarted: {reason}");
+            eprintln!("{message}");
+            events.send(AppEvent::Player(PlayerEvent::Error { message })).ok();
+            std::thread::sleep(PLAYER_RESTART_DELAY);
+        }
+    });
+
+    tx
+}
+
+/// Serve player commands until every sender is gone.
+fn run_player(library: &Arc<Library>, rx: &mpsc::Receiver<PlayerMessage>, events: &broadcast::Sender<AppEvent>) {
+    // Without an output device, keep serving commands on the null output rather than
+    // failing here, which would restart the thread over and over.
+    let mut player = Player::new().unwrap_or_else(|e| {
+        let message = format!("No audio output, playing silently: {e:#}");
+        eprintln!("{message}");
+        events.send(AppEvent::Player(PlayerEvent::Error { message })).ok();
+        Player::with_output(Box::new(NullOutput::new(1.0))).expect("The null output always opens")
+    });
+    if let Some(volume) = library.setting(VOLUME_SETTING).ok().flatten().and_then(|v| v.parse().ok()) {
+        player.set_volume(volume);
+    }
+    if let Some(muted) = library.setting(MUTED_SETTING).ok().flatten().and_then(|v| v.parse().ok()) {
+        player.mute(muted);
+    }
+    if let Some(eq) = library.setting(EQUALIZER_SETTING).ok().flatten().and_then(|v| serde_json::from_str(&v).ok()) {
+        player.set_equalizer(eq);
+    }
+    if let Some(device) = library.setting(OUTPUT_DEVICE_SETTING).ok().flatten().filter(|d| !d.is_empty()) {
+        // Stay on the default device if the saved one isn't plugged in.
+        if let Err(e) = player.set_output_device(Some(device)) {
+            eprintln!("{e:#}");
+        }
+    }
+    let mut queue = library.load_queue().unwrap_or_else(|e| {
+        eprintln!("Failed to restore the play queue: {e:#}");
+        PlayQueue::default()
+    });
+    // Id of the queue item handed to the player ahead of time, if the queued track is one.
+    let mut prequeued: Option<i64> = None;
+    // Cover art of the track last asked about, so status polls don't hit the DB each time.
+    let mut artwork: Option<(PathBuf, Option<i64>)> = None;
+    // Every time a track starts playing counts as a play, written off this thread.
+    let plays = spawn_play_counter(Arc::clone(library));
+    let count_play = |path: &Path| {
+        plays.send(path.to_path_buf()).ok();
+    };
+
+    // Loudness measured by the library, for tracks whose tags carry no ReplayGain values.
+    let analyzed = |path: &Path| library.analyzed_replay_gain(path).ok().flatten();
+
+    // In Shuffle, keep a random pick lined up once the queue runs dry, unless it was
+    // playing through a context. Returns whether the queue changed.
+    let top_up = |player: &Player, queue: &mut PlayQueue| -> bool {
+        if queue.peek_next().is_some()
+            || queue.context().is_some()
+            || !matches!(player.get_mode(), PlayerMode::Shuffle)
+        {
+            return false;
+        }
+        let current = queue.current().map(|item| item.path.clone());
+        let Some(path) = random_track(library, current.as_ref()) else { return false };
+        queue.enqueue([path]);
+        true
+    };
+
+    // Move on to the next upcoming track. Returns false at the end of the queue.
+    let advance = |player: &mut Player, queue: &mut PlayQueue| -> bool {
+        match queue.advance() {
+            Some(item) => {
+                if player.crossfade_to(item.path.clone(), analyzed(&item.path)).is_ok() {
+                    count_play(&item.path);
                 }
+                true
+            }
+            None => false,
+        }
+    };
+
+    // Hand the track that follows the current one to the player ahead of time so the
+    // transition is gapless. Returns the id of the queue item that was handed over.
+    let prequeue = |player: &mut Player, queue: &PlayQueue| -> Option<i64> {
+        player.clear_next();
+        let current = &player.current_track()?.info;
+        let (next_path, gain, id) = match player.get_mode() {
+            PlayerMode::Replay => (current.path.clone(), Some(current.replay_gain), None),
+            PlayerMode::Default | PlayerMode::Shuffle => {
+                let next = queue.peek_next()?;
+                (next.path.clone(), analyzed(&next.path), Some(next.id))
             }
         };
+        player.queue_next(next_path, gain).ok()?;
+        id
+    };
 
-        while let Ok(cmd) = rx.recv() {
-            match cmd {
-                PlayerMessage::Play(path, reply) => {
-                    // Truncate any forward history, then append the new track.
-                    if !history.is_empty() {
-                        history.truncate(history_pos + 1);
-                    }
-                    history.push(path.clone());
-                    history_pos = history.len() - 1;
-                    let result = player.load_and_play(path).map_err(|e| e.to_string());
-                    reply.send(result).ok();
-                }
-                PlayerMessage::Pause => {
-                    player.pause();
-                }
-                PlayerMessage::Resume => {
-                    player.resume();
+    loop {
+        let cmd = match rx.recv_timeout(PLAYER_TICK) {
+            Ok(cmd) => Some(cmd),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+
+        // Set by commands that change what should follow the current track.
+        let mut requeue = false;
+        // Set whenever the queue changed, to save it and tell clients.
+        let mut queue_changed = false;
+        match cmd {
+            Some(PlayerMessage::Play(path, reply)) => {
+                let result = player.load_and_play(path.clone(), analyzed(&path)).map_err(|e| e.to_string());
+                if result.is_ok() {
+                    count_play(&path);
+                    queue.play_now(path);
+                    queue_changed = true;
                 }
-                PlayerMessage::Stop => {
-                    player.stop();
+                reply.send(result).ok();
+                requeue = true;
+            }
+            Some(PlayerMessage::Pause) => {
+                player.pause();
+            }
+            Some(PlayerMessage::Resume) => {
+                player.resume();
+            }
+            Some(PlayerMessage::Stop) => {
+                player.stop();
+            }
+            Some(PlayerMessage::Previous) => {
+                if let Some(item) = queue.back() {
+                    if player.load_and_play(item.path.clone(), analyzed(&item.path)).is_ok() {
+                        count_play(&item.path);
+                    }
+                    queue_changed = true;
+                    requeue = true;
                 }
-                PlayerMessage::Previous => {
-                    if history_pos > 0 {
-                        history_pos -= 1;
-                        player.load_and_play(history[history_pos].clone()).ok();
+            }
+            Some(PlayerMessage::Next) => {
+                queue_changed |= top_up(&player, &mut queue);
+                queue_changed |= advance(&mut player, &mut queue);
+                requeue = true;
+            }
+            Some(PlayerMessage::Queue(command, reply)) => {
+                let edits = !matches!(command, QueueCommand::Get);
+                let result = match command {
+                    QueueCommand::Get => Ok(()),
+                    QueueCommand::Enqueue(paths) => {
+                        queue.enqueue(paths);
+                        Ok(())
+                    }
+                    QueueCommand::PlayNext(paths) => {
+                        queue.play_next(paths);
+                        Ok(())
+                    }
+                    QueueCommand::Move(id, index) => queue.move_item(id, index),
+                    QueueCommand::Remove(id) => {
+                        queue.remove(id);
+                        Ok(())
                     }
+                    QueueCommand::Clear => {
+                        queue.clear();
+                        Ok(())
+                    }
+                    QueueCommand::SkipTo(id) => queue.skip_to(id).map(|item| {
+                        if player.crossfade_to(item.path.clone(), analyzed(&item.path)).is_ok() {
+                            count_play(&item.path);
+                        }
+                    }),
+                    QueueCommand::PlayContext(context, start) => {
+                        let shuffle = matches!(player.get_mode(), PlayerMode::Shuffle);
+                        library.context_tracks(&context)
+                            .and_then(|paths| queue.play_context(context, paths, start, shuffle).map(|item| item.path.clone()))
+                            .and_then(|path| {
+                                let gain = analyzed(&path);
+                                player.load_and_play(path, gain).map(|info| count_play(&info.path))
+                            })
+                    }
+                };
+                if edits && result.is_ok() {
+                    queue_changed = true;
+                    requeue = true;
                 }
-                PlayerMessage::Next => {
-                    advance(&mut player, &mut history, &mut history_pos, &library);
+                reply.send(result.map(|()| queue.clone()).map_err(|e| e.to_string())).ok();
+            }
+            Some(PlayerMessage::Seek(to_ms, reply)) => {
+                let result = player.seek(to_ms).map_err(|e| e.to_string());
+                reply.send(result).ok();
+            }
+            Some(PlayerMessage::SetMode(mode)) => {
+                queue.set_shuffle(matches!(mode, PlayerMode::Shuffle));
+                player.set_mode(mode);
+                queue_changed = true;
+                requeue = true;
+            }
+            Some(PlayerMessage::SetCrossfade(ms)) => {
+                player.set_crossfade(Duration::from_millis(ms));
+                requeue = true;
+            }
+            Some(PlayerMessage::SetReplayGain(mode, preamp_db)) => {
+                player.set_replay_gain(mode, preamp_db);
+            }
+            Some(PlayerMessage::SetVolume(volume)) => {
+                player.set_volume(volume);
+                library.set_setting(VOLUME_SETTING, &player.get_volume().to_string()).ok();
+            }
+            Some(PlayerMessage::SetMuted(muted)) => {
+                player.mute(muted);
+                library.set_setting(MUTED_SETTING, &muted.to_string()).ok();
+            }
+            Some(PlayerMessage::SetEqualizer(settings)) => {
+                player.set_equalizer(settings);
+                save_equalizer(&player, library);
+            }
+            Some(PlayerMessage::SetEqEnabled(enabled)) => {
+                player.set_equalizer(EqSettings { enabled, ..player.equalizer() });
+                save_equalizer(&player, library);
+            }
+            Some(PlayerMessage::SetOutputDevice(name, reply)) => {
+                let result = player.set_output_device(name.clone()).map_err(|e| e.to_string());
+                if result.is_ok() {
+                    library.set_setting(OUTPUT_DEVICE_SETTING, name.as_deref().unwrap_or("")).ok();
                 }
-                PlayerMessage::Seek(to_ms, reply) => {
-                    let result = player.seek(to_ms).map_err(|e| e.to_string());
-                    reply.send(result).ok();
+                reply.send(result).ok();
+                requeue = true;
+            }
+            Some(PlayerMessage::Status(reply)) => {
+                let status = player.current_track().map(|track| StatusResponse {
+                    artwork_id: match &artwork {
+                        Some((path, id)) if *path == track.info.path => *id,
+                        _ => {
+                            let id = library.artwork_id(&track.info.path).ok().flatten();
+                            artwork = Some((track.info.path.clone(), id));
+                            id
+                        }
+                    },
+                    path: track.info.path.to_string_lossy().into_owned(),
+                    duration_ms: track.info.duration_ms,
+                    seekable: track.info.seekable(),
+                    position_ms: player.current_position_ms(),
+                    snapshot_at_ms: now_ms(),
+                    paused: track.paused,
+                    title: track.info.title.clone(),
+                    artist: track.info.artist.clone(),
+                    mode: player.get_mode(),
+                    crossfade_ms: player.crossfade().as_millis() as u64,
+                    replay_gain_mode: player.replay_gain_mode(),
+                    preamp_db: player.preamp_db(),
+                    volume: player.get_volume(),
+                    muted: player.is_muted(),
+                    equalizer: player.equalizer(),
+                    output_device: player.output_device().map(String::from),
+                });
+                reply.send(status).ok();
+            }
+            None => {}
+        }
+
+        for event in player.poll_events() {
+            if let PlayerEvent::TrackChanged { track } = &event {
+                count_play(&track.path);
+                // The sink moved on to the prequeued item by itself; follow it in the queue.
+                if prequeued.is_some() && prequeued == queue.peek_next().map(|item| item.id) {
+                    queue.advance();
+                    queue_changed = true;
                 }
-                PlayerMessage::SetMode(mode) => {
-                    player.set_mode(mode);
+                requeue = true;
+            }
+            events.send(AppEvent::Player(event)).ok();
+        }
+
+        // Move on when rodio's sink runs dry (track reached EOF with nothing queued),
+        // stopping at the end of the queue.
+        if player.current_track().is_some() && player.is_finished() {
+            if let PlayerMode::Replay = player.get_mode() {
+                let info = &player.current_track().as_ref().unwrap().info;
+                let (path, gain) = (info.path.clone(), info.replay_gain);
+                if player.load_and_play(path.clone(), Some(gain)).is_ok() {
+                    count_play(&path);
                 }
-                PlayerMessage::Status(reply) => {
-                    // Auto-stop when rodio's sink runs dry (track reached EOF).
-                    if player.current_track().is_some() && player.is_finished() {
-                        match player.get_mode() {
-                            PlayerMode::Default => { player.stop() }
-                            PlayerMode::Replay => {
-                                let path = player.current_track().as_ref().unwrap().info.path.clone();
-                                player.load_and_play(path).ok();
-                            }
-                            PlayerMode::Shuffle => {
-                                advance(&mut player, &mut history, &mut history_pos, &library);
-                            }
-                        }
-                    }
-                    let status = player.current_track().map(|track| StatusResponse {
-                        path: track.info.path.to_string_lossy().into_owned(),
-                        duration_ms: track.info.duration_ms,
-                        position_ms: player.current_position_ms(),
-                        paused: track.last_playback_timestamp.is_none(),
-                        title: track.info.title.clone(),
-                        artist: track.info.artist.clone(),
-                        mode: player.get_mode(),
-                    });
-                    reply.send(status).ok();
+            } else {
+                top_up(&player, &mut queue);
+                if !advance(&mut player, &mut queue) {
+                    player.stop();
+                    queue.finish();
                 }
+                queue_changed = true;
+            }
+            requeue = true;
+        }
+
+        if requeue {
+            if player.current_track().is_some() {
+                queue_changed |= top_up(&player, &mut queue);
+            }
+            prequeued = prequeue(&mut player, &queue);
+        }
+        if queue_changed {
+            if let Err(e) = library.save_queue(&queue) {
+                eprintln!("Failed to save the play queue: {e:#}");
+            }
+            events.send(AppEvent::Queue(QueueEvent::QueueChanged { queue: queue.clone() })).ok();
+        }
+    }
+}
+
+fn save_equalizer(player: &Player, library: &Library) {
+    if let Ok(json) = serde_json::to_string(&player.equalizer()) {
+        library.set_setting(EQUALIZER_SETTING, &json).ok();
+    }
+}
+
+/// An EQ preset as listed to clients; built-in ones can't be overwritten or deleted.
+#[derive(Serialize)]
+pub(crate) struct EqPresetEntry {
+    #[serde(flatten)]
+    pub preset: EqPreset,
+    pub builtin: bool,
+}
+
+/// Built-in presets followed by the user's.
+pub(crate) fn list_eq_presets(library: &Library) -> Result<Vec<EqPresetEntry>, String> {
+    let user = library.eq_presets().map_err(|e| e.to_string())?;
+    let builtin = cadence_core::eq::builtin_presets().into_iter().map(|preset| EqPresetEntry { preset, builtin: true });
+    Ok(builtin.chain(user.into_iter().map(|preset| EqPresetEntry { preset, builtin: false })).collect())
+}
+
+/// Equalizer settings for the built-in or user preset called `name`.
+pub(crate) fn eq_preset_settings(library: &Library, name: &str) -> Result<EqSettings, String> {
+    let preset = match cadence_core::eq::builtin_preset(name) {
+        Some(preset) => preset,
+        None => library.eq_presets().map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| format!("No EQ preset named {name:?}"))?,
+    };
+    Ok(EqSettings::from_preset(&preset))
+}
+
+/// Analyze the loudness of tracks lacking ReplayGain tags. Runs once at startup, resuming
+/// whatever the previous session didn't finish, then again each time it is woken up.
+fn spawn_loudness_worker(library: Arc<Library>, events: broadcast::Sender<AppEvent>) -> mpsc::Sender<()> {
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::spawn(move || loop {
+        let result = library.analyze_loudness(|progress| {
+            events.send(AppEvent::Library(LibraryEvent::LoudnessProgress(progress))).ok();
+        });
+        if let Err(e) = result {
+            eprintln!("Loudness analysis failed: {e}");
+        }
+
+        if rx.recv().is_err() {
+            break;
+        }
+        // Requests that piled up during the run are covered by the next one.
+        while rx.try_recv().is_ok() {}
+    });
+
+    tx
+}
+
+/// Count a play of each path sent, so the player thread doesn't wait on the DB for it.
+fn spawn_play_counter(library: Arc<Library>) -> mpsc::Sender<PathBuf> {
+    let (tx, rx) = mpsc::channel::<PathBuf>();
+
+    std::thread::spawn(move || {
+        for path in rx {
+            if let Err(e) = library.record_play(&path) {
+                eprintln!("Failed to count a play: {e:#}");
             }
         }
     });
@@ -137,6 +560,16 @@ fn spawn_player_thread(lib_rx: mpsc::Receiver<Arc<Library>>) -> mpsc::Sender<Pla
     tx
 }
 
+/// Pick a random library track other than `current`.
+fn random_track(library: &Library, current: Option<&PathBuf>) -> Option<PathBuf> {
+    use rand::seq::SliceRandom;
+    let paths = library.all_track_paths().ok()?;
+    let candidates: Vec<&PathBuf> = paths.iter()
+        .filter(|p| Some(*p) != current)
+        .collect();
+    candidates.choose(&mut rand::thread_rng()).map(|p| (*p).clone())
+}
+
 #[tauri::command]
 fn play(path: String, handle: State<PlayerHandle>) -> Result<TrackInfo, String> {
     let (tx, rx) = mpsc::sync_channel(1);
@@ -181,6 +614,117 @@ fn set_mode(mode: PlayerMode, handle: State<PlayerHandle>) {
     handle.tx.send(PlayerMessage::SetMode(mode)).ok();
 }
 
+#[tauri::command]
+fn set_crossfade(ms: u64, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetCrossfade(ms)).ok();
+}
+
+#[tauri::command]
+fn set_replay_gain(mode: ReplayGainMode, preamp_db: f32, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetReplayGain(mode, preamp_db)).ok();
+}
+
+#[tauri::command]
+fn set_volume(volume: f32, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetVolume(volume)).ok();
+}
+
+#[tauri::command]
+fn set_muted(muted: bool, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetMuted(muted)).ok();
+}
+
+#[tauri::command]
+fn set_equalizer(settings: EqSettings, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetEqualizer(settings)).ok();
+}
+
+#[tauri::command]
+fn set_eq_enabled(enabled: bool, handle: State<PlayerHandle>) {
+    handle.tx.send(PlayerMessage::SetEqEnabled(enabled)).ok();
+}
+
+#[tauri::command]
+fn eq_presets(library: State<Arc<Library>>) -> Result<Vec<EqPresetEntry>, String> {
+    list_eq_presets(&library)
+}
+
+#[tauri::command]
+fn apply_eq_preset(name: String, library: State<Arc<Library>>, handle: State<PlayerHandle>) -> Result<(), String> {
+    let settings = eq_preset_settings(&library, &name)?;
+    handle.tx.send(PlayerMessage::SetEqualizer(settings)).ok();
+    Ok(())
+}
+
+#[tauri::command]
+fn save_eq_preset(preset: EqPreset, library: State<Arc<Library>>) -> Result<(), String> {
+    library.save_eq_preset(&preset).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_eq_preset(name: String, library: State<Arc<Library>>) -> Result<(), String> {
+    library.delete_eq_preset(&name).map_err(|e| e.to_string())
+}
+
+/// Run `command` on the player thread's queue.
+pub(crate) fn queue_command(tx: &mpsc::Sender<PlayerMessage>, command: QueueCommand) -> Result<PlayQueue, String> {
+    let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+    tx.send(PlayerMessage::Queue(command, reply_tx)).ok();
+    reply_rx.recv().map_err(|_| "Player thread died".to_string())?
+}
+
+#[tauri::command]
+fn queue(handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::Get)
+}
+
+#[tauri::command]
+fn enqueue(paths: Vec<String>, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::Enqueue(paths.into_iter().map(PathBuf::from).collect()))
+}
+
+#[tauri::command]
+fn play_next(paths: Vec<String>, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::PlayNext(paths.into_iter().map(PathBuf::from).collect()))
+}
+
+#[tauri::command]
+fn move_queue_item(id: i64, index: usize, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::Move(id, index))
+}
+
+#[tauri::command]
+fn remove_queue_item(id: i64, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::Remove(id))
+}
+
+#[tauri::command]
+fn clear_queue(handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::Clear)
+}
+
+#[tauri::command]
+fn skip_to_queue_item(id: i64, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::SkipTo(id))
+}
+
+#[tauri::command]
+fn play_context(context: PlayContext, start_index: usize, handle: State<PlayerHandle>) -> Result<PlayQueue, String> {
+    queue_command(&handle.tx, QueueCommand::PlayContext(context, start_index))
+}
+
+#[tauri::command]
+fn output_devices() -> Result<Vec<String>, String> {
+    cadence_core::output::output_devices().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_output_device(name: Option<String>, handle: State<PlayerHandle>) -> Result<(), String> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    handle.tx.send(PlayerMessage::SetOutputDevice(name, tx)).ok();
+    rx.recv().map_err(|_| "Player thread died".to_string())?
+}
+
 #[tauri::command]
 fn status(handle: State<PlayerHandle>) -> Option<StatusResponse> {
     let (tx, rx) = mpsc::sync_channel(1);
@@ -202,10 +746,52 @@ fn ws_address() -> String {
     format!("ws://{}:7878", ip)
 }
 
+// Runs off the main thread so the window stays responsive during long scans.
+#[tauri::command(async)]
+fn index_library(
+    path: String,
+    library: State<Arc<Library>>,
+    loudness: State<LoudnessHandle>,
+    watcher: State<WatcherHandle>,
+    scan: State<ScanHandle>,
+) -> Result<IndexSummary, String> {
+    let dir = std::path::Path::new(&path);
+    let running = scan.scans.start();
+    let summary = library
+        .scan_directory(
+            dir,
+            |progress| {
+                scan.events.send(AppEvent::Library(LibraryEvent::ScanProgress(progress))).ok();
+            },
+            &running.cancel,
+        )
+        .map_err(|e| e.to_string())?;
+    if summary.changed() {
+        // Other clients may be showing smart playlists that now pick different tracks.
+        scan.events.send(AppEvent::Library(LibraryEvent::LibraryChanged(summary.clone()))).ok();
+    }
+    loudness.tx.send(()).ok();
+    if let Some(watcher) = watcher.watcher.lock().unwrap().as_mut() {
+        watcher.watch(dir).map_err(|e| e.to_string())?;
+    }
+    Ok(summary)
+}
+
 #[tauri::command]
-fn index_library(path: String, library: State<Arc<Library>>) -> Result<usize, String> {
-    library.index_directory(std::path::Path::new(&path))
-        .map_err(|e| e.to_string())
+fn cancel_scan(scan: State<ScanHandle>) {
+    scan.scans.cancel();
+}
+
+#[tauri::command]
+fn scan_issues(library_id: Option<i64>, library: State<Arc<Library>>) -> Result<Vec<ScanIssue>, String> {
+    library.scan_issues(library_id).map_err(|e| e.to_string())
+}
+
+/// Cover art `id` (or its thumbnail) as a data URL the webview can show directly.
+#[tauri::command]
+fn artwork(id: i64, thumbnail: bool, library: State<Arc<Library>>) -> Result<Option<String>, String> {
+    let art = library.artwork(id, thumbnail).map_err(|e| e.to_string())?;
+    Ok(art.map(|art| format!("data:{};base64,{}", art.mime, BASE64.encode(&art.data))))
 }
 
 #[tauri::command]
@@ -213,14 +799,231 @@ fn search_tracks(query: String, library: State<Arc<Library>>) -> Result<Vec<Trac
     library.search(&query).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn browse_artists(sort: Option<ArtistSort>, page: Option<PageRequest>, library: State<Arc<Library>>) -> Result<Page<ArtistEntry>, String> {
+    library.artists(sort.unwrap_or_default(), &page.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn browse_albums(
+    artist_id: Option<i64>,
+    sort: Option<AlbumSort>,
+    page: Option<PageRequest>,
+    library: State<Arc<Library>>,
+) -> Result<Page<AlbumEntry>, String> {
+    library.albums(artist_id, sort.unwrap_or_default(), &page.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn browse_tracks(
+    filter: Option<TrackFilter>,
+    sort: Option<TrackSort>,
+    page: Option<PageRequest>,
+    library: State<Arc<Library>>,
+) -> Result<Page<TrackRecord>, String> {
+    library
+        .tracks(&filter.unwrap_or_default(), sort.unwrap_or_default(), &page.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn browse_genres(sort: Option<GenreSort>, page: Option<PageRequest>, library: State<Arc<Library>>) -> Result<Page<GenreEntry>, String> {
+    library.genres(sort.unwrap_or_default(), &page.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn browse_years(page: Option<PageRequest>, library: State<Arc<Library>>) -> Result<Page<YearEntry>, String> {
+    library.years(&page.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+/// Tell every client about an edited playlist, passing on how the edit went.
+pub(crate) fn playlist_changed(
+    events: &broadcast::Sender<AppEvent>,
+    edited: Result<Playlist, impl std::fmt::Display>,
+) -> Result<Playlist, String> {
+    let playlist = edited.map_err(|e| e.to_string())?;
+    events.send(AppEvent::Playlist(PlaylistEvent::PlaylistChanged { playlist: playlist.clone() })).ok();
+    Ok(playlist)
+}
+
+/// Delete playlist `id` and tell every client.
+pub(crate) fn delete_playlist_and_announce(
+    library: &Library,
+    events: &broadcast::Sender<AppEvent>,
+    id: i64,
+) -> Result<(), String> {
+    library.delete_playlist(id).map_err(|e| e.to_string())?;
+    events.send(AppEvent::Playlist(PlaylistEvent::PlaylistDeleted { id })).ok();
+    Ok(())
+}
+
+/// Tell every client about an edited smart playlist, passing on how the edit went.
+pub(crate) fn smart_playlist_changed(
+    events: &broadcast::Sender<AppEvent>,
+    edited: Result<SmartPlaylist, impl std::fmt::Display>,
+) -> Result<SmartPlaylist, String> {
+    let playlist = edited.map_err(|e| e.to_string())?;
+    events.send(AppEvent::Playlist(PlaylistEvent::SmartPlaylistChanged { playlist: playlist.clone() })).ok();
+    Ok(playlist)
+}
+
+/// Delete smart playlist `id` and tell every client.
+pub(crate) fn delete_smart_playlist_and_announce(
+    library: &Library,
+    events: &broadcast::Sender<AppEvent>,
+    id: i64,
+) -> Result<(), String> {
+    library.delete_smart_playlist(id).map_err(|e| e.to_string())?;
+    events.send(AppEvent::Playlist(PlaylistEvent::SmartPlaylistDeleted { id })).ok();
+    Ok(())
+}
+
+#[tauri::command]
+fn playlists(library: State<Arc<Library>>) -> Result<Vec<Playlist>, String> {
+    library.playlists().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn playlist_items(id: i64, library: State<Arc<Library>>) -> Result<Vec<PlaylistItem>, String> {
+    library.playlist_items(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_playlist(name: String, library: State<Arc<Library>>, events: State<EventsHandle>) -> Result<Playlist, String> {
+    playlist_changed(&events.tx, library.create_playlist(&name))
+}
+
+#[tauri::command]
+fn rename_playlist(
+    id: i64,
+    name: String,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<Playlist, String> {
+    playlist_changed(&events.tx, library.rename_playlist(id, &name))
+}
+
+#[tauri::command]
+fn delete_playlist(id: i64, library: State<Arc<Library>>, events: State<EventsHandle>) -> Result<(), String> {
+    delete_playlist_and_announce(&library, &events.tx, id)
+}
+
+#[tauri::command]
+fn add_to_playlist(
+    id: i64,
+    paths: Vec<String>,
+    index: Option<usize>,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<Playlist, String> {
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    playlist_changed(&events.tx, library.add_to_playlist(id, &paths, index))
+}
+
+#[tauri::command]
+fn remove_from_playlist(
+    id: i64,
+    item_ids: Vec<i64>,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<Playlist, String> {
+    playlist_changed(&events.tx, library.remove_from_playlist(id, &item_ids))
+}
+
+#[tauri::command]
+fn move_playlist_item(
+    id: i64,
+    item_id: i64,
+    index: usize,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<Playlist, String> {
+    playlist_changed(&events.tx, library.move_playlist_item(id, item_id, index))
+}
+
+/// Make a playlist of an M3U/M3U8, PLS or XSPF file, reporting the entries that
+/// aren't in the library.
+#[tauri::command]
+fn import_playlist(
+    path: String,
+    name: Option<String>,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<PlaylistImport, String> {
+    let import = library
+        .import_playlist(std::path::Path::new(&path), name.as_deref())
+        .map_err(|e| format!("{e:#}"))?;
+    events.tx.send(AppEvent::Playlist(PlaylistEvent::PlaylistChanged { playlist: import.playlist.clone() })).ok();
+    Ok(import)
+}
+
+/// Write playlist `id` to `path`, in the format its extension names.
+#[tauri::command]
+fn export_playlist(id: i64, path: String, relative: bool, library: State<Arc<Library>>) -> Result<(), String> {
+    library.export_playlist(id, std::path::Path::new(&path), relative).map_err(|e| format!("{e:#}"))
+}
+
+#[tauri::command]
+fn smart_playlists(library: State<Arc<Library>>) -> Result<Vec<SmartPlaylist>, String> {
+    library.smart_playlists().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn smart_playlist_tracks(id: i64, library: State<Arc<Library>>) -> Result<Vec<TrackRecord>, String> {
+    library.smart_playlist_tracks(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_smart_playlist(
+    name: String,
+    rules: SmartPlaylistRules,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<SmartPlaylist, String> {
+    smart_playlist_changed(&events.tx, library.create_smart_playlist(&name, &rules))
+}
+
+#[tauri::command]
+fn update_smart_playlist(
+    id: i64,
+    name: String,
+    rules: SmartPlaylistRules,
+    library: State<Arc<Library>>,
+    events: State<EventsHandle>,
+) -> Result<SmartPlaylist, String> {
+    smart_playlist_changed(&events.tx, library.update_smart_playlist(id, &name, &rules))
+}
+
+#[tauri::command]
+fn delete_smart_playlist(id: i64, library: State<Arc<Library>>, events: State<EventsHandle>) -> Result<(), String> {
+    delete_smart_playlist_and_announce(&library, &events.tx, id)
+}
+
 #[tauri::command]
 fn list_libraries(library: State<Arc<Library>>) -> Result<Vec<LibraryRecord>, String> {
     library.list_libraries().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn delete_library(id: i64, library: State<Arc<Library>>) -> Result<(), String> {
-    library.delete_library(id).map_err(|e| e.to_string())
+fn delete_library(
+    id: i64,
+    library: State<Arc<Library>>,
+    watcher: State<WatcherHandle>,
+    events: State<EventsHandle>,
+) -> Result<(), String> {
+    let libraries = library.list_libraries().map_err(|e| e.to_string())?;
+    let summary = library.delete_library(id).map_err(|e| e.to_string())?;
+    if summary.changed() {
+        events.tx.send(AppEvent::Library(LibraryEvent::LibraryChanged(summary))).ok();
+    }
+    if let (Some(watcher), Some(removed)) = (
+        watcher.watcher.lock().unwrap().as_mut(),
+        libraries.iter().find(|record| record.id == id),
+    ) {
+        // The folder may be gone already, in which case it is no longer watched anyway.
+        watcher.unwatch(std::path::Path::new(&removed.path)).ok();
+    }
+    Ok(())
 }
 
 fn local_ipv4() -> Option<std::net::Ipv4Addr> {
@@ -268,13 +1071,19 @@ pub fn run() {
     // Library is created in setup (needs app data dir).
     // Both the player thread and WS server need it — send via separate sync channels.
     let (player_lib_tx, player_lib_rx) = mpsc::sync_channel::<Arc<Library>>(1);
-    let player_tx = spawn_player_thread(player_lib_rx);
+    // Events fan out to the desktop UI and every WS client.
+    let (events_tx, _) = broadcast::channel::<AppEvent>(32);
+    let player_tx = spawn_player_thread(player_lib_rx, events_tx.clone());
+
+    let scans = RunningScans::default();
 
     let (ws_lib_tx, ws_lib_rx) = tokio::sync::oneshot::channel::<Arc<Library>>();
     let player_tx_for_ws = player_tx.clone();
+    let events_for_ws = events_tx.clone();
+    let scans_for_ws = scans.clone();
     tauri::async_runtime::spawn(async move {
         let Ok(library) = ws_lib_rx.await else { return };
-        websocket::serve(player_tx_for_ws, library).await;
+        websocket::serve(player_tx_for_ws, events_for_ws, library, scans_for_ws).await;
     });
 
     advertise_mdns();
@@ -290,13 +1099,58 @@ pub fn run() {
                 .expect("Failed to open library database"));
             player_lib_tx.send(Arc::clone(&library)).ok();
             ws_lib_tx.send(Arc::clone(&library)).ok();
+            let loudness_tx = spawn_loudness_worker(Arc::clone(&library), events_tx.clone());
+            let watcher = LibraryWatcher::start(
+                Arc::clone(&library),
+                {
+                    let events = events_tx.clone();
+                    let loudness = loudness_tx.clone();
+                    move |summary| {
+                        loudness.send(()).ok();
+                        events.send(AppEvent::Library(LibraryEvent::LibraryChanged(summary))).ok();
+                    }
+                },
+                |e| eprintln!("{e:#}"),
+            );
+            let watcher = watcher.map_err(|e| eprintln!("{e:#}")).ok();
+            app.manage(WatcherHandle { watcher: Mutex::new(watcher) });
+            app.manage(LoudnessHandle { tx: loudness_tx });
+            app.manage(ScanHandle { scans, events: events_tx.clone() });
+            app.manage(EventsHandle { tx: events_tx.clone() });
             app.manage(library);
+
+            let app_handle = app.handle().clone();
+            let mut events_rx = events_tx.subscribe();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match events_rx.recv().await {
+                        Ok(event) => {
+                            let name = match event {
+                                AppEvent::Player(_) => "player-event",
+                                AppEvent::Library(_) => "library-event",
+                                AppEvent::Queue(_) => "queue-event",
+                                AppEvent::Playlist(_) => "playlist-event",
+                            };
+                            app_handle.emit(name, &event).ok();
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(_) => break,
+                    }
+                }
+            });
             Ok(())
         })
         .manage(PlayerHandle { tx: player_tx })
         .invoke_handler(tauri::generate_handler![
-            play, pause, resume, stop, next, previous, seek, set_mode, status, ws_address,
-            index_library, search_tracks, list_libraries, delete_library
+            play, pause, resume, stop, next, previous, seek, set_mode, set_crossfade, set_replay_gain,
+            set_volume, set_muted, set_equalizer, set_eq_enabled, eq_presets, apply_eq_preset,
+            save_eq_preset, delete_eq_preset, queue, enqueue, play_next, move_queue_item, remove_queue_item,
+            clear_queue, skip_to_queue_item, play_context, output_devices, set_output_device, status, ws_address,
+            index_library, cancel_scan, scan_issues, artwork, search_tracks, browse_artists, browse_albums,
+            browse_tracks, browse_genres, browse_years, playlists, playlist_items, create_playlist, rename_playlist,
+            delete_playlist, add_to_playlist, remove_from_playlist, move_playlist_item, import_playlist,
+            export_playlist, smart_playlists, smart_playlist_tracks, create_smart_playlist, update_smart_playlist,
+            delete_smart_playlist, list_libraries, delete_library
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,14 +1,24 @@
 use std::sync::{mpsc, Arc};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tokio_tungstenite::tungstenite::Message;
 
-use cadence_core::{Library, PlayerMode, TrackRecord};
-use crate::{PlayerMessage, StatusResponse};
+use cadence_core::{
+    AlbumSort, ArtistSort, EqSettings, GenreSort, Library, Page, PageRequest, PlayContext, PlayQueue, PlayerMode,
+    Playlist, PlaylistItem, QueryError, ReplayGainMode, SmartPlaylist, SmartPlaylistRules, TrackFilter, TrackRecord,
+    TrackSort,
+};
+use crate::{
+    delete_playlist_and_announce, delete_smart_playlist_and_announce, eq_preset_settings, list_eq_presets,
+    playlist_changed, queue_command, smart_playlist_changed, AppEvent, EqPresetEntry, PlayerMessage, QueueCommand,
+    RunningScans, StatusResponse,
+};
 
 /// State broadcast sent to all clients every 500 ms.
 #[derive(Serialize)]
@@ -18,11 +28,20 @@ struct StateMsg<'a> {
     track_path: &'a str,
     title: Option<&'a str>,
     artist: Option<&'a str>,
-    duration_ms: u64,
+    artwork_id: Option<i64>,
+    duration_ms: Option<u64>,
+    seekable: bool,
     position_ms: u64,
     playing: bool,
     snapshot_at_ms: u64,
     mode: &'a PlayerMode,
+    crossfade_ms: u64,
+    replay_gain_mode: ReplayGainMode,
+    preamp_db: f32,
+    volume: f32,
+    muted: bool,
+    output_device: Option<&'a str>,
+    equalizer: &'a EqSettings,
 }
 
 /// Search results sent only to the requesting client.
@@ -32,6 +51,102 @@ struct SearchResultsMsg {
     msg_type: &'static str,
     query: String,
     tracks: Vec<TrackRecord>,
+    error: Option<String>,
+    /// Where in `query` a syntax error is, in characters from the start.
+    error_position: Option<usize>,
+}
+
+/// Available output devices sent only to the requesting client.
+#[derive(Serialize)]
+struct OutputDevicesMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    devices: Vec<String>,
+}
+
+/// Built-in and user EQ presets sent only to the requesting client.
+#[derive(Serialize)]
+struct EqPresetsMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    presets: Vec<EqPresetEntry>,
+}
+
+/// The play queue sent only to the requesting client; changes reach everyone as events.
+#[derive(Serialize)]
+struct QueueMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    queue: PlayQueue,
+}
+
+/// Cover art sent only to the requesting client. `data` is base64; it is None when there
+/// is no such art.
+#[derive(Serialize)]
+struct ArtworkMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    id: i64,
+    thumbnail: bool,
+    mime: Option<String>,
+    data: Option<String>,
+}
+
+/// A page of a library listing sent only to the requesting client. `cursor` is the one
+/// the page was asked for, so the client can tell a first page from a later one.
+#[derive(Serialize)]
+struct BrowseMsg<T> {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    cursor: Option<String>,
+    #[serde(flatten)]
+    page: Option<Page<T>>,
+    error: Option<String>,
+}
+
+/// Every playlist, sent only to the requesting client; edits reach everyone as events.
+#[derive(Serialize)]
+struct PlaylistsMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    playlists: Vec<Playlist>,
+}
+
+/// The entries of playlist `id` sent only to the requesting client.
+#[derive(Serialize)]
+struct PlaylistItemsMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    id: i64,
+    items: Vec<PlaylistItem>,
+    error: Option<String>,
+}
+
+/// Every smart playlist, sent only to the requesting client.
+#[derive(Serialize)]
+struct SmartPlaylistsMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    playlists: Vec<SmartPlaylist>,
+}
+
+/// The tracks smart playlist `id` picks right now, sent only to the requesting client.
+#[derive(Serialize)]
+struct SmartPlaylistTracksMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    id: i64,
+    tracks: Vec<TrackRecord>,
+    error: Option<String>,
+}
+
+/// Why a request (`request` being its type) failed, sent only to the client that made it.
+#[derive(Serialize)]
+struct ErrorMsg {
+    #[serde(rename = "type")]
+    msg_type: &'static str,
+    request: &'static str,
+    message: String,
 }
 
 /// Commands sent from clients to the server.
@@ -47,13 +162,77 @@ enum ClientMsg {
     Seek { to_ms: u64 },
     Search { query: String },
     SetMode { mode: PlayerMode },
-}
-
-fn now_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as u64
+    SetCrossfade { ms: u64 },
+    SetReplayGain { mode: ReplayGainMode, preamp_db: f32 },
+    SetVolume { volume: f32 },
+    SetMuted { muted: bool },
+    ListOutputDevices,
+    SetOutputDevice { name: Option<String> },
+    SetEqualizer { settings: EqSettings },
+    SetEqEnabled { enabled: bool },
+    ApplyEqPreset { name: String },
+    ListEqPresets,
+    GetQueue,
+    Enqueue { paths: Vec<String> },
+    PlayNext { paths: Vec<String> },
+    MoveQueueItem { id: i64, index: usize },
+    RemoveQueueItem { id: i64 },
+    ClearQueue,
+    SkipToQueueItem { id: i64 },
+    PlayContext { context: PlayContext, start_index: usize },
+    CancelScan,
+    GetArtwork { id: i64, thumbnail: bool },
+    BrowseArtists {
+        #[serde(default)]
+        sort: ArtistSort,
+        #[serde(default)]
+        page: PageRequest,
+    },
+    BrowseAlbums {
+        #[serde(default)]
+        artist_id: Option<i64>,
+        #[serde(default)]
+        sort: AlbumSort,
+        #[serde(default)]
+        page: PageRequest,
+    },
+    BrowseTracks {
+        #[serde(default)]
+        filter: TrackFilter,
+        #[serde(default)]
+        sort: TrackSort,
+        #[serde(default)]
+        page: PageRequest,
+    },
+    BrowseGenres {
+        #[serde(default)]
+        sort: GenreSort,
+        #[serde(default)]
+        page: PageRequest,
+    },
+    BrowseYears {
+        #[serde(default)]
+        page: PageRequest,
+    },
+    ListPlaylists,
+    GetPlaylistItems { id: i64 },
+    CreatePlaylist { name: String },
+    RenamePlaylist { id: i64, name: String },
+    DeletePlaylist { id: i64 },
+    AddToPlaylist {
+        id: i64,
+        paths: Vec<String>,
+        /// Where to insert; at the end when missing
+        #[serde(default)]
+        index: Option<usize>,
+    },
+    RemoveFromPlaylist { id: i64, item_ids: Vec<i64> },
+    MovePlaylistItem { id: i64, item_id: i64, index: usize },
+    ListSmartPlaylists,
+    GetSmartPlaylistTracks { id: i64 },
+    CreateSmartPlaylist { name: String, rules: SmartPlaylistRules },
+    UpdateSmartPlaylist { id: i64, name: String, rules: SmartPlaylistRules },
+    DeleteSmartPlaylist { id: i64 },
 }
 
 fn state_json(status: &StatusResponse) -> String {
@@ -62,18 +241,70 @@ fn state_json(status: &StatusResponse) -> String {
         track_path: &status.path,
         title: status.title.as_deref(),
         artist: status.artist.as_deref(),
+        artwork_id: status.artwork_id,
         duration_ms: status.duration_ms,
+        seekable: status.seekable,
         position_ms: status.position_ms,
         playing: !status.paused,
-        snapshot_at_ms: now_ms(),
+        snapshot_at_ms: status.snapshot_at_ms,
         mode: &status.mode,
+        crossfade_ms: status.crossfade_ms,
+        replay_gain_mode: status.replay_gain_mode,
+        preamp_db: status.preamp_db,
+        volume: status.volume,
+        muted: status.muted,
+        output_device: status.output_device.as_deref(),
+        equalizer: &status.equalizer,
     };
     serde_json::to_string(&msg).unwrap()
 }
 
+/// Run a library listing off the async runtime and wrap its page (or error) in a
+/// `msg_type` reply.
+async fn browse_reply<T: Serialize + Send + 'static>(
+    library: &Arc<Library>,
+    msg_type: &'static str,
+    page: PageRequest,
+    list: impl FnOnce(&Library, &PageRequest) -> Result<Page<T>, String> + Send + 'static,
+) -> String {
+    let library = Arc::clone(library);
+    let cursor = page.cursor.clone();
+    let result = tokio::task::spawn_blocking(move || list(&library, &page))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    let (page, error) = match result {
+        Ok(page) => (Some(page), None),
+        Err(e) => (None, Some(e)),
+    };
+    serde_json::to_string(&BrowseMsg { msg_type, cursor, page, error }).unwrap()
+}
+
+/// Run a playlist edit off the async runtime. Every client hears about the result through
+/// a playlist event; a failure comes back as an error reply for the requesting client.
+async fn edit_playlist(
+    library: &Arc<Library>,
+    request: &'static str,
+    edit: impl FnOnce(&Library) -> Result<(), String> + Send + 'static,
+) -> Option<String> {
+    let library = Arc::clone(library);
+    let result = tokio::task::spawn_blocking(move || edit(&library))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    let message = result.err()?;
+    Some(serde_json::to_string(&ErrorMsg { msg_type: "error", request, message }).unwrap())
+}
+
+/// Fire off a queue edit; every client hears about the result through the queue_changed event.
+fn send_queue_command(player_tx: &mpsc::Sender<PlayerMessage>, command: QueueCommand) {
+    let (tx, _) = mpsc::sync_channel(1);
+    player_tx.send(PlayerMessage::Queue(command, tx)).ok();
+}
+
 pub async fn serve(
     player_tx: mpsc::Sender<PlayerMessage>,
+    events: broadcast::Sender<AppEvent>,
     library: Arc<Library>,
+    scans: RunningScans,
 ) {
     let listener = TcpListener::bind("0.0.0.0:7878").await
         .expect("Failed to bind WS server on port 7878");
@@ -104,9 +335,26 @@ pub async fn serve(
         });
     }
 
+    // Forward events to all clients as they happen.
+    {
+        let mut erx = events.subscribe();
+        let btx = broadcast_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                match erx.recv().await {
+                    Ok(event) => { btx.send(serde_json::to_string(&event).unwrap()).ok(); }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
     while let Ok((stream, _addr)) = listener.accept().await {
         let ptx = player_tx.clone();
         let lib = Arc::clone(&library);
+        let scans = scans.clone();
+        let events = events.clone();
         let mut brx = broadcast_tx.subscribe();
 
         tokio::spawn(async move {
@@ -136,20 +384,305 @@ pub async fn serve(
                                     ClientMsg::Search { query } => {
                                         let lib2 = Arc::clone(&lib);
                                         let q = query.clone();
-                                        let tracks = tokio::task::spawn_blocking(move || {
-                                            lib2.search(&q).unwrap_or_default()
-                                        }).await.unwrap_or_default();
+                                        let result = tokio::task::spawn_blocking(move || lib2.search(&q))
+                                            .await
+                                            .unwrap_or_else(|e| Err(e.into()));
+                                        let (tracks, error, error_position) = match result {
+                                            Ok(tracks) => (tracks, None, None),
+                                            Err(e) => {
+                                                let position = e.downcast_ref::<QueryError>().map(|e| e.position);
+                                                (Vec::new(), Some(e.to_string()), position)
+                                            }
+                                        };
 
                                         let reply = serde_json::to_string(&SearchResultsMsg {
                                             msg_type: "search_results",
                                             query,
                                             tracks,
+                                            error,
+                                            error_position,
                                         }).unwrap();
                                         if write.send(Message::Text(reply)).await.is_err() { break; }
                                     }
                                     ClientMsg::SetMode { mode } => {
                                         ptx.send(PlayerMessage::SetMode(mode)).ok();
                                     }
+                                    ClientMsg::SetCrossfade { ms } => {
+                                        ptx.send(PlayerMessage::SetCrossfade(ms)).ok();
+                                    }
+                                    ClientMsg::SetReplayGain { mode, preamp_db } => {
+                                        ptx.send(PlayerMessage::SetReplayGain(mode, preamp_db)).ok();
+                                    }
+                                    ClientMsg::SetVolume { volume } => {
+                                        ptx.send(PlayerMessage::SetVolume(volume)).ok();
+                                    }
+                                    ClientMsg::SetMuted { muted } => {
+                                        ptx.send(PlayerMessage::SetMuted(muted)).ok();
+                                    }
+                                    ClientMsg::ListOutputDevices => {
+                                        let devices = tokio::task::spawn_blocking(|| {
+                                            cadence_core::output::output_devices().unwrap_or_default()
+                                        }).await.unwrap_or_default();
+
+                                        let reply = serde_json::to_string(&OutputDevicesMsg {
+                                            msg_type: "output_devices",
+                                            devices,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::SetOutputDevice { name } => {
+                                        let (tx, _) = mpsc::sync_channel(1);
+                                        ptx.send(PlayerMessage::SetOutputDevice(name, tx)).ok();
+                                    }
+                                    ClientMsg::SetEqualizer { settings } => {
+                                        ptx.send(PlayerMessage::SetEqualizer(settings)).ok();
+                                    }
+                                    ClientMsg::SetEqEnabled { enabled } => {
+                                        ptx.send(PlayerMessage::SetEqEnabled(enabled)).ok();
+                                    }
+                                    ClientMsg::ApplyEqPreset { name } => {
+                                        if let Ok(settings) = eq_preset_settings(&lib, &name) {
+                                            ptx.send(PlayerMessage::SetEqualizer(settings)).ok();
+                                        }
+                                    }
+                                    ClientMsg::ListEqPresets => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let presets = tokio::task::spawn_blocking(move || {
+                                            list_eq_presets(&lib2).unwrap_or_default()
+                                        }).await.unwrap_or_default();
+
+                                        let reply = serde_json::to_string(&EqPresetsMsg {
+                                            msg_type: "eq_presets",
+                                            presets,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::GetQueue => {
+                                        let ptx2 = ptx.clone();
+                                        let queue = tokio::task::spawn_blocking(move || {
+                                            queue_command(&ptx2, QueueCommand::Get)
+                                        }).await;
+                                        let Ok(Ok(queue)) = queue else { continue };
+
+                                        let reply = serde_json::to_string(&QueueMsg {
+                                            msg_type: "queue",
+                                            queue,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::Enqueue { paths } => {
+                                        let paths = paths.into_iter().map(Into::into).collect();
+                                        send_queue_command(&ptx, QueueCommand::Enqueue(paths));
+                                    }
+                                    ClientMsg::PlayNext { paths } => {
+                                        let paths = paths.into_iter().map(Into::into).collect();
+                                        send_queue_command(&ptx, QueueCommand::PlayNext(paths));
+                                    }
+                                    ClientMsg::MoveQueueItem { id, index } => {
+                                        send_queue_command(&ptx, QueueCommand::Move(id, index));
+                                    }
+                                    ClientMsg::RemoveQueueItem { id } => {
+                                        send_queue_command(&ptx, QueueCommand::Remove(id));
+                                    }
+                                    ClientMsg::ClearQueue => {
+                                        send_queue_command(&ptx, QueueCommand::Clear);
+                                    }
+                                    ClientMsg::SkipToQueueItem { id } => {
+                                        send_queue_command(&ptx, QueueCommand::SkipTo(id));
+                                    }
+                                    ClientMsg::PlayContext { context, start_index } => {
+                                        send_queue_command(&ptx, QueueCommand::PlayContext(context, start_index));
+                                    }
+                                    ClientMsg::CancelScan => {
+                                        scans.cancel();
+                                    }
+                                    ClientMsg::GetArtwork { id, thumbnail } => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let art = tokio::task::spawn_blocking(move || {
+                                            lib2.artwork(id, thumbnail).ok().flatten()
+                                        }).await.ok().flatten();
+
+                                        let reply = serde_json::to_string(&ArtworkMsg {
+                                            msg_type: "artwork",
+                                            id,
+                                            thumbnail,
+                                            mime: art.as_ref().map(|art| art.mime.clone()),
+                                            data: art.map(|art| BASE64.encode(&art.data)),
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::BrowseArtists { sort, page } => {
+                                        let reply = browse_reply(&lib, "artists", page, move |lib, page| {
+                                            lib.artists(sort, page).map_err(|e| e.to_string())
+                                        }).await;
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::BrowseAlbums { artist_id, sort, page } => {
+                                        let reply = browse_reply(&lib, "albums", page, move |lib, page| {
+                                            lib.albums(artist_id, sort, page).map_err(|e| e.to_string())
+                                        }).await;
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::BrowseTracks { filter, sort, page } => {
+                                        let reply = browse_reply(&lib, "tracks", page, move |lib, page| {
+                                            lib.tracks(&filter, sort, page).map_err(|e| e.to_string())
+                                        }).await;
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::BrowseGenres { sort, page } => {
+                                        let reply = browse_reply(&lib, "genres", page, move |lib, page| {
+                                            lib.genres(sort, page).map_err(|e| e.to_string())
+                                        }).await;
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::BrowseYears { page } => {
+                                        let reply = browse_reply(&lib, "years", page, move |lib, page| {
+                                            lib.years(page).map_err(|e| e.to_string())
+                                        }).await;
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::ListPlaylists => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let playlists = tokio::task::spawn_blocking(move || {
+                                            lib2.playlists().unwrap_or_default()
+                                        }).await.unwrap_or_default();
+
+                                        let reply = serde_json::to_string(&PlaylistsMsg {
+                                            msg_type: "playlists",
+                                            playlists,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::GetPlaylistItems { id } => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let result = tokio::task::spawn_blocking(move || lib2.playlist_items(id))
+                                            .await
+                                            .unwrap_or_else(|e| Err(e.into()));
+                                        let (items, error) = match result {
+                                            Ok(items) => (items, None),
+                                            Err(e) => (Vec::new(), Some(e.to_string())),
+                                        };
+
+                                        let reply = serde_json::to_string(&PlaylistItemsMsg {
+                                            msg_type: "playlist_items",
+                                            id,
+                                            items,
+                                            error,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::CreatePlaylist { name } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "create_playlist", move |lib| {
+                                            playlist_changed(&events, lib.create_playlist(&name)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::RenamePlaylist { id, name } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "rename_playlist", move |lib| {
+                                            playlist_changed(&events, lib.rename_playlist(id, &name)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::DeletePlaylist { id } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "delete_playlist", move |lib| {
+                                            delete_playlist_and_announce(lib, &events, id)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::AddToPlaylist { id, paths, index } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "add_to_playlist", move |lib| {
+                                            let paths: Vec<_> = paths.into_iter().map(Into::into).collect();
+                                            playlist_changed(&events, lib.add_to_playlist(id, &paths, index)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::RemoveFromPlaylist { id, item_ids } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "remove_from_playlist", move |lib| {
+                                            playlist_changed(&events, lib.remove_from_playlist(id, &item_ids)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::MovePlaylistItem { id, item_id, index } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "move_playlist_item", move |lib| {
+                                            playlist_changed(&events, lib.move_playlist_item(id, item_id, index)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::ListSmartPlaylists => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let playlists = tokio::task::spawn_blocking(move || {
+                                            lib2.smart_playlists().unwrap_or_default()
+                                        }).await.unwrap_or_default();
+
+                                        let reply = serde_json::to_string(&SmartPlaylistsMsg {
+                                            msg_type: "smart_playlists",
+                                            playlists,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::GetSmartPlaylistTracks { id } => {
+                                        let lib2 = Arc::clone(&lib);
+                                        let result = tokio::task::spawn_blocking(move || lib2.smart_playlist_tracks(id))
+                                            .await
+                                            .unwrap_or_else(|e| Err(e.into()));
+                                        let (tracks, error) = match result {
+                                            Ok(tracks) => (tracks, None),
+                                            Err(e) => (Vec::new(), Some(e.to_string())),
+                                        };
+
+                                        let reply = serde_json::to_string(&SmartPlaylistTracksMsg {
+                                            msg_type: "smart_playlist_tracks",
+                                            id,
+                                            tracks,
+                                            error,
+                                        }).unwrap();
+                                        if write.send(Message::Text(reply)).await.is_err() { break; }
+                                    }
+                                    ClientMsg::CreateSmartPlaylist { name, rules } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "create_smart_playlist", move |lib| {
+                                            smart_playlist_changed(&events, lib.create_smart_playlist(&name, &rules)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::UpdateSmartPlaylist { id, name, rules } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "update_smart_playlist", move |lib| {
+                                            smart_playlist_changed(&events, lib.update_smart_playlist(id, &name, &rules)).map(drop)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
+                                    ClientMsg::DeleteSmartPlaylist { id } => {
+                                        let events = events.clone();
+                                        let error = edit_playlist(&lib, "delete_smart_playlist", move |lib| {
+                                            delete_smart_playlist_and_announce(lib, &events, id)
+                                        }).await;
+                                        if let Some(reply) = error {
+                                            if write.send(Message::Text(reply)).await.is_err() { break; }
+                                        }
+                                    }
                                 }
                             }
                             Some(Ok(Message::Close(_))) | None => break,
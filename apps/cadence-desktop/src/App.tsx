@@ -13,6 +13,19 @@ interface TrackRecord {
     title: string;
     artist: string;
     duration_ms: number;
+    album: string | null;
+    album_artist: string | null;
+    track_number: number | null;
+    disc_number: number | null;
+    year: number | null;
+    genre: string | null;
+    composer: string | null;
+    codec: string | null;
+    sample_rate: number | null;
+    bit_depth: number | null;
+    channels: number | null;
+    bitrate_kbps: number | null;
+    artwork_id: number | null;
 }
 
 function fmt(ms: number) {
@@ -21,13 +34,14 @@ function fmt(ms: number) {
 }
 
 function App() {
-    const { displayMs, durationMs, paused, active, trackPath, trackTitle, trackArtist, mode: playbackMode, onDragChange, onDragCommit, sync } = usePlayback();
+    const { displayMs, durationMs, paused, active, trackPath, trackTitle, trackArtist, artworkUrl, mode: playbackMode, onDragChange, onDragCommit, sync } = usePlayback();
     const [mode, setMode] = useState<"Default" | "Shuffle" | "Replay">("Default");
     useEffect(() => { if (playbackMode) setMode(playbackMode); }, [playbackMode]);
 
     const [view, setView] = useState<"main" | "libraries">("main");
     const [query, setQuery] = useState("");
     const [results, setResults] = useState<TrackRecord[]>([]);
+    const [searchError, setSearchError] = useState<string | null>(null);
     const [wsAddr, setWsAddr] = useState<string | null>(null);
     const [menuOpen, setMenuOpen] = useState(false);
     const menuRef = useRef<HTMLDivElement>(null);
@@ -48,8 +62,11 @@ function App() {
     }, []);
 
     useEffect(() => {
+        setSearchError(null);
         if (!query.trim()) { setResults([]); return; }
-        invoke<TrackRecord[]>("search_tracks", { query }).then(setResults);
+        invoke<TrackRecord[]>("search_tracks", { query })
+            .then(setResults)
+            .catch(e => { setResults([]); setSearchError(String(e)); });
     }, [query]);
 
     const handlePlayResult = async (path: string) => {
@@ -125,6 +142,10 @@ function App() {
                 />
             </div>
 
+            {searchError && (
+                <p style={{ color: "#e57373", fontSize: "0.85rem", marginTop: "0.5rem" }}>{searchError}</p>
+            )}
+
             {!active && results.length == 0 && (
                 <div style={{ display: "flex", gap: "0.5rem", marginTop: "0.7rem" }}>
                     <Button variant={"outline"} onClick={() => handleNext()}>Play Something</Button>
@@ -147,9 +168,14 @@ function App() {
             )}
 
             {active && (
-                <div style={{ marginTop: "1.5rem" }}>
-                    <p style={{ margin: 0 }}>{trackTitle ?? trackPath?.split("\\").at(-1) ?? trackPath}</p>
-                    <p style={{ margin: 0, color: "#888", fontSize: "0.85rem" }}>{trackArtist ?? "Unknown Artist"}</p>
+                <div style={{ marginTop: "1.5rem", display: "flex", alignItems: "center", gap: "0.75rem" }}>
+                    {artworkUrl && (
+                        <img src={artworkUrl} alt="" style={{ width: 64, height: 64, objectFit: "cover", borderRadius: "4px" }} />
+                    )}
+                    <div>
+                        <p style={{ margin: 0 }}>{trackTitle ?? trackPath?.split("\\").at(-1) ?? trackPath}</p>
+                        <p style={{ margin: 0, color: "#888", fontSize: "0.85rem" }}>{trackArtist ?? "Unknown Artist"}</p>
+                    </div>
                 </div>
             )}
 
@@ -1,4 +1,5 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { open } from "@tauri-apps/plugin-dialog";
 import { useEffect, useState } from "react";
 import { Button } from "@/components/ui/button";
@@ -8,6 +9,32 @@ interface LibraryRecord {
     path: string;
 }
 
+interface IndexSummary {
+    added: number;
+    updated: number;
+    removed: number;
+    unchanged: number;
+    failed: number;
+    cancelled: boolean;
+}
+
+interface ScanProgress {
+    type: "scan_progress";
+    path: string;
+    seen: number;
+    processed: number;
+    failed: number;
+}
+
+interface ScanIssue {
+    id: number;
+    library_id: number;
+    path: string;
+    kind: "unreadable" | "corrupt" | "unsupported_codec" | "zero_duration";
+    message: string;
+    found_at_ms: number;
+}
+
 interface Props {
     onBack: () => void;
 }
@@ -16,6 +43,8 @@ export function LibraryManager({ onBack }: Props) {
     const [libraries, setLibraries] = useState<LibraryRecord[]>([]);
     const [selectedId, setSelectedId] = useState<number | null>(null);
     const [status, setStatus] = useState<string | null>(null);
+    const [scanning, setScanning] = useState(false);
+    const [issues, setIssues] = useState<ScanIssue[] | null>(null);
 
     const refresh = () => {
         invoke<LibraryRecord[]>("list_libraries").then(setLibraries);
@@ -23,20 +52,41 @@ export function LibraryManager({ onBack }: Props) {
 
     useEffect(() => { refresh(); }, []);
 
+    useEffect(() => {
+        const unlisten = listen<{ type: string }>("library-event", event => {
+            if (event.payload.type !== "scan_progress") return;
+            const p = event.payload as ScanProgress;
+            setStatus(`Indexing… ${p.processed} of ${p.seen} files${p.failed ? `, ${p.failed} unreadable` : ""}`);
+        });
+        return () => { void unlisten.then(f => f()); };
+    }, []);
+
     const handleAdd = async () => {
         const dir = await open({ directory: true, multiple: false });
         if (!dir) return;
         setStatus("Indexing…");
-        const count = await invoke<number>("index_library", { path: dir });
-        setStatus(`${count} tracks indexed`);
+        setScanning(true);
+        try {
+            const summary = await invoke<IndexSummary>("index_library", { path: dir });
+            const counts = `${summary.added} added, ${summary.updated} updated, ${summary.removed} removed`;
+            setStatus(summary.cancelled ? `Cancelled (${counts})` : counts);
+        } finally {
+            setScanning(false);
+        }
         refresh();
     };
 
+    const handleShowIssues = async () => {
+        if (selectedId === null) return;
+        setIssues(await invoke<ScanIssue[]>("scan_issues", { libraryId: selectedId }));
+    };
+
     const handleRemove = async () => {
         if (selectedId === null) return;
         await invoke("delete_library", { id: selectedId });
         setSelectedId(null);
         setStatus(null);
+        setIssues(null);
         refresh();
     };
 
@@ -50,7 +100,10 @@ export function LibraryManager({ onBack }: Props) {
                 <h2 style={{ margin: 0 }}>Libraries</h2>
             </div>
 
-            <Button variant="outline" onClick={() => void handleAdd()}>Add Folder</Button>
+            <Button variant="outline" disabled={scanning} onClick={() => void handleAdd()}>Add Folder</Button>
+            {scanning && (
+                <Button variant="outline" style={{ marginLeft: "0.5rem" }} onClick={() => void invoke("cancel_scan")}>Cancel</Button>
+            )}
 
             {status && (
                 <p style={{ color: "#888", marginTop: "0.5rem", fontSize: "0.8rem" }}>{status}</p>
@@ -81,7 +134,20 @@ export function LibraryManager({ onBack }: Props) {
 
             <div style={{ marginTop: "1rem" }}>
                 <Button variant="outline" disabled={selectedId === null} onClick={() => void handleRemove()}>Remove</Button>
+                <Button variant="outline" style={{ marginLeft: "0.5rem" }} disabled={selectedId === null} onClick={() => void handleShowIssues()}>Problems</Button>
             </div>
+
+            {issues && (
+                <ul style={{ listStyle: "none", padding: 0, marginTop: "1rem", fontSize: "0.8rem" }}>
+                    {issues.length === 0 && <li style={{ color: "#555" }}>No problems found.</li>}
+                    {issues.map(issue => (
+                        <li key={issue.id} style={{ padding: "0.3rem 0", borderBottom: "1px solid #222", wordBreak: "break-all" }}>
+                            {issue.path}
+                            <span style={{ color: "#888", marginLeft: "0.5rem" }}>{issue.kind.replace("_", " ")}: {issue.message}</span>
+                        </li>
+                    ))}
+                </ul>
+            )}
         </div>
     );
 }
@@ -5,11 +5,13 @@ type PlayerMode = "Default" | "Shuffle" | "Replay";
 
 interface StatusResponse {
     path: string;
-    duration_ms: number;
+    duration_ms: number | null;
+    seekable: boolean;
     position_ms: number;
     paused: boolean;
     title: string | null;
     artist: string | null;
+    artwork_id: number | null;
     mode: PlayerMode;
 }
 
@@ -28,6 +30,8 @@ export function usePlayback() {
     const [trackPath, setTrackPath] = useState<string | null>(null);
     const [trackTitle, setTrackTitle] = useState<string | null>(null);
     const [trackArtist, setTrackArtist] = useState<string | null>(null);
+    const [artworkId, setArtworkId] = useState<number | null>(null);
+    const [artworkUrl, setArtworkUrl] = useState<string | null>(null);
     const [mode, setMode] = useState<PlayerMode>("Default");
 
     // Polls backend and resets the extrapolation anchor.
@@ -42,6 +46,7 @@ export function usePlayback() {
             setTrackPath(null);
             setTrackTitle(null);
             setTrackArtist(null);
+            setArtworkId(null);
             setDurationMs(0);
         } else {
             if (dragRef.current === null) {
@@ -56,7 +61,8 @@ export function usePlayback() {
             setTrackPath(status.path);
             setTrackTitle(status.title);
             setTrackArtist(status.artist);
-            setDurationMs(status.duration_ms);
+            setArtworkId(status.artwork_id);
+            setDurationMs(status.duration_ms ?? 0);
             setMode(status.mode);
         }
     }, []);
@@ -68,6 +74,19 @@ export function usePlayback() {
         return () => clearInterval(id);
     }, [poll]);
 
+    // Fetch the cover thumbnail whenever the art changes.
+    useEffect(() => {
+        if (artworkId === null) {
+            setArtworkUrl(null);
+            return;
+        }
+        let stale = false;
+        invoke<string | null>("artwork", { id: artworkId, thumbnail: true })
+            .then((url) => { if (!stale) setArtworkUrl(url); })
+            .catch(() => { if (!stale) setArtworkUrl(null); });
+        return () => { stale = true; };
+    }, [artworkId]);
+
     // rAF loop - extrapolates forward from the last anchor at 60fps.
     const tick = useCallback(() => {
         if (dragRef.current !== null) {
@@ -97,5 +116,5 @@ export function usePlayback() {
         await invoke("seek", { toMs: ms });
     }, []);
 
-    return { displayMs, durationMs, paused: isPaused, active: isAnyTrackActive, trackPath, trackTitle, trackArtist, mode, onDragChange, onDragCommit, sync: poll };
+    return { displayMs, durationMs, paused: isPaused, active: isAnyTrackActive, trackPath, trackTitle, trackArtist, artworkUrl, mode, onDragChange, onDragCommit, sync: poll };
 }
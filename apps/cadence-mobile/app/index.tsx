@@ -1,6 +1,7 @@
 import { useEffect, useRef, useState } from "react";
 import {
     FlatList,
+    Image,
     Pressable,
     StyleSheet,
     Text,
@@ -8,7 +9,7 @@ import {
     View,
 } from "react-native";
 import { StatusBar } from "expo-status-bar";
-import { useDesktopSync } from "@/hooks/useDesktopSync";
+import { BrowseListing, useDesktopSync } from "@/hooks/useDesktopSync";
 import { useDiscovery } from "@/hooks/useDiscovery";
 
 function fmt(ms: number) {
@@ -29,8 +30,18 @@ export default function App() {
     const [displayMs, setDisplayMs] = useState(0);
     const rafRef = useRef<number>(0);
 
-    const { status, playback, searchResults, search, play, pause, resume, stop, next, previous, seek, setMode } =
-        useDesktopSync(connectedUrl);
+    const {
+        status, playback, artwork, searchResults, searchError, search, play, pause, resume, stop, next, previous, seek, setMode,
+        browse, browseArtists, browseAlbums, browseTracks, browseGenres, browseYears,
+        playlists, browsePlaylists, openPlaylist, createPlaylist, addToPlaylist, removeFromPlaylist, playPlaylist,
+        smartPlaylists, openSmartPlaylist, playSmartPlaylist,
+    } = useDesktopSync(connectedUrl);
+    // Playlist last opened: long-pressing a track adds it there.
+    const [targetPlaylist, setTargetPlaylist] = useState<number | null>(null);
+    const [newPlaylistName, setNewPlaylistName] = useState("");
+    const target = playlists.find((p) => p.id === targetPlaylist);
+    // Loads the page after `cursor` of the listing being browsed.
+    const browseMoreRef = useRef<(cursor: string) => void>(browseArtists);
     const MODES = ["Default", "Shuffle", "Replay"] as const;
     const handleCycleMode = () => {
         if (!playback) return;
@@ -76,6 +87,90 @@ export default function App() {
         return () => clearTimeout(t);
     }, [query, search]);
 
+    // Start browsing at the artists once connected.
+    useEffect(() => {
+        if (status === "connected") {
+            browseMoreRef.current = browseArtists;
+            browseArtists();
+        }
+    }, [status, browseArtists]);
+
+    function openListing(load: (cursor?: string) => void) {
+        browseMoreRef.current = load;
+        load();
+    }
+
+    function addToTarget(path: string) {
+        if (target) addToPlaylist(target.id, [path]);
+    }
+
+    /** Rows of a listing: tapping one opens what is under it, or plays it. */
+    function browseRows(listing: BrowseListing): BrowseRow[] {
+        switch (listing.kind) {
+            case "artists":
+                return listing.items.map((a) => ({
+                    key: `artist-${a.id}`, title: a.name, subtitle: `${a.track_count} tracks`,
+                    onPress: () => openListing((cursor) => browseAlbums(a.id, cursor)),
+                }));
+            case "albums":
+                return listing.items.map((al) => ({
+                    key: `album-${al.id}`, title: al.title, subtitle: [al.album_artist, al.year].filter(Boolean).join(" · "),
+                    onPress: () => openListing((cursor) => browseTracks({ album_id: al.id }, cursor)),
+                }));
+            case "tracks":
+                return listing.items.map((t) => ({
+                    key: `track-${t.id}`, title: t.title, subtitle: t.artist, onPress: () => play(t.path),
+                    onLongPress: () => addToTarget(t.path),
+                }));
+            case "genres":
+                return listing.items.map((g) => ({
+                    key: `genre-${g.id}`, title: g.name, subtitle: `${g.track_count} tracks`,
+                    onPress: () => openListing((cursor) => browseTracks({ genre_id: g.id }, cursor)),
+                }));
+            case "years":
+                return listing.items.map((y) => ({
+                    key: `year-${y.year}`, title: String(y.year), subtitle: `${y.track_count} tracks`,
+                    onPress: () => openListing((cursor) => browseTracks({ year: y.year }, cursor)),
+                }));
+            case "playlists":
+                return [
+                    ...playlists.map((p) => ({
+                        key: `playlist-${p.id}`, title: p.name, subtitle: `${p.track_count} tracks · ${fmt(p.duration_ms)}`,
+                        onPress: () => { setTargetPlaylist(p.id); openPlaylist(p.id); },
+                    })),
+                    ...smartPlaylists.map((p) => ({
+                        key: `smart-${p.id}`, title: p.name, subtitle: `Smart playlist · ${p.track_count} tracks`,
+                        onPress: () => openSmartPlaylist(p.id),
+                    })),
+                ];
+            case "smart_playlist_tracks": {
+                // A shuffled one is shuffled afresh when played, so it can only start from the top.
+                const id = listing.id;
+                const shuffled = smartPlaylists.find((p) => p.id === id)?.rules.sort === "random";
+                return listing.items.map((t, index) => ({
+                    key: `smart-track-${t.id}`, title: t.title, subtitle: t.artist,
+                    onPress: () => playSmartPlaylist(id, shuffled ? 0 : index),
+                }));
+            }
+            case "playlist_items": {
+                // Entries missing from the library are skipped when the playlist plays.
+                const id = listing.id;
+                let playable = 0;
+                return listing.items.map((item) => {
+                    const startIndex = playable;
+                    if (item.track) playable += 1;
+                    return {
+                        key: `item-${item.id}`,
+                        title: item.track?.title ?? item.path.split(/[\\/]/).at(-1) ?? item.path,
+                        subtitle: item.track?.artist ?? "Not in the library",
+                        onPress: () => { if (item.track) playPlaylist(id, startIndex); },
+                        onLongPress: () => removeFromPlaylist(id, [item.id]),
+                    };
+                });
+            }
+        }
+    }
+
     // ── Connect screen ──────────────────────────────────────────
     if (status !== "connected") {
         const isConnecting = status === "connecting";
@@ -148,26 +243,76 @@ export default function App() {
                 autoCorrect={false}
             />
 
-            {/* Results */}
-            <FlatList
-                data={searchResults}
-                keyExtractor={(item) => String(item.id)}
-                style={styles.list}
-                renderItem={({ item }) => (
-                    <Pressable style={styles.trackRow} onPress={() => play(item.path)}>
-                        <Text style={styles.trackTitle} numberOfLines={1}>{item.title}</Text>
-                        <Text style={styles.trackArtist} numberOfLines={1}>{item.artist}</Text>
+            {/* Browse, while nothing is searched for */}
+            {!query.trim() && (
+                <View style={styles.browseTabs}>
+                    {([["Artists", browseArtists], ["Albums", (cursor?: string) => browseAlbums(undefined, cursor)],
+                       ["Genres", browseGenres], ["Years", browseYears], ["Playlists", browsePlaylists]] as const)
+                        .map(([label, load]) => (
+                            <Pressable key={label} onPress={() => openListing(load)}>
+                                <Text style={styles.browseTab}>{label}</Text>
+                            </Pressable>
+                        ))}
+                </View>
+            )}
+            {!query.trim() && browse?.kind === "playlists" && (
+                <View style={styles.newPlaylist}>
+                    <TextInput
+                        style={[styles.searchInput, styles.newPlaylistInput]}
+                        value={newPlaylistName}
+                        onChangeText={setNewPlaylistName}
+                        placeholder="New playlist…"
+                        placeholderTextColor="#555"
+                    />
+                    <Pressable onPress={() => { if (newPlaylistName.trim()) createPlaylist(newPlaylistName); setNewPlaylistName(""); }}>
+                        <Text style={styles.browseTab}>Create</Text>
                     </Pressable>
-                )}
-                ListEmptyComponent={
-                    query.trim() ? <Text style={styles.empty}>No results</Text> : null
-                }
-            />
+                </View>
+            )}
+            {target && (
+                <Text style={styles.playlistHint} numberOfLines={1}>
+                    Long-press a track to add it to {target.name}
+                </Text>
+            )}
+            {!query.trim() && browse ? (
+                <FlatList
+                    data={browseRows(browse)}
+                    keyExtractor={(row) => row.key}
+                    style={styles.list}
+                    renderItem={({ item }) => (
+                        <Pressable style={styles.trackRow} onPress={item.onPress} onLongPress={item.onLongPress}>
+                            <Text style={styles.trackTitle} numberOfLines={1}>{item.title}</Text>
+                            <Text style={styles.trackArtist} numberOfLines={1}>{item.subtitle}</Text>
+                        </Pressable>
+                    )}
+                    onEndReached={() => { if (browse.nextCursor) browseMoreRef.current(browse.nextCursor); }}
+                />
+            ) : (
+                <FlatList
+                    data={searchResults}
+                    keyExtractor={(item) => String(item.id)}
+                    style={styles.list}
+                    renderItem={({ item }) => (
+                        <Pressable
+                            style={styles.trackRow}
+                            onPress={() => play(item.path)}
+                            onLongPress={() => addToTarget(item.path)}
+                        >
+                            <Text style={styles.trackTitle} numberOfLines={1}>{item.title}</Text>
+                            <Text style={styles.trackArtist} numberOfLines={1}>{item.artist}</Text>
+                        </Pressable>
+                    )}
+                    ListEmptyComponent={
+                        query.trim() ? <Text style={styles.empty}>{searchError ?? "No results"}</Text> : null
+                    }
+                />
+            )}
 
             {/* Now playing bar */}
             {playback && (
                 <View style={styles.nowPlaying}>
                     <View style={styles.nowPlayingInfo}>
+                        {artwork && <Image source={{ uri: artwork }} style={styles.nowArtwork} />}
                         <Text style={styles.nowTitle} numberOfLines={1}>
                             {playback.title ?? playback.trackPath.split(/[\\/]/).at(-1)}
                         </Text>
@@ -225,6 +370,14 @@ export default function App() {
     );
 }
 
+interface BrowseRow {
+    key: string;
+    title: string;
+    subtitle: string;
+    onPress: () => void;
+    onLongPress?: () => void;
+}
+
 const C = {
     bg: "#0a0a0a",
     surface: "#141414",
@@ -364,6 +517,16 @@ const styles = StyleSheet.create({
         flex: 1,
         paddingHorizontal: 16,
     },
+    browseTabs: {
+        flexDirection: "row",
+        gap: 16,
+        paddingHorizontal: 16,
+        paddingBottom: 8,
+    },
+    browseTab: {
+        color: C.muted,
+        fontSize: 14,
+    },
     trackRow: {
         paddingVertical: 10,
         borderBottomWidth: 1,
@@ -378,6 +541,20 @@ const styles = StyleSheet.create({
         fontSize: 13,
         marginTop: 2,
     },
+    newPlaylist: {
+        flexDirection: "row",
+        alignItems: "center",
+        paddingRight: 16,
+    },
+    newPlaylistInput: {
+        flex: 1,
+    },
+    playlistHint: {
+        color: C.dim,
+        fontSize: 12,
+        paddingHorizontal: 16,
+        paddingBottom: 8,
+    },
     empty: {
         color: C.dim,
         textAlign: "center",
@@ -394,6 +571,12 @@ const styles = StyleSheet.create({
     nowPlayingInfo: {
         marginBottom: 8,
     },
+    nowArtwork: {
+        width: 56,
+        height: 56,
+        borderRadius: 4,
+        marginBottom: 6,
+    },
     nowTitle: {
         color: C.accent,
         fontSize: 15,
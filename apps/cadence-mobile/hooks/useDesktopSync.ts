@@ -6,14 +6,133 @@ export interface TrackRecord {
     title: string;
     artist: string;
     duration_ms: number;
+    album: string | null;
+    album_artist: string | null;
+    track_number: number | null;
+    disc_number: number | null;
+    year: number | null;
+    genre: string | null;
+    composer: string | null;
+    codec: string | null;
+    sample_rate: number | null;
+    bit_depth: number | null;
+    channels: number | null;
+    bitrate_kbps: number | null;
+    artwork_id: number | null;
 }
 
+export interface ArtistEntry {
+    id: number;
+    name: string;
+    track_count: number;
+    album_count: number;
+}
+
+export interface AlbumEntry {
+    id: number;
+    title: string;
+    album_artist_id: number;
+    album_artist: string;
+    year: number | null;
+    track_count: number;
+    duration_ms: number;
+    artwork_id: number | null;
+}
+
+export interface GenreEntry {
+    id: number;
+    name: string;
+    track_count: number;
+}
+
+export interface YearEntry {
+    year: number;
+    track_count: number;
+    album_count: number;
+}
+
+export interface Playlist {
+    id: number;
+    name: string;
+    track_count: number;
+    duration_ms: number;
+    created_at_ms: number;
+    updated_at_ms: number;
+}
+
+export interface PlaylistItem {
+    id: number;
+    path: string;
+    /** null while the file isn't in the desktop's library */
+    track: TrackRecord | null;
+}
+
+export type SortOrder = "asc" | "desc";
+
+/** A test on a track, or a combination of them; see `Rule` in cadence-core. */
+export type SmartRule =
+    | { type: "all" | "any"; rules: SmartRule[] }
+    | { type: "not"; rule: SmartRule }
+    | {
+        type: "text";
+        field: "title" | "artist" | "album" | "album_artist" | "genre" | "composer" | "codec" | "path";
+        op: "is" | "contains" | "starts_with" | "ends_with";
+        value: string;
+    }
+    | {
+        type: "number";
+        field: "year" | "duration_ms" | "play_count" | "track_number" | "disc_number" | "bitrate_kbps"
+            | "sample_rate" | "bit_depth" | "channels";
+        op: "eq" | "ne" | "lt" | "le" | "gt" | "ge";
+        value: number;
+    }
+    | { type: "in_last"; field: "added" | "last_played"; days: number };
+
+export interface SmartPlaylistRules {
+    rule: SmartRule;
+    sort?: "artist" | "album" | "title" | "year" | "added" | "last_played" | "play_count" | "random";
+    order?: SortOrder;
+    limit?: number | null;
+}
+
+export interface SmartPlaylist {
+    id: number;
+    name: string;
+    rules: SmartPlaylistRules;
+    /** Tracks the rules pick right now */
+    track_count: number;
+    created_at_ms: number;
+    updated_at_ms: number;
+}
+
+export interface TrackFilter {
+    artist_id?: number;
+    album_id?: number;
+    genre_id?: number;
+    year?: number;
+}
+
+/** One library listing, with every page loaded so far. */
+export type BrowseListing =
+    | { kind: "artists"; items: ArtistEntry[]; nextCursor: string | null }
+    | { kind: "albums"; items: AlbumEntry[]; nextCursor: string | null }
+    | { kind: "tracks"; items: TrackRecord[]; nextCursor: string | null }
+    | { kind: "genres"; items: GenreEntry[]; nextCursor: string | null }
+    | { kind: "years"; items: YearEntry[]; nextCursor: string | null }
+    // The playlists themselves come from the hook's `playlists`, which stays up to date.
+    | { kind: "playlists"; items: []; nextCursor: null }
+    | { kind: "playlist_items"; id: number; items: PlaylistItem[]; nextCursor: null }
+    | { kind: "smart_playlist_tracks"; id: number; items: TrackRecord[]; nextCursor: null };
+
+const BROWSE_KINDS = ["artists", "albums", "tracks", "genres", "years"];
+
 export type PlayerMode = "Default" | "Shuffle" | "Replay";
 
 export interface PlaybackState {
     trackPath: string;
     title: string | null;
     artist: string | null;
+    artworkId: number | null;
     durationMs: number;
     positionMs: number;
     playing: boolean;
@@ -35,6 +154,18 @@ export function useDesktopSync(url: string | null) {
     const [status, setStatus] = useState<ConnectionStatus>("disconnected");
     const [playback, setPlayback] = useState<PlaybackState | null>(null);
     const [searchResults, setSearchResults] = useState<TrackRecord[]>([]);
+    // Why the last search found nothing, when its query couldn't be understood.
+    const [searchError, setSearchError] = useState<string | null>(null);
+    const [browse, setBrowse] = useState<BrowseListing | null>(null);
+    const [playlists, setPlaylists] = useState<Playlist[]>([]);
+    const [smartPlaylists, setSmartPlaylists] = useState<SmartPlaylist[]>([]);
+    // Playlist whose entries are being browsed, to refresh them when it changes.
+    const shownPlaylistRef = useRef<number | null>(null);
+    // Likewise for a smart playlist, whose tracks also change with the library.
+    const shownSmartPlaylistRef = useRef<number | null>(null);
+    // Thumbnail of the playing track's cover, as a data URI.
+    const [artwork, setArtwork] = useState<string | null>(null);
+    const artworkIdRef = useRef<number | null>(null);
 
     useEffect(() => {
         if (!url) {
@@ -63,6 +194,8 @@ export function useDesktopSync(url: string | null) {
                 opened = true;
                 backoffRef.current = BACKOFF_INITIAL_MS; // reset on success
                 setStatus("connected");
+                ws.send(JSON.stringify({ type: "list_playlists" }));
+                ws.send(JSON.stringify({ type: "list_smart_playlists" }));
             };
 
             ws.onerror = () => {
@@ -100,16 +233,86 @@ export function useDesktopSync(url: string | null) {
                             trackPath: msg.track_path,
                             title: msg.title ?? null,
                             artist: msg.artist ?? null,
-                            durationMs: msg.duration_ms,
+                            artworkId: msg.artwork_id ?? null,
+                            durationMs: msg.duration_ms ?? 0,
                             positionMs: msg.position_ms,
                             playing: msg.playing,
                             snapshotAtMs: Date.now(), // use client receive time to avoid PC/phone clock skew
                             mode: msg.mode ?? "Default",
                         });
+                        const artworkId = msg.artwork_id ?? null;
+                        if (artworkId !== artworkIdRef.current) {
+                            artworkIdRef.current = artworkId;
+                            setArtwork(null);
+                            if (artworkId !== null) {
+                                ws.send(JSON.stringify({ type: "get_artwork", id: artworkId, thumbnail: true }));
+                            }
+                        }
                     } else if (msg.type === "stopped") {
                         setPlayback(null);
+                        artworkIdRef.current = null;
+                        setArtwork(null);
+                    } else if (msg.type === "artwork") {
+                        if (msg.id === artworkIdRef.current && msg.data) {
+                            setArtwork(`data:${msg.mime};base64,${msg.data}`);
+                        }
                     } else if (msg.type === "search_results") {
                         setSearchResults(msg.tracks ?? []);
+                        setSearchError(msg.error ?? null);
+                    } else if (msg.type === "playlists") {
+                        setPlaylists(msg.playlists);
+                    } else if (msg.type === "playlist_changed") {
+                        const changed: Playlist = msg.playlist;
+                        setPlaylists((prev) => [...prev.filter((p) => p.id !== changed.id), changed]
+                            .sort((a, b) => a.name.localeCompare(b.name, undefined, { sensitivity: "base" })));
+                        if (shownPlaylistRef.current === changed.id) {
+                            ws.send(JSON.stringify({ type: "get_playlist_items", id: changed.id }));
+                        }
+                    } else if (msg.type === "playlist_deleted") {
+                        setPlaylists((prev) => prev.filter((p) => p.id !== msg.id));
+                        if (shownPlaylistRef.current === msg.id) {
+                            shownPlaylistRef.current = null;
+                            setBrowse({ kind: "playlists", items: [], nextCursor: null });
+                        }
+                    } else if (msg.type === "playlist_items" && !msg.error) {
+                        if (shownPlaylistRef.current === msg.id) {
+                            shownSmartPlaylistRef.current = null;
+                            setBrowse({ kind: "playlist_items", id: msg.id, items: msg.items, nextCursor: null });
+                        }
+                    } else if (msg.type === "smart_playlists") {
+                        setSmartPlaylists(msg.playlists);
+                    } else if (msg.type === "smart_playlist_changed") {
+                        const changed: SmartPlaylist = msg.playlist;
+                        setSmartPlaylists((prev) => [...prev.filter((p) => p.id !== changed.id), changed]
+                            .sort((a, b) => a.name.localeCompare(b.name, undefined, { sensitivity: "base" })));
+                        if (shownSmartPlaylistRef.current === changed.id) {
+                            ws.send(JSON.stringify({ type: "get_smart_playlist_tracks", id: changed.id }));
+                        }
+                    } else if (msg.type === "smart_playlist_deleted") {
+                        setSmartPlaylists((prev) => prev.filter((p) => p.id !== msg.id));
+                        if (shownSmartPlaylistRef.current === msg.id) {
+                            shownSmartPlaylistRef.current = null;
+                            setBrowse({ kind: "playlists", items: [], nextCursor: null });
+                        }
+                    } else if (msg.type === "smart_playlist_tracks" && !msg.error) {
+                        if (shownSmartPlaylistRef.current === msg.id) {
+                            setBrowse({ kind: "smart_playlist_tracks", id: msg.id, items: msg.tracks, nextCursor: null });
+                        }
+                    } else if (msg.type === "library_changed") {
+                        // Smart playlists pick from the library, so what they hold may have changed.
+                        ws.send(JSON.stringify({ type: "list_smart_playlists" }));
+                        if (shownSmartPlaylistRef.current !== null) {
+                            ws.send(JSON.stringify({ type: "get_smart_playlist_tracks", id: shownSmartPlaylistRef.current }));
+                        }
+                    } else if (BROWSE_KINDS.includes(msg.type) && !msg.error) {
+                        shownPlaylistRef.current = null;
+                        shownSmartPlaylistRef.current = null;
+                        // A page asked for with a cursor continues the listing shown; any other starts it afresh.
+                        setBrowse((prev) => ({
+                            kind: msg.type,
+                            items: msg.cursor && prev?.kind === msg.type ? [...prev.items, ...msg.items] : msg.items,
+                            nextCursor: msg.next_cursor ?? null,
+                        }) as BrowseListing);
                     }
                 } catch {}
             };
@@ -136,6 +339,7 @@ export function useDesktopSync(url: string | null) {
             send({ type: "search", query });
         } else {
             setSearchResults([]);
+            setSearchError(null);
         }
     }, [send]);
 
@@ -148,5 +352,61 @@ export function useDesktopSync(url: string | null) {
     const seek = useCallback((toMs: number) => send({ type: "seek", to_ms: toMs }), [send]);
     const setMode = useCallback((mode: PlayerMode) => send({ type: "set_mode", mode }), [send]);
 
-    return { status, playback, searchResults, search, play, pause, resume, stop, next, previous, seek, setMode };
+    // Browsing: pass the listing's nextCursor to load the page after the ones shown.
+    const browseArtists = useCallback((cursor?: string, order: SortOrder = "asc") =>
+        send({ type: "browse_artists", page: { cursor, order } }), [send]);
+    const browseAlbums = useCallback((artistId?: number, cursor?: string) =>
+        send({ type: "browse_albums", artist_id: artistId, page: { cursor } }), [send]);
+    const browseTracks = useCallback((filter: TrackFilter, cursor?: string) =>
+        send({ type: "browse_tracks", filter, page: { cursor } }), [send]);
+    const browseGenres = useCallback((cursor?: string) => send({ type: "browse_genres", page: { cursor } }), [send]);
+    const browseYears = useCallback((cursor?: string) => send({ type: "browse_years", page: { cursor } }), [send]);
+
+    // Playlists: edits come back to every client as playlist_changed / playlist_deleted.
+    const browsePlaylists = useCallback(() => {
+        shownPlaylistRef.current = null;
+        shownSmartPlaylistRef.current = null;
+        setBrowse({ kind: "playlists", items: [], nextCursor: null });
+        send({ type: "list_playlists" });
+        send({ type: "list_smart_playlists" });
+    }, [send]);
+    const openPlaylist = useCallback((id: number) => {
+        shownPlaylistRef.current = id;
+        shownSmartPlaylistRef.current = null;
+        send({ type: "get_playlist_items", id });
+    }, [send]);
+    const createPlaylist = useCallback((name: string) => send({ type: "create_playlist", name }), [send]);
+    const renamePlaylist = useCallback((id: number, name: string) =>
+        send({ type: "rename_playlist", id, name }), [send]);
+    const deletePlaylist = useCallback((id: number) => send({ type: "delete_playlist", id }), [send]);
+    const addToPlaylist = useCallback((id: number, paths: string[], index?: number) =>
+        send({ type: "add_to_playlist", id, paths, index }), [send]);
+    const removeFromPlaylist = useCallback((id: number, itemIds: number[]) =>
+        send({ type: "remove_from_playlist", id, item_ids: itemIds }), [send]);
+    const movePlaylistItem = useCallback((id: number, itemId: number, index: number) =>
+        send({ type: "move_playlist_item", id, item_id: itemId, index }), [send]);
+    const playPlaylist = useCallback((id: number, startIndex = 0) =>
+        send({ type: "play_context", context: { type: "playlist", id }, start_index: startIndex }), [send]);
+
+    // Smart playlists: the desktop works out their tracks each time they are listed or played.
+    const openSmartPlaylist = useCallback((id: number) => {
+        shownSmartPlaylistRef.current = id;
+        shownPlaylistRef.current = null;
+        send({ type: "get_smart_playlist_tracks", id });
+    }, [send]);
+    const createSmartPlaylist = useCallback((name: string, rules: SmartPlaylistRules) =>
+        send({ type: "create_smart_playlist", name, rules }), [send]);
+    const updateSmartPlaylist = useCallback((id: number, name: string, rules: SmartPlaylistRules) =>
+        send({ type: "update_smart_playlist", id, name, rules }), [send]);
+    const deleteSmartPlaylist = useCallback((id: number) => send({ type: "delete_smart_playlist", id }), [send]);
+    const playSmartPlaylist = useCallback((id: number, startIndex = 0) =>
+        send({ type: "play_context", context: { type: "smart_playlist", id }, start_index: startIndex }), [send]);
+
+    return {
+        status, playback, artwork, searchResults, searchError, search, play, pause, resume, stop, next, previous, seek, setMode,
+        browse, browseArtists, browseAlbums, browseTracks, browseGenres, browseYears,
+        playlists, browsePlaylists, openPlaylist, createPlaylist, renamePlaylist, deletePlaylist, addToPlaylist,
+        removeFromPlaylist, movePlaylistItem, playPlaylist, smartPlaylists, openSmartPlaylist, createSmartPlaylist,
+        updateSmartPlaylist, deleteSmartPlaylist, playSmartPlaylist,
+    };
 }
@@ -0,0 +1,119 @@
+//! Cover art, taken from a track's tags or from an image file in its folder. Each
+//! distinct image is stored once in the artwork cache, named by the SHA-256 of its
+//! bytes, with a JPEG thumbnail next to it.
+
+use anyhow::{bail, Context, Result};
+use image::ImageFormat;
+use lofty::picture::PictureType;
+use lofty::tag::Tag;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// Names (without extension, case-insensitive) of the image files taken as a folder's cover.
+const FOLDER_ART_NAMES: &[&str] = &["cover", "folder", "front", "album"];
+const FOLDER_ART_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// An image read back from the artwork cache.
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub mime: String,
+    pub data: Vec<u8>,
+}
+
+/// An image stored in the artwork cache.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedArtwork {
+    pub hash: String,
+    pub mime: &'static str,
+}
+
+/// The picture for the track at `path`: the front cover embedded in `tag` (or its first
+/// picture), else a cover image in the track's folder.
+pub(crate) fn find_artwork(path: &Path, tag: Option<&Tag>) -> Option<Vec<u8>> {
+    let pictures = tag.map(Tag::pictures).unwrap_or_default();
+    let embedded = pictures
+        .iter()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first());
+    if let Some(picture) = embedded {
+        return Some(picture.data().to_vec());
+    }
+    folder_art(path.parent()?).and_then(|file| std::fs::read(file).ok())
+}
+
+fn folder_art(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| Some((folder_art_rank(&path)?, path)))
+        .min()
+        .map(|(_, path)| path)
+}
+
+/// Whether `path` is named like a folder's cover image, so the tracks next to it may
+/// pick it up.
+pub(crate) fn is_folder_art(path: &Path) -> bool {
+    folder_art_rank(path).is_some()
+}
+
+/// How preferred a cover image named like `path` is (lower is better), if it is one.
+fn folder_art_rank(path: &Path) -> Option<usize> {
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let rank = FOLDER_ART_NAMES.iter().position(|name| *name == stem)?;
+    FOLDER_ART_EXTENSIONS.contains(&ext.as_str()).then_some(rank)
+}
+
+/// Store `data` in the cache at `dir`, along with its thumbnail, unless the same image
+/// is there already.
+pub(crate) fn cache_artwork(dir: &Path, data: &[u8]) -> Result<CachedArtwork> {
+    let (format, mime) = match image::guess_format(data) {
+        Ok(ImageFormat::Jpeg) => (ImageFormat::Jpeg, "image/jpeg"),
+        Ok(ImageFormat::Png) => (ImageFormat::Png, "image/png"),
+        _ => bail!("Unsupported image format"),
+    };
+    let hash = format!("{:x}", Sha256::digest(data));
+    let cached = CachedArtwork { hash, mime };
+
+    let original = artwork_file(dir, &cached.hash, mime, false);
+    let thumbnail = artwork_file(dir, &cached.hash, mime, true);
+    if original.exists() && thumbnail.exists() {
+        return Ok(cached);
+    }
+
+    let image = image::load_from_memory_with_format(data, format).context("Failed to decode cover art")?;
+    let mut thumb = Vec::new();
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8()
+        .write_to(&mut std::io::Cursor::new(&mut thumb), ImageFormat::Jpeg)
+        .context("Failed to encode a cover art thumbnail")?;
+
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create the artwork cache at {:?}", dir))?;
+    write_atomically(&original, data)?;
+    write_atomically(&thumbnail, &thumb)?;
+    Ok(cached)
+}
+
+/// Where the cache at `dir` keeps image `hash`, or its thumbnail.
+pub(crate) fn artwork_file(dir: &Path, hash: &str, mime: &str, thumbnail: bool) -> PathBuf {
+    if thumbnail {
+        return dir.join(format!("{hash}-thumb.jpg"));
+    }
+    let ext = if mime == "image/png" { "png" } else { "jpg" };
+    dir.join(format!("{hash}.{ext}"))
+}
+
+/// Scans probe on several threads at once, so two of them may store the same image;
+/// each writes a file of its own and moves it into place.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let temp = path.with_extension(format!("{}.tmp", rand::random::<u64>()));
+    std::fs::write(&temp, data).with_context(|| format!("Failed to write {:?}", temp))?;
+    std::fs::rename(&temp, path).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
@@ -0,0 +1,384 @@
+//! Listings of the library by artist, album, genre and year, for browsing it without a
+//! search. Every listing comes in pages: a page ends with a cursor that picks up right
+//! after its last row, so rows added or removed in between don't shift what comes next.
+
+use anyhow::{anyhow, bail, Result};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::library::{track_from_row, Library, TrackRecord, TRACK_SELECT};
+
+/// Rows in a page unless the request asks for another size.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Most rows a page can hold.
+pub const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Which page of a listing to return.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PageRequest {
+    /// `next_cursor` of the previous page; None for the first one
+    pub cursor: Option<String>,
+    /// Defaults to `DEFAULT_PAGE_SIZE`, and is capped at `MAX_PAGE_SIZE`
+    pub limit: Option<usize>,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where the next page starts; None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtistSort {
+    #[default]
+    Name,
+    TrackCount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlbumSort {
+    #[default]
+    Title,
+    AlbumArtist,
+    Year,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GenreSort {
+    #[default]
+    Name,
+    TrackCount,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackSort {
+    /// By album, then disc and track number: an album plays through in order
+    #[default]
+    Album,
+    Title,
+    Artist,
+    Year,
+}
+
+/// Which tracks `Library::tracks` lists. Filters that are set all have to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TrackFilter {
+    /// Tracks by this artist, or on an album by them
+    pub artist_id: Option<i64>,
+    pub album_id: Option<i64>,
+    pub genre_id: Option<i64>,
+    pub year: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtistEntry {
+    pub id: i64,
+    pub name: String,
+    /// Tracks the artist is credited with
+    pub track_count: u32,
+    /// Albums the artist is the album artist of
+    pub album_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumEntry {
+    pub id: i64,
+    pub title: String,
+    pub album_artist_id: i64,
+    pub album_artist: String,
+    /// The earliest year among its tracks
+    pub year: Option<u32>,
+    pub track_count: u32,
+    pub duration_ms: u64,
+    /// Cover art of the first of its tracks that has any
+    pub artwork_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GenreEntry {
+    pub id: i64,
+    pub name: String,
+    pub track_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct YearEntry {
+    pub year: u32,
+    pub track_count: u32,
+    pub album_count: u32,
+}
+
+impl Library {
+    /// Artists credited with a track or an album.
+    pub fn artists(&self, sort: ArtistSort, page: &PageRequest) -> Result<Page<ArtistEntry>> {
+        let key = match sort {
+            ArtistSort::Name => "lower(a.name)",
+            ArtistSort::TrackCount => "(SELECT COUNT(*) FROM tracks t WHERE t.artist_id = a.id)",
+        };
+        let listing = Listing {
+            id: "a.id",
+            keys: &[key],
+            from: "FROM artists a
+                   WHERE EXISTS (SELECT 1 FROM tracks t WHERE t.artist_id = a.id)
+                      OR EXISTS (SELECT 1 FROM tracks t JOIN albums al ON al.id = t.album_id
+                                 WHERE al.album_artist_id = a.id)",
+        };
+        let conn = self.conn.lock().unwrap();
+        listing.page(&conn, vec![], page, |conn, ids| {
+            fetch_by_id(conn, ids, "
+                SELECT a.id, a.name,
+                       (SELECT COUNT(*) FROM tracks t WHERE t.artist_id = a.id),
+                       (SELECT COUNT(DISTINCT t.album_id) FROM tracks t JOIN albums al ON al.id = t.album_id
+                        WHERE al.album_artist_id = a.id)
+                FROM artists a
+                WHERE a.id IN ({ids})",
+                |row| Ok(ArtistEntry { id: row.get(0)?, name: row.get(1)?, track_count: row.get(2)?, album_count: row.get(3)? }),
+            )
+        })
+    }
+
+    /// Albums with tracks in the library; with `artist_id`, only those by that album
+    /// artist or with a track by that artist on them.
+    pub fn albums(&self, artist_id: Option<i64>, sort: AlbumSort, page: &PageRequest) -> Result<Page<AlbumEntry>> {
+        let keys: &[&str] = match sort {
+            AlbumSort::Title => &["lower(al.title)"],
+            AlbumSort::AlbumArtist => &["lower(aa.name)", "lower(al.title)"],
+            AlbumSort::Year => &["(SELECT COALESCE(MIN(t.year), 0) FROM tracks t WHERE t.album_id = al.id)", "lower(al.title)"],
+        };
+        let listing = Listing {
+            id: "al.id",
+            keys,
+            from: "FROM albums al
+                   JOIN artists aa ON aa.id = al.album_artist_id
+                   WHERE EXISTS (SELECT 1 FROM tracks t WHERE t.album_id = al.id)
+                     AND (?1 IS NULL OR al.album_artist_id = ?1
+                          OR EXISTS (SELECT 1 FROM tracks t WHERE t.album_id = al.id AND t.artist_id = ?1))",
+        };
+        let conn = self.conn.lock().unwrap();
+        listing.page(&conn, vec![artist_id.into()], page, |conn, ids| {
+            fetch_by_id(conn, ids, "
+                SELECT al.id, al.title, al.album_artist_id, aa.name, MIN(t.year), COUNT(t.id), SUM(t.duration_ms),
+                       (SELECT t2.artwork_id FROM tracks t2 WHERE t2.album_id = al.id AND t2.artwork_id IS NOT NULL
+                        ORDER BY t2.disc_number, t2.track_number, t2.path LIMIT 1)
+                FROM albums al
+                JOIN artists aa ON aa.id = al.album_artist_id
+                JOIN tracks t ON t.album_id = al.id
+                WHERE al.id IN ({ids})
+                GROUP BY al.id",
+                |row| Ok(AlbumEntry {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    album_artist_id: row.get(2)?,
+                    album_artist: row.get(3)?,
+                    year: row.get(4)?,
+                    track_count: row.get(5)?,
+                    duration_ms: row.get::<_, i64>(6)? as u64,
+                    artwork_id: row.get(7)?,
+                }),
+            )
+        })
+    }
+
+    /// Tracks matching `filter`.
+    pub fn tracks(&self, filter: &TrackFilter, sort: TrackSort, page: &PageRequest) -> Result<Page<TrackRecord>> {
+        let keys: &[&str] = match sort {
+            TrackSort::Album => &[
+                "COALESCE(lower(al.title), '')",
+                "COALESCE(t.disc_number, 0)",
+                "COALESCE(t.track_number, 0)",
+                "t.path",
+            ],
+            TrackSort::Title => &["lower(t.title)", "t.path"],
+            TrackSort::Artist => &["lower(a.name)", "lower(t.title)", "t.path"],
+            TrackSort::Year => &["COALESCE(t.year, 0)", "lower(t.title)", "t.path"],
+        };
+        let listing = Listing {
+            id: "t.id",
+            keys,
+            from: "FROM tracks t
+                   JOIN artists a ON a.id = t.artist_id
+                   LEFT JOIN albums al ON al.id = t.album_id
+                   WHERE (?1 IS NULL OR t.artist_id = ?1 OR al.album_artist_id = ?1)
+                     AND (?2 IS NULL OR t.album_id = ?2)
+                     AND (?3 IS NULL OR t.genre_id = ?3)
+                     AND (?4 IS NULL OR t.year = ?4)",
+        };
+        let params = vec![filter.artist_id.into(), filter.album_id.into(), filter.genre_id.into(), filter.year.into()];
+        let conn = self.conn.lock().unwrap();
+        listing.page(&conn, params, page, |conn, ids| {
+            fetch_by_id(conn, ids, &format!("{TRACK_SELECT} WHERE t.id IN ({{ids}})"), track_from_row)
+        })
+    }
+
+    /// Genres with tracks in the library.
+    pub fn genres(&self, sort: GenreSort, page: &PageRequest) -> Result<Page<GenreEntry>> {
+        let key = match sort {
+            GenreSort::Name => "lower(g.name)",
+            GenreSort::TrackCount => "(SELECT COUNT(*) FROM tracks t WHERE t.genre_id = g.id)",
+        };
+        let listing = Listing {
+            id: "g.id",
+            keys: &[key],
+            from: "FROM genres g WHERE EXISTS (SELECT 1 FROM tracks t WHERE t.genre_id = g.id)",
+        };
+        let conn = self.conn.lock().unwrap();
+        listing.page(&conn, vec![], page, |conn, ids| {
+            fetch_by_id(conn, ids, "
+                SELECT g.id, g.name, (SELECT COUNT(*) FROM tracks t WHERE t.genre_id = g.id)
+                FROM genres g
+                WHERE g.id IN ({ids})",
+                |row| Ok(GenreEntry { id: row.get(0)?, name: row.get(1)?, track_count: row.get(2)? }),
+            )
+        })
+    }
+
+    /// Years tracks in the library are from.
+    pub fn years(&self, page: &PageRequest) -> Result<Page<YearEntry>> {
+        let listing = Listing {
+            id: "year",
+            keys: &[],
+            from: "FROM tracks WHERE year IS NOT NULL GROUP BY year",
+        };
+        let conn = self.conn.lock().unwrap();
+        listing.page(&conn, vec![], page, |conn, years| {
+            fetch_by_id(conn, years, "
+                SELECT year, COUNT(*), COUNT(DISTINCT album_id) FROM tracks
+                WHERE year IN ({ids})
+                GROUP BY year",
+                |row| Ok(YearEntry { year: row.get(0)?, track_count: row.get(1)?, album_count: row.get(2)? }),
+            )
+        })
+    }
+}
+
+/// The rows a listing pages through: `SELECT {id}, {keys...} {from}`. Rows are ordered by
+/// the keys, then the id, so none of them may be NULL.
+struct Listing<'a> {
+    id: &'a str,
+    keys: &'a [&'a str],
+    /// FROM clause, and any WHERE/GROUP BY, with `params` numbered from ?1
+    from: &'a str,
+}
+
+impl Listing<'_> {
+    /// The page of rows after `page.cursor`. Their ids are handed to `fetch`, which returns
+    /// their entries in the same order.
+    fn page<T>(
+        &self,
+        conn: &Connection,
+        mut params: Vec<Value>,
+        page: &PageRequest,
+        fetch: impl FnOnce(&Connection, &[i64]) -> Result<Vec<T>>,
+    ) -> Result<Page<T>> {
+        let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let columns: Vec<String> = (1..=self.keys.len()).map(|i| format!("k{i}")).chain(["id".to_string()]).collect();
+        let selected: Vec<String> = self.keys.iter().zip(&columns)
+            .map(|(key, column)| format!("{key} AS {column}"))
+            .chain([format!("{} AS id", self.id)])
+            .collect();
+        let (compare, direction) = match page.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+
+        let mut sql = format!("SELECT * FROM (SELECT {} {})", selected.join(", "), self.from);
+        if let Some(cursor) = &page.cursor {
+            let after = decode_cursor(cursor, columns.len())?;
+            let first = params.len() + 1;
+            let placeholders: Vec<String> = (first..first + after.len()).map(|i| format!("?{i}")).collect();
+            sql += &format!(" WHERE ({}) {compare} ({})", columns.join(", "), placeholders.join(", "));
+            params.extend(after);
+        }
+        let order: Vec<String> = columns.iter().map(|c| format!("{c} {direction}")).collect();
+        // One row past the page tells whether there is a next one.
+        sql += &format!(" ORDER BY {} LIMIT {}", order.join(", "), limit + 1);
+
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows: Vec<Vec<Value>> = stmt
+            .query_map(params_from_iter(params), |row| (0..columns.len()).map(|i| row.get(i)).collect())?
+            .collect::<rusqlite::Result<_>>()?;
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|last| encode_cursor(last))
+        } else {
+            None
+        };
+
+        let ids: Vec<i64> = rows.iter()
+            .map(|row| match row.last() {
+                Some(Value::Integer(id)) => Ok(*id),
+                _ => Err(anyhow!("Listing row without an id")),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Page { items: fetch(conn, &ids)?, next_cursor })
+    }
+}
+
+/// Rows of `select` for `ids`, in the order of `ids`. `select` takes the ids where it says
+/// `{ids}`, and has the id as its first column.
+fn fetch_by_id<T>(
+    conn: &Connection,
+    ids: &[i64],
+    select: &str,
+    map: impl Fn(&rusqlite::Row) -> rusqlite::Result<T>,
+) -> Result<Vec<T>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let placeholders = vec!["?"; ids.len()].join(", ");
+    let mut stmt = conn.prepare(&select.replace("{ids}", &placeholders))?;
+    let mut found: HashMap<i64, T> = stmt
+        .query_map(params_from_iter(ids), |row| Ok((row.get(0)?, map(row)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(ids.iter().filter_map(|id| found.remove(id)).collect())
+}
+
+/// The sort keys and id of a page's last row, as a JSON array.
+fn encode_cursor(row: &[Value]) -> String {
+    let values: Vec<serde_json::Value> = row
+        .iter()
+        .map(|value| match value {
+            Value::Integer(i) => (*i).into(),
+            Value::Real(f) => (*f).into(),
+            Value::Text(s) => s.clone().into(),
+            Value::Null | Value::Blob(_) => serde_json::Value::Null,
+        })
+        .collect();
+    serde_json::Value::Array(values).to_string()
+}
+
+fn decode_cursor(cursor: &str, len: usize) -> Result<Vec<Value>> {
+    let values: Vec<serde_json::Value> =
+        serde_json::from_str(cursor).map_err(|_| anyhow!("Invalid page cursor"))?;
+    if values.len() != len {
+        bail!("Invalid page cursor");
+    }
+    values
+        .into_iter()
+        .map(|value| match value {
+            serde_json::Value::String(s) => Ok(Value::Text(s)),
+            serde_json::Value::Number(n) => n.as_i64().map(Value::Integer)
+                .or_else(|| n.as_f64().map(Value::Real))
+                .ok_or_else(|| anyhow!("Invalid page cursor")),
+            _ => Err(anyhow!("Invalid page cursor")),
+        })
+        .collect()
+}
@@ -0,0 +1,342 @@
+//! Parametric equalizer: a chain of biquad filters (RBJ audio EQ cookbook) applied to
+//! every track the player outputs, with settings that can change mid-track.
+
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+use serde::{Deserialize, Serialize};
+
+/// Most bands an equalizer can have.
+pub const MAX_BANDS: usize = 16;
+/// Gain range of a band and of the pre-amp, in dB.
+pub const GAIN_RANGE_DB: (f32, f32) = (-12.0, 12.0);
+/// Centre/corner frequencies accepted for a band, in Hz.
+pub const FREQ_RANGE_HZ: (f32, f32) = (20.0, 20_000.0);
+/// Quality factors accepted for a band.
+pub const Q_RANGE: (f32, f32) = (0.1, 10.0);
+
+/// Centre frequencies of the classic 10-band graphic layout the built-in presets use.
+const GRAPHIC_FREQS_HZ: [f32; 10] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+/// About one octave wide, so neighbouring graphic bands overlap smoothly.
+const GRAPHIC_Q: f32 = 1.41;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: BandKind,
+    pub freq_hz: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+/// Equalizer state as the player applies it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EqSettings {
+    pub enabled: bool,
+    /// Gain applied ahead of the bands, typically negative to leave headroom for boosts
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+}
+
+impl Default for EqSettings {
+    /// Disabled, with a flat 10-band graphic layout ready to be adjusted.
+    fn default() -> Self {
+        Self { enabled: false, preamp_db: 0.0, bands: graphic_bands([0.0; 10]) }
+    }
+}
+
+impl EqSettings {
+    /// Clamp every value into its accepted range, dropping bands with non-finite values
+    /// and any beyond `MAX_BANDS`.
+    pub fn sanitized(mut self) -> Self {
+        let clamp = |v: f32, (min, max): (f32, f32)| v.clamp(min, max);
+        self.preamp_db = if self.preamp_db.is_finite() { clamp(self.preamp_db, GAIN_RANGE_DB) } else { 0.0 };
+        self.bands.retain(|b| b.freq_hz.is_finite() && b.gain_db.is_finite() && b.q.is_finite());
+        self.bands.truncate(MAX_BANDS);
+        for band in &mut self.bands {
+            band.freq_hz = clamp(band.freq_hz, FREQ_RANGE_HZ);
+            band.gain_db = clamp(band.gain_db, GAIN_RANGE_DB);
+            band.q = clamp(band.q, Q_RANGE);
+        }
+        self
+    }
+
+    /// Enabled settings with the pre-amp and bands of `preset`.
+    pub fn from_preset(preset: &EqPreset) -> Self {
+        Self { enabled: true, preamp_db: preset.preamp_db, bands: preset.bands.clone() }.sanitized()
+    }
+}
+
+/// A named set of bands, either built in or saved by the user in the library DB.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub name: String,
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+}
+
+/// Peaking bands at the graphic layout's frequencies with the given gains.
+pub fn graphic_bands(gains_db: [f32; 10]) -> Vec<EqBand> {
+    GRAPHIC_FREQS_HZ
+        .iter()
+        .zip(gains_db)
+        .map(|(&freq_hz, gain_db)| EqBand { kind: BandKind::Peaking, freq_hz, gain_db, q: GRAPHIC_Q })
+        .collect()
+}
+
+/// Presets that ship with the player. Their pre-amps offset the largest boost.
+pub fn builtin_presets() -> Vec<EqPreset> {
+    let preset = |name: &str, gains_db: [f32; 10]| {
+        let max_boost = gains_db.iter().copied().fold(0.0, f32::max);
+        EqPreset { name: name.to_string(), preamp_db: -max_boost, bands: graphic_bands(gains_db) }
+    };
+    vec![
+        preset("Flat", [0.0; 10]),
+        preset("Bass Boost", [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        preset("Treble Boost", [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 4.0, 5.0, 6.0]),
+        preset("Vocal", [-2.0, -2.0, -1.0, 1.0, 3.0, 3.5, 3.0, 1.5, 0.0, -1.0]),
+        preset("Rock", [4.5, 3.5, 2.0, 0.0, -1.5, -1.0, 1.0, 2.5, 3.5, 4.0]),
+        preset("Pop", [-1.0, 0.0, 1.5, 3.0, 3.5, 2.5, 1.0, 0.0, -0.5, -1.0]),
+        preset("Jazz", [3.0, 2.0, 1.0, 1.5, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+        preset("Classical", [3.5, 2.5, 1.5, 0.5, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0]),
+        preset("Electronic", [5.0, 4.0, 1.5, 0.0, -1.5, 1.0, 0.5, 1.5, 4.0, 4.5]),
+        preset("Loudness", [5.0, 3.5, 0.0, 0.0, -1.0, 0.0, -0.5, -3.0, 4.0, 2.0]),
+    ]
+}
+
+/// The built-in preset called `name`, if there is one.
+pub fn builtin_preset(name: &str) -> Option<EqPreset> {
+    builtin_presets().into_iter().find(|p| p.name == name)
+}
+
+/// Settings shared between the player and the sources playing in its sinks.
+#[derive(Debug)]
+pub(crate) struct EqControl {
+    settings: Mutex<EqSettings>,
+    /// Bumped on every change so sources know to recompute their filters.
+    version: AtomicU64,
+}
+
+impl EqControl {
+    pub(crate) fn new(settings: EqSettings) -> Self {
+        Self { settings: Mutex::new(settings), version: AtomicU64::new(0) }
+    }
+
+    pub(crate) fn settings(&self) -> EqSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, settings: EqSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Normalized biquad coefficients, a0 = 1.
+#[derive(Debug, Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    fn new(band: &EqBand, sample_rate: u32) -> Self {
+        // Keep the design stable for bands set above what this sample rate can represent.
+        let freq = band.freq_hz.min(sample_rate as f32 * 0.45);
+        let w0 = 2.0 * PI * freq / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q);
+        let a = 10f32.powf(band.gain_db / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            BandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            BandKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+        Self { b0: b0 / a0, b1: b1 / a0, b2: b2 / a0, a1: a1 / a0, a2: a2 / a0 }
+    }
+}
+
+/// Filter memory of one band on one channel (transposed direct form II).
+#[derive(Debug, Clone, Copy, Default)]
+struct BiquadState {
+    s1: f32,
+    s2: f32,
+}
+
+impl BiquadState {
+    #[inline]
+    fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + self.s1;
+        self.s1 = c.b1 * x - c.a1 * y + self.s2;
+        self.s2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+/// Runs a track through the equalizer. Picks up new settings at the next frame boundary;
+/// filter memory carries over when only gains or frequencies change, avoiding clicks.
+pub(crate) struct Equalizer<S> {
+    inner: S,
+    control: Arc<EqControl>,
+    /// Settings version the filters were built for; None forces a rebuild.
+    version: Option<u64>,
+    sample_rate: u32,
+    channels: u16,
+    enabled: bool,
+    preamp: f32,
+    coefficients: Vec<Coefficients>,
+    /// One state per band for each channel, channel-major.
+    states: Vec<BiquadState>,
+    /// Channel of the next sample within the current frame.
+    channel: usize,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    pub(crate) fn new(inner: S, control: Arc<EqControl>) -> Self {
+        let (sample_rate, channels) = (inner.sample_rate(), inner.channels());
+        Self {
+            inner,
+            control,
+            version: None,
+            sample_rate,
+            channels,
+            enabled: false,
+            preamp: 1.0,
+            coefficients: Vec::new(),
+            states: Vec::new(),
+            channel: 0,
+        }
+    }
+
+    /// Rebuild the filters when the settings or the stream format changed.
+    fn refresh(&mut self) {
+        let (sample_rate, channels) = (self.inner.sample_rate(), self.inner.channels());
+        let format_changed = sample_rate != self.sample_rate || channels != self.channels;
+        let version = self.control.version.load(Ordering::Acquire);
+        if !format_changed && self.version == Some(version) {
+            return;
+        }
+        // The player holds the lock only to swap settings; pick them up next frame instead of waiting.
+        let Ok(settings) = self.control.settings.try_lock() else { return };
+
+        let bands = settings.bands.len();
+        if format_changed || !self.enabled || bands != self.coefficients.len() {
+            self.states = vec![BiquadState::default(); bands * channels as usize];
+        }
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+        self.enabled = settings.enabled;
+        self.preamp = 10f32.powf(settings.preamp_db / 20.0);
+        self.coefficients = settings.bands.iter().map(|b| Coefficients::new(b, sample_rate)).collect();
+        self.version = Some(version);
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.refresh();
+        }
+        let x = self.inner.next()?.to_f32();
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.channels.max(1) as usize;
+
+        if !self.enabled {
+            return Some(x);
+        }
+        let bands = self.coefficients.len();
+        let states = &mut self.states[channel * bands..(channel + 1) * bands];
+        let y = self.coefficients
+            .iter()
+            .zip(states)
+            .fold(x * self.preamp, |y, (c, state)| state.process(c, y));
+        Some(y)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        // Ringing from before the seek would otherwise bleed into the new position.
+        self.states.fill(BiquadState::default());
+        self.channel = 0;
+        Ok(())
+    }
+}
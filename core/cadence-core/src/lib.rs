@@ -1,21 +1,79 @@
+pub mod artwork;
+pub mod browse;
+pub mod eq;
 pub mod library;
-pub use library::{Library, LibraryRecord, TrackRecord};
+pub mod loudness;
+pub mod migrations;
+pub mod output;
+pub mod playlist;
+pub mod playlist_file;
+pub mod query;
+pub mod queue;
+pub mod replaygain;
+pub mod smart_playlist;
+pub mod watcher;
+mod source;
+pub use artwork::Artwork;
+pub use browse::{
+    AlbumEntry, AlbumSort, ArtistEntry, ArtistSort, GenreEntry, GenreSort, Page, PageRequest, SortOrder, TrackFilter,
+    TrackSort, YearEntry,
+};
+pub use eq::{EqBand, EqPreset, EqSettings};
+pub use library::{
+    IndexSummary, Library, LibraryEvent, LibraryRecord, LoudnessProgress, ScanIssue, ScanIssueKind, ScanProgress,
+    TrackRecord,
+};
+pub use playlist::{Playlist, PlaylistEvent, PlaylistItem};
+pub use playlist_file::{PlaylistFileEntry, PlaylistFormat, PlaylistImport, UnresolvedEntry};
+pub use query::QueryError;
+pub use queue::{PlayContext, PlayQueue, QueueEvent, QueueItem};
+pub use replaygain::{ReplayGain, ReplayGainMode};
+pub use smart_playlist::{
+    DateField, NumberField, NumberOp, Rule, SmartPlaylist, SmartPlaylistRules, SmartSort, TextField, TextOp,
+};
+pub use watcher::LibraryWatcher;
 
 use anyhow::{Context, Result};
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::{Decoder, Sink, Source};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::fs::File;
 use std::io::BufReader;
 use lofty::file::TaggedFile;
+use eq::{EqControl, Equalizer};
+use output::{AudioOutput, DeviceOutput};
+use source::{TrackControl, Tracked};
+
+/// How often `poll_events` tries to fall back to the default device while the selected
+/// one is gone.
+const DEVICE_FALLBACK_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TrackInfo {
     pub path: PathBuf,
-    pub duration_ms: u64,
+    /// None when neither the container, the packets nor the bitrate tell how long the
+    /// track is. Such tracks play normally but can't be seeked.
+    pub duration_ms: Option<u64>,
+    /// True when `duration_ms` is estimated from the bitrate, so seeking is approximate.
+    pub duration_estimated: bool,
     pub title: Option<String>,
     pub artist: Option<String>,
+    pub album: Option<String>,
+    pub replay_gain: ReplayGain,
+}
+
+impl TrackInfo {
+    /// Seeking needs a duration to bound it, estimated or not.
+    pub fn seekable(&self) -> bool {
+        self.duration_ms.is_some()
+    }
+
+    /// True when both tracks are tagged with the same album.
+    fn same_album(&self, other: &TrackInfo) -> bool {
+        self.album.is_some() && self.album == other.album
+    }
 }
 
 fn get_tagged_file(path: &Path) -> Option<TaggedFile> {
@@ -24,17 +82,35 @@ fn get_tagged_file(path: &Path) -> Option<TaggedFile> {
         .and_then(|p| p.read().ok())
 }
 
-fn probe_tags(path: &Path) -> (Option<String>, Option<String>) {
+/// Tag values and stream properties the player cares about.
+#[derive(Default)]
+struct PlaybackTags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    replay_gain: ReplayGain,
+    /// Average bitrate in kbps as reported by the file's headers
+    bitrate_kbps: Option<u32>,
+}
+
+fn probe_tags(path: &Path) -> PlaybackTags {
     use lofty::prelude::*;
     let Some(tagged) =
         get_tagged_file(path)
     else {
-        return (None, None);
+        return PlaybackTags::default();
+    };
+    let bitrate_kbps = tagged.properties().audio_bitrate().filter(|&kbps| kbps > 0);
+    let Some(tag) = tagged.primary_tag().or_else(|| tagged.first_tag()) else {
+        return PlaybackTags { bitrate_kbps, ..Default::default() };
     };
-    let tag = tagged.primary_tag().or_else(|| tagged.first_tag());
-    let title = tag.and_then(|t| t.title().as_deref().map(String::from));
-    let artist = tag.and_then(|t| t.artist().as_deref().map(String::from));
-    (title, artist)
+    PlaybackTags {
+        title: tag.title().as_deref().map(String::from),
+        artist: tag.artist().as_deref().map(String::from),
+        album: tag.album().as_deref().map(String::from),
+        replay_gain: ReplayGain::from_tag(tag),
+        bitrate_kbps,
+    }
 }
 
 /// Represents the current state of a playing track
@@ -42,52 +118,27 @@ fn probe_tags(path: &Path) -> (Option<String>, Option<String>) {
 pub struct CurrentTrack {
     /// Information about the track (path, duration)
     pub info: TrackInfo,
-    /// Timestamp when playback last started/resumed; None when paused
-    pub last_playback_timestamp: Option<Instant>,
-    /// Position in ms at the time of last playback start/pause
-    pub last_playback_position: u64,
+    /// True while playback is paused
+    pub paused: bool,
+    /// Handle on the source playing this track in the sink
+    control: Arc<TrackControl>,
 }
 
 impl CurrentTrack {
-    /// Create a new CurrentTrack starting from position 0
-    fn new(info: TrackInfo) -> Self {
-        Self {
-            info,
-            last_playback_timestamp: Some(Instant::now()),
-            last_playback_position: 0,
-        }
+    /// Create a new, playing CurrentTrack
+    fn new(info: TrackInfo, control: Arc<TrackControl>) -> Self {
+        Self { info, paused: false, control }
     }
 
-    /// Get the current playback position in milliseconds
+    /// Get the current playback position in milliseconds, from the samples the sink
+    /// has consumed. Doesn't advance while paused, stalled or after the track has drained.
     pub fn current_position_ms(&self) -> u64 {
-        match self.last_playback_timestamp {
-            Some(timestamp) => self.last_playback_position + timestamp.elapsed().as_millis() as u64,
-            None => self.last_playback_position,
-        }
-    }
-
-    /// Mark as paused, capturing current position
-    fn pause(&mut self) {
-        self.last_playback_position = self.current_position_ms();
-        self.last_playback_timestamp = None;
-    }
-
-    /// Mark as resumed, starting time tracking from now
-    fn resume(&mut self) {
-        self.last_playback_timestamp = Some(Instant::now());
-    }
-
-    /// Update position after seek, preserving playing/paused state
-    fn set_position(&mut self, position_ms: u64, playing: bool) {
-        self.last_playback_position = position_ms;
-        self.last_playback_timestamp = if playing { Some(Instant::now()) } else { None };
+        self.control.position().as_millis() as u64
     }
 }
 
-/// Fallback duration probe for files where the decoder can't report total_duration()
-/// (e.g. VBR MP3s without a Xing/VBRI header).
-/// Fast path: reads n_frames from codec params. Slow path: walks all packets.
-fn scan_duration_ms(path: &std::path::Path) -> Option<u64> {
+/// Open `path` with symphonia's default probe, using the extension as a format hint.
+fn probe_format(path: &Path) -> Option<symphonia::core::probe::ProbeResult> {
     use symphonia::core::formats::FormatOptions;
     use symphonia::core::io::MediaSourceStream;
     use symphonia::core::meta::MetadataOptions;
@@ -99,9 +150,15 @@ fn scan_duration_ms(path: &std::path::Path) -> Option<u64> {
     if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
         hint.with_extension(ext);
     }
-    let mut probed = symphonia::default::get_probe()
+    symphonia::default::get_probe()
         .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
-        .ok()?;
+        .ok()
+}
+
+/// Exact duration probe. Fast path: reads n_frames from codec params.
+/// Slow path: walks all packets (e.g. VBR MP3s without a Xing/VBRI header).
+fn scan_duration_ms(path: &std::path::Path) -> Option<u64> {
+    let mut probed = probe_format(path)?;
 
     let track = probed.format.default_track()?;
 
@@ -130,6 +187,13 @@ fn scan_duration_ms(path: &std::path::Path) -> Option<u64> {
     Some((secs * 1000.0) as u64)
 }
 
+/// Rough duration from the file size and average bitrate, for streams whose packets
+/// carry no timestamps.
+fn estimate_duration_ms(path: &Path, bitrate_kbps: u32) -> Option<u64> {
+    let bytes = std::fs::metadata(path).ok()?.len();
+    Some(bytes * 8 / bitrate_kbps as u64)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PlayerMode {
     Default,
@@ -137,26 +201,116 @@ pub enum PlayerMode {
     Replay,
 }
 
+/// Something that happened inside the player without a command asking for it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlayerEvent {
+    /// The sink moved on to the pre-queued track.
+    TrackChanged { track: TrackInfo },
+    /// The selected output device went away and playback moved to the default one.
+    OutputDeviceLost { device: String },
+    /// Something went wrong that playback recovered from on its own.
+    Error { message: String },
+}
+
+type TrackDecoder = Decoder<BufReader<File>>;
+
+/// Dynamic range covered by the volume control; volume 0.0 is silence below this.
+const VOLUME_RANGE_DB: f32 = 60.0;
+
+/// Map a volume control position in 0.0..=1.0 to a linear amplitude on a perceptual
+/// (logarithmic) scale. The bottom tenth ramps linearly down to true silence.
+fn volume_to_amplitude(volume: f32) -> f32 {
+    if volume <= 0.0 {
+        return 0.0;
+    }
+    let amplitude = 10f32.powf(VOLUME_RANGE_DB * (volume - 1.0) / 20.0);
+    if volume < 0.1 { amplitude * volume * 10.0 } else { amplitude }
+}
+
+/// Longest supported crossfade between two tracks.
+pub const MAX_CROSSFADE: Duration = Duration::from_secs(12);
+
+/// A track that has been decoded ahead of time to follow the current one.
+struct QueuedTrack {
+    info: TrackInfo,
+    control: Arc<TrackControl>,
+    /// Decoder held back until the crossfade into this track starts.
+    /// None when the track was appended to the sink for a gapless transition.
+    pending: Option<TrackDecoder>,
+}
+
+/// Open `path` with the decoder used for playback and gather its metadata.
+fn open_track(path: PathBuf) -> Result<(TrackDecoder, TrackInfo)> {
+    let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+    let src = Decoder::new(BufReader::new(file))
+        .with_context(|| format!("Unsupported/invalid audio: {:?}", path))?;
+
+    let tags = probe_tags(&path);
+    // Not the decoder's total_duration(): rodio mangles its sub-second part.
+    let (duration_ms, duration_estimated) = match scan_duration_ms(&path) {
+        Some(ms) => (Some(ms), false),
+        None => {
+            let estimate = tags.bitrate_kbps.and_then(|kbps| estimate_duration_ms(&path, kbps));
+            (estimate, estimate.is_some())
+        }
+    };
+    let info = TrackInfo {
+        path,
+        duration_ms,
+        duration_estimated,
+        title: tags.title,
+        artist: tags.artist,
+        album: tags.album,
+        replay_gain: tags.replay_gain,
+    };
+    Ok((src, info))
+}
+
 pub struct Player {
-    _stream: OutputStream,
-    _handle: OutputStreamHandle,
+    output: Box<dyn AudioOutput>,
     sink: Sink,
+    /// Sinks of outgoing tracks still fading out under a crossfade
+    fading: Vec<Sink>,
     /// Current track state, if any
     current_track: Option<CurrentTrack>,
+    /// Track lined up after the current one, for gapless or crossfaded transitions
+    next_track: Option<QueuedTrack>,
     mode: PlayerMode,
+    crossfade: Duration,
+    replay_gain_mode: ReplayGainMode,
+    preamp_db: f32,
+    /// Volume control position in 0.0..=1.0
+    volume: f32,
+    muted: bool,
+    /// Equalizer settings, shared with every source in the sinks
+    eq: Arc<EqControl>,
+    last_device_fallback: Instant,
 }
 
 impl Player {
+    /// Player on the system's default output device.
     pub fn new() -> Result<Self> {
-        let (stream, handle) =
-            OutputStream::try_default().context("No default output device available")?;
-        let sink = Sink::try_new(&handle).context("Failed to create sink")?;
+        Self::with_output(Box::new(DeviceOutput::open(None)?))
+    }
+
+    /// Player on any output backend, e.g. `output::NullOutput` where there is no sound card.
+    pub fn with_output(output: Box<dyn AudioOutput>) -> Result<Self> {
+        let sink = output.new_sink()?;
         Ok(Self {
-            _stream: stream,
-            _handle: handle,
+            output,
             sink,
+            fading: Vec::new(),
             current_track: None,
+            next_track: None,
             mode: PlayerMode::Default,
+            crossfade: Duration::ZERO,
+            replay_gain_mode: ReplayGainMode::Off,
+            preamp_db: 0.0,
+            volume: 1.0,
+            muted: false,
+            eq: Arc::new(EqControl::new(EqSettings::default())),
+            last_device_fallback: Instant::now(),
         })
     }
 
@@ -173,53 +327,317 @@ impl Player {
             .unwrap_or(0)
     }
 
-    pub fn load_and_play(&mut self, path: PathBuf) -> Result<TrackInfo> {
-        // Open once for duration using the same decoder we'll use for playback.
-        let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
-        let src = Decoder::new(BufReader::new(file))
-            .with_context(|| format!("Unsupported/invalid audio: {:?}", path))?;
-        let duration_ms = src.total_duration()
-            .map(|d| d.as_millis() as u64)
-            .or_else(|| scan_duration_ms(&path))
-            .unwrap_or_else(|| panic!("Cannot determine duration for {:?}", path));
-
-        let (title, artist) = probe_tags(&path);
-        let info = TrackInfo {
-            path,
-            duration_ms,
-            title,
-            artist,
-        };
+    /// The track that will play once the current one ends, if one has been queued.
+    pub fn next_track(&self) -> Option<&TrackInfo> {
+        self.next_track.as_ref().map(|t| &t.info)
+    }
+
+    /// Volume control position in 0.0..=1.0, regardless of mute.
+    pub fn get_volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Set the volume control position (clamped to 0.0..=1.0). The sinks get a
+    /// perceptually scaled amplitude, so 0.5 sounds about half as loud as 1.0.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = if volume.is_finite() { volume.clamp(0.0, 1.0) } else { 1.0 };
+        self.apply_volume();
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silence output without losing the volume setting.
+    pub fn mute(&mut self, muted: bool) {
+        self.muted = muted;
+        self.apply_volume();
+    }
+
+    fn amplitude(&self) -> f32 {
+        if self.muted { 0.0 } else { volume_to_amplitude(self.volume) }
+    }
+
+    fn apply_volume(&self) {
+        let amplitude = self.amplitude();
+        self.sink.set_volume(amplitude);
+        for sink in &self.fading {
+            sink.set_volume(amplitude);
+        }
+    }
+
+    /// Name of the selected output device; None when playing on the system default
+    /// or on a software output.
+    pub fn output_device(&self) -> Option<&str> {
+        self.output.device_name()
+    }
+
+    /// Move playback to the output device called `name` (None for the system default).
+    pub fn set_output_device(&mut self, name: Option<String>) -> Result<()> {
+        let output = DeviceOutput::open(name.as_deref())?;
+        self.set_output(Box::new(output))
+    }
+
+    /// Swap the output backend. The sinks are rebuilt and the current track resumes where it was
+    /// (or from the start when it can't be seeked).
+    pub fn set_output(&mut self, output: Box<dyn AudioOutput>) -> Result<()> {
+        let sink = output.new_sink()?;
+        sink.set_volume(self.amplitude());
+
+        let resume = self.current_track.as_ref().map(|t| {
+            (t.info.path.clone(), t.info.replay_gain, t.current_position_ms(), !t.paused)
+        });
+        let next = self.next_track.as_ref().map(|t| (t.info.path.clone(), t.info.replay_gain));
+        self.stop();
+
+        self.sink = sink;
+        self.output = output;
+
+        if let Some((path, replay_gain, position_ms, playing)) = resume {
+            self.load_and_play(path, Some(replay_gain))?;
+            if !playing {
+                self.pause();
+            }
+            if self.current_track.as_ref().is_some_and(|t| t.info.seekable()) {
+                self.seek(position_ms)?;
+            }
+            if let Some((next, replay_gain)) = next {
+                self.queue_next(next, Some(replay_gain)).ok();
+            }
+        }
+        Ok(())
+    }
 
+    /// `open_track`, falling back to `analyzed` when the tags have no ReplayGain values.
+    fn open(&self, path: PathBuf, analyzed: Option<ReplayGain>) -> Result<(TrackDecoder, TrackInfo)> {
+        let (src, mut info) = open_track(path)?;
+        if info.replay_gain.track_gain_db.is_none() && info.replay_gain.album_gain_db.is_none() {
+            if let Some(analyzed) = analyzed {
+                info.replay_gain = analyzed;
+            }
+        }
+        Ok((src, info))
+    }
+
+    /// Play `path` right away. `analyzed` stands in for ReplayGain tags the file doesn't
+    /// have, e.g. what `Library::analyzed_replay_gain` found for it; the same goes for
+    /// `crossfade_to` and `queue_next`.
+    pub fn load_and_play(&mut self, path: PathBuf, analyzed: Option<ReplayGain>) -> Result<TrackInfo> {
+        let (src, info) = self.open(path, analyzed)?;
+        self.play_now(src, info.clone());
+        Ok(info)
+    }
+
+    /// Replace whatever is playing with `src`, cutting off any queued or fading tracks.
+    fn play_now(&mut self, src: TrackDecoder, info: TrackInfo) {
+        self.next_track = None;
+        self.stop_fading();
         self.sink.clear();
-        self.sink.append(src);
+        let control = Arc::new(TrackControl::new(self.gain_for(&info)));
+        self.sink.append(Tracked::new(Equalizer::new(src, Arc::clone(&self.eq)), Arc::clone(&control)));
         self.sink.play();
 
-        self.current_track = Some(CurrentTrack::new(info.clone()));
+        self.current_track = Some(CurrentTrack::new(info, control));
+    }
 
+    /// Switch to `path`, crossfading from the current track when crossfade is enabled.
+    /// Falls back to `load_and_play` when nothing is playing or both tracks share an album.
+    pub fn crossfade_to(&mut self, path: PathBuf, analyzed: Option<ReplayGain>) -> Result<TrackInfo> {
+        let (src, info) = self.open(path, analyzed)?;
+        if self.should_crossfade(&info) {
+            self.clear_next();
+            let control = Arc::new(TrackControl::new(self.gain_for(&info)));
+            self.start_crossfade(src, info.clone(), control)?;
+        } else {
+            self.play_now(src, info.clone());
+        }
         Ok(info)
     }
 
+    /// Line up `path` to play once the current track ends. Replaces any previously queued track.
+    /// The track is appended to the sink right away for a gapless transition, or held back
+    /// and crossfaded in over the last seconds of the current track when crossfade applies.
+    pub fn queue_next(&mut self, path: PathBuf, analyzed: Option<ReplayGain>) -> Result<TrackInfo> {
+        self.clear_next();
+        let (src, info) = self.open(path, analyzed)?;
+
+        let control = Arc::new(TrackControl::new(self.gain_for(&info)));
+        let pending = if self.should_crossfade(&info) {
+            Some(src)
+        } else {
+            self.sink.append(Tracked::new(Equalizer::new(src, Arc::clone(&self.eq)), Arc::clone(&control)));
+            None
+        };
+        self.next_track = Some(QueuedTrack { info: info.clone(), control, pending });
+
+        Ok(info)
+    }
+
+    /// Forget the queued track. It is skipped by the sink if it hasn't started yet.
+    pub fn clear_next(&mut self) {
+        if let Some(next) = self.next_track.take() {
+            next.control.cancel();
+        }
+    }
+
+    /// Pick up transitions the sink made on its own since the last call.
+    /// The current track is switched at the sample boundary where the queued track started.
+    pub fn poll_events(&mut self) -> Vec<PlayerEvent> {
+        let mut events = Vec::new();
+        self.fading.retain(|sink| !sink.empty());
+
+        if self.output.device_lost() && self.last_device_fallback.elapsed() >= DEVICE_FALLBACK_INTERVAL {
+            self.last_device_fallback = Instant::now();
+            let device = self.output.device_name().unwrap_or_default().to_string();
+            if self.set_output_device(None).is_ok() {
+                events.push(PlayerEvent::OutputDeviceLost { device });
+            }
+        }
+
+        // A held-back track starts once the current one enters its final crossfade window.
+        let crossfade_due = match (&self.next_track, &self.current_track) {
+            (Some(next), Some(current)) if next.pending.is_some() => {
+                let playing = !current.paused;
+                let position_ms = current.current_position_ms();
+                let near_end = current.info.duration_ms
+                    .is_some_and(|d| d.saturating_sub(position_ms) <= self.crossfade.as_millis() as u64);
+                self.sink.empty() || (playing && near_end)
+            }
+            _ => false,
+        };
+        if crossfade_due {
+            let mut next = self.next_track.take().unwrap();
+            let src = next.pending.take().unwrap();
+            if self.start_crossfade(src, next.info.clone(), next.control).is_ok() {
+                events.push(PlayerEvent::TrackChanged { track: next.info });
+            }
+            return events;
+        }
+
+        let started = self.next_track
+            .as_ref()
+            .is_some_and(|next| next.pending.is_none() && next.control.has_started());
+        if started {
+            let next = self.next_track.take().unwrap();
+            self.current_track = Some(CurrentTrack::new(next.info.clone(), next.control));
+            events.push(PlayerEvent::TrackChanged { track: next.info });
+        }
+        events
+    }
+
+    /// Crossfade duration; zero when crossfading is off.
+    pub fn crossfade(&self) -> Duration {
+        self.crossfade
+    }
+
+    /// Set the crossfade duration, clamped to `MAX_CROSSFADE`. Zero turns crossfading off.
+    /// Applies to transitions lined up after this call.
+    pub fn set_crossfade(&mut self, duration: Duration) {
+        self.crossfade = duration.min(MAX_CROSSFADE);
+    }
+
+    pub fn replay_gain_mode(&self) -> ReplayGainMode {
+        self.replay_gain_mode
+    }
+
+    pub fn preamp_db(&self) -> f32 {
+        self.preamp_db
+    }
+
+    /// Select the loudness normalization mode and pre-amp (clamped to `PREAMP_RANGE_DB`).
+    /// Takes effect immediately on the current and queued tracks.
+    pub fn set_replay_gain(&mut self, mode: ReplayGainMode, preamp_db: f32) {
+        let (min_db, max_db) = replaygain::PREAMP_RANGE_DB;
+        self.replay_gain_mode = mode;
+        self.preamp_db = if preamp_db.is_finite() { preamp_db.clamp(min_db, max_db) } else { 0.0 };
+
+        if let Some(current) = &self.current_track {
+            current.control.set_gain(self.gain_for(&current.info));
+        }
+        if let Some(next) = &self.next_track {
+            next.control.set_gain(self.gain_for(&next.info));
+        }
+    }
+
+    pub fn equalizer(&self) -> EqSettings {
+        self.eq.settings()
+    }
+
+    /// Replace the equalizer settings (sanitized to their accepted ranges).
+    /// Playing and queued tracks pick them up within a frame.
+    pub fn set_equalizer(&mut self, settings: EqSettings) {
+        self.eq.set(settings.sanitized());
+    }
+
+    /// Linear gain stage for `info` under the current normalization settings.
+    fn gain_for(&self, info: &TrackInfo) -> f32 {
+        info.replay_gain.linear_gain(self.replay_gain_mode, self.preamp_db)
+    }
+
+    /// Crossfade only between playing tracks that don't belong to the same album,
+    /// so albums meant to be heard back to back stay gapless.
+    fn should_crossfade(&self, incoming: &TrackInfo) -> bool {
+        if self.crossfade.is_zero() {
+            return false;
+        }
+        match &self.current_track {
+            Some(current) => !current.paused && !current.info.same_album(incoming),
+            None => false,
+        }
+    }
+
+    /// Start `src` fading in on a fresh sink while the current track fades out on the old one.
+    fn start_crossfade(&mut self, src: TrackDecoder, info: TrackInfo, control: Arc<TrackControl>) -> Result<()> {
+        let sink = self.output.new_sink()?;
+        sink.set_volume(self.amplitude());
+        let src = Equalizer::new(src.fade_in(self.crossfade), Arc::clone(&self.eq));
+        sink.append(Tracked::new(src, Arc::clone(&control)));
+
+        if let Some(current) = &self.current_track {
+            current.control.fade_out(self.crossfade);
+        }
+        let outgoing = std::mem::replace(&mut self.sink, sink);
+        self.fading.push(outgoing);
+
+        self.current_track = Some(CurrentTrack::new(info, control));
+        Ok(())
+    }
+
+    /// Cut off any tracks still fading out.
+    fn stop_fading(&mut self) {
+        for sink in self.fading.drain(..) {
+            sink.stop();
+        }
+    }
+
     pub fn pause(&mut self) {
         if let Some(track) = &mut self.current_track {
-            track.pause();
+            track.paused = true;
         }
         self.sink.pause();
+        for sink in &self.fading {
+            sink.pause();
+        }
     }
 
     pub fn resume(&mut self) {
         if let Some(track) = &mut self.current_track {
-            track.resume();
+            track.paused = false;
         }
         self.sink.play();
+        for sink in &self.fading {
+            sink.play();
+        }
     }
 
     pub fn stop(&mut self) {
+        self.clear_next();
+        self.stop_fading();
         self.sink.stop();
         self.current_track = None;
     }
 
-    /// True when the sink has no more samples i.e. the track finished playing.
+    /// True when the sink has no more samples i.e. the track (and any queued one) finished playing.
     pub fn is_finished(&self) -> bool {
         self.sink.empty()
     }
@@ -228,16 +646,14 @@ impl Player {
         use std::time::Duration;
 
         let Some(track) = &self.current_track else { return Ok(()) };
+        let Some(duration_ms) = track.info.duration_ms else {
+            anyhow::bail!("Cannot seek in {:?}: its duration is unknown", track.info.path);
+        };
 
-        let to_ms = to_ms.min(track.info.duration_ms.saturating_sub(1));
+        let to_ms = to_ms.min(duration_ms.saturating_sub(1));
 
-        let was_playing = track.last_playback_timestamp.is_some();
+        // The source resets its sample clock to the new position once the seek lands.
         self.sink.try_seek(Duration::from_millis(to_ms)).map_err(|e| anyhow::anyhow!("{e}"))?;
-
-        if let Some(track) = &mut self.current_track {
-            track.set_position(to_ms, was_playing);
-        }
-
         Ok(())
     }
 
@@ -1,9 +1,23 @@
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use rayon::prelude::*;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use crate::artwork::{self, Artwork, CachedArtwork};
+use crate::eq::{self, EqBand, EqPreset};
 use crate::get_tagged_file;
+use crate::migrations;
+use crate::query;
+use crate::queue::{PlayContext, PlayQueue, QueueItem, StoredContext};
+use crate::loudness::{self, Loudness};
+use crate::replaygain::ReplayGain;
+use crate::smart_playlist::TrackCounts;
 
 const AUDIO_EXTENSIONS: &[&str] = &[
     "mp3", "flac", "ogg", "wav", "aac", "m4a", "opus", "wv", "ape",
@@ -11,8 +25,25 @@ const AUDIO_EXTENSIONS: &[&str] = &[
 
 const UNKNOWN_ARTIST: &str = "Unknown Artist";
 
+/// Files a scan probes on the worker pool between two writes. The DB lock is only held
+/// for the writes, so searches get through while a big folder is scanned.
+const SCAN_BATCH: usize = 64;
+
+/// Codecs the tag reader understands but the decoder can't play.
+const UNPLAYABLE_CODECS: &[&str] = &["Monkey's Audio", "Musepack", "Opus", "Speex", "WavPack"];
+
+/// Most tracks a search returns.
+const SEARCH_LIMIT: usize = 50;
+
+/// Most tracks a search for misspelled words ranks by spelling, keeping the best
+/// `SEARCH_LIMIT`.
+const FUZZY_CANDIDATES: usize = 500;
+
 pub struct Library {
-    conn: Mutex<Connection>,
+    pub(crate) conn: Mutex<Connection>,
+    /// Where cover art and its thumbnails are cached, next to the DB
+    artwork_dir: PathBuf,
+    pub(crate) smart_counts: Mutex<TrackCounts>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -22,6 +53,100 @@ pub struct TrackRecord {
     pub title: String,
     pub artist: String,
     pub duration_ms: u64,
+    pub album: Option<String>,
+    /// Falls back to the track artist when the album has no album artist tag
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    /// e.g. "FLAC", "MP3", "AAC"
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    pub bitrate_kbps: Option<u32>,
+    /// Cover art, from the file's tags or its folder; see `Library::artwork`
+    pub artwork_id: Option<i64>,
+}
+
+/// Columns and joins that `track_from_row` reads a `TrackRecord` from.
+pub(crate) const TRACK_SELECT: &str = "
+    SELECT t.id, t.path, t.title, a.name, t.duration_ms,
+           al.title, aa.name, t.track_number, t.disc_number, t.year, g.name, c.name,
+           t.codec, t.sample_rate, t.bit_depth, t.channels, t.bitrate_kbps, t.artwork_id
+    FROM tracks t
+    JOIN artists a ON a.id = t.artist_id
+    LEFT JOIN albums al ON al.id = t.album_id
+    LEFT JOIN artists aa ON aa.id = al.album_artist_id
+    LEFT JOIN genres g ON g.id = t.genre_id
+    LEFT JOIN artists c ON c.id = t.composer_id";
+
+pub(crate) fn track_from_row(row: &rusqlite::Row) -> rusqlite::Result<TrackRecord> {
+    Ok(TrackRecord {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        title: row.get(2)?,
+        artist: row.get(3)?,
+        duration_ms: row.get::<_, i64>(4)? as u64,
+        album: row.get(5)?,
+        album_artist: row.get(6)?,
+        track_number: row.get(7)?,
+        disc_number: row.get(8)?,
+        year: row.get(9)?,
+        genre: row.get(10)?,
+        composer: row.get(11)?,
+        codec: row.get(12)?,
+        sample_rate: row.get(13)?,
+        bit_depth: row.get(14)?,
+        channels: row.get(15)?,
+        bitrate_kbps: row.get(16)?,
+        artwork_id: row.get(17)?,
+    })
+}
+
+/// Everything indexing reads from a file besides its path.
+#[derive(Default)]
+struct ProbedTrack {
+    title: Option<String>,
+    artist: Option<String>,
+    duration_ms: u64,
+    album: Option<String>,
+    album_artist: Option<String>,
+    track_number: Option<u32>,
+    disc_number: Option<u32>,
+    year: Option<u32>,
+    genre: Option<String>,
+    composer: Option<String>,
+    codec: Option<String>,
+    sample_rate: Option<u32>,
+    bit_depth: Option<u8>,
+    channels: Option<u8>,
+    bitrate_kbps: Option<u32>,
+    artwork: Option<CachedArtwork>,
+}
+
+/// What a scan of a library folder changed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct IndexSummary {
+    pub added: usize,
+    /// Tracks whose files (or folder cover art) changed since the last scan and were probed again
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Files whose tags or audio properties couldn't be read. They are indexed under
+    /// their file name all the same.
+    pub failed: usize,
+    /// Whether the scan was cancelled before it was done
+    pub cancelled: bool,
+}
+
+impl IndexSummary {
+    /// Whether the scan changed any track.
+    pub fn changed(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -30,34 +155,94 @@ pub struct LibraryRecord {
     pub path: String,
 }
 
+/// What is wrong with a file (or folder) a scan came across.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanIssueKind {
+    /// Couldn't be opened or read, e.g. for lack of permissions
+    Unreadable,
+    /// Could be read, but its headers or tags are malformed
+    Corrupt,
+    /// The format isn't recognized, or it is but the player can't decode it
+    UnsupportedCodec,
+    /// Reports no audio at all
+    ZeroDuration,
+}
+
+impl ScanIssueKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ScanIssueKind::Unreadable => "unreadable",
+            ScanIssueKind::Corrupt => "corrupt",
+            ScanIssueKind::UnsupportedCodec => "unsupported_codec",
+            ScanIssueKind::ZeroDuration => "zero_duration",
+        }
+    }
+
+    fn from_str(kind: &str) -> Option<Self> {
+        match kind {
+            "unreadable" => Some(ScanIssueKind::Unreadable),
+            "corrupt" => Some(ScanIssueKind::Corrupt),
+            "unsupported_codec" => Some(ScanIssueKind::UnsupportedCodec),
+            "zero_duration" => Some(ScanIssueKind::ZeroDuration),
+            _ => None,
+        }
+    }
+}
+
+/// A problem found with a file during the last scan that looked at it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanIssue {
+    pub id: i64,
+    pub library_id: i64,
+    pub path: String,
+    pub kind: ScanIssueKind,
+    /// Details for the user, e.g. the error the file couldn't be read with
+    pub message: String,
+    pub found_at_ms: u64,
+}
+
+/// Progress of a library folder scan, reported as files are found and after each batch
+/// of them is written.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanProgress {
+    /// The library folder being scanned
+    pub path: String,
+    /// Audio files found so far
+    pub seen: usize,
+    /// Files dealt with so far, unchanged ones included
+    pub processed: usize,
+    /// Files whose tags or audio properties couldn't be read
+    pub failed: usize,
+}
+
+/// Progress of a loudness analysis run, reported after each track.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoudnessProgress {
+    pub analyzed: usize,
+    pub total: usize,
+}
+
+/// Something that happened to the library in the background.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LibraryEvent {
+    LoudnessProgress(LoudnessProgress),
+    ScanProgress(ScanProgress),
+    /// A scan, the folder watcher or removing a library folder added, changed or removed tracks
+    LibraryChanged(IndexSummary),
+}
+
 impl Library {
     pub fn open(db_path: &Path) -> Result<Self> {
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        let conn = Connection::open(db_path)
+        let mut conn = Connection::open(db_path)
             .with_context(|| format!("Failed to open DB at {:?}", db_path))?;
 
         conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA foreign_keys=ON;")?;
-        conn.execute_batch("
-            CREATE TABLE IF NOT EXISTS libraries (
-                id   INTEGER PRIMARY KEY,
-                path TEXT UNIQUE NOT NULL
-            );
-            CREATE TABLE IF NOT EXISTS artists (
-                id   INTEGER PRIMARY KEY,
-                name TEXT UNIQUE NOT NULL
-            );
-            CREATE TABLE IF NOT EXISTS tracks (
-                id          INTEGER PRIMARY KEY,
-                library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
-                path        TEXT UNIQUE NOT NULL,
-                title       TEXT NOT NULL,
-                artist_id   INTEGER NOT NULL REFERENCES artists(id),
-                duration_ms INTEGER NOT NULL
-            );
-            CREATE VIRTUAL TABLE IF NOT EXISTS tracks_fts USING fts5 (title, artist, filename);
-        ")?;
+        migrations::migrate(&mut conn, db_path)?;
 
         // Ensure the sentinel artist always exists.
         conn.execute(
@@ -65,160 +250,585 @@ impl Library {
             params![UNKNOWN_ARTIST],
         )?;
 
-        Ok(Self { conn: Mutex::new(conn) })
+        let artwork_dir = db_path.parent().unwrap_or(Path::new(".")).join("artwork");
+        Ok(Self { conn: Mutex::new(conn), artwork_dir, smart_counts: Mutex::default() })
+    }
+
+    /// Walk `dir` and bring its tracks in the DB up to date. Only files that are new or
+    /// whose mtime or size changed since the last scan are probed; changed tracks keep
+    /// their id, and tracks whose files are gone are removed.
+    pub fn index_directory(&self, dir: &Path) -> Result<IndexSummary> {
+        self.scan_directory(dir, |_| {}, &AtomicBool::new(false))
     }
 
-    /// Walk `dir`, probe every audio file for tags + duration, and upsert into DB.
-    /// Re-indexes from scratch on repeated calls (stale tracks are removed first).
-    /// Returns the number of tracks inserted.
-    pub fn index_directory(&self, dir: &Path) -> Result<usize> {
+    /// `index_directory`, reporting progress along the way. Files are probed on a worker
+    /// pool and written in batches, each in its own transaction. Setting `cancel` stops
+    /// the scan before the next batch; the batches written so far are kept.
+    pub fn scan_directory(
+        &self,
+        dir: &Path,
+        mut progress: impl FnMut(ScanProgress),
+        cancel: &AtomicBool,
+    ) -> Result<IndexSummary> {
         let dir_str = dir.to_string_lossy().into_owned();
-        let conn = self.conn.lock().unwrap();
+        let (library_id, mut known) = {
+            let conn = self.conn.lock().unwrap();
+            conn.execute(
+                "INSERT OR IGNORE INTO libraries (path) VALUES (?1)",
+                params![dir_str],
+            )?;
+            let library_id: i64 = conn.query_row(
+                "SELECT id FROM libraries WHERE path = ?1",
+                params![dir_str],
+                |row| row.get(0),
+            )?;
 
-        conn.execute(
-            "INSERT OR IGNORE INTO libraries (path) VALUES (?1)",
-            params![dir_str],
-        )?;
-        let library_id: i64 = conn.query_row(
-            "SELECT id FROM libraries WHERE path = ?1",
-            params![dir_str],
-            |row| row.get(0),
+            // What the last scan saw, by path. Whatever is left over once the walk is done has vanished.
+            // A track marked for a re-probe has no mtime here, so it counts as changed.
+            let mut statement = conn.prepare(
+                "SELECT path, id, CASE WHEN needs_reprobe = 0 THEN file_mtime_ns END, file_size
+                 FROM tracks WHERE library_id = ?1",
+            )?;
+            let known: HashMap<String, StoredFile> = statement
+                .query_map(params![library_id], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?, r.get(3)?))))?
+                .filter_map(|r| r.ok())
+                .collect();
+            (library_id, known)
+        };
+
+        let mut summary = IndexSummary::default();
+        let mut report = ScanProgress { path: dir_str, ..Default::default() };
+
+        // Walk everything first, sorting out the files that need probing.
+        let mut pending: Vec<(PathBuf, Option<i64>, FileStamp)> = Vec::new();
+        let mut walk_issues = Vec::new();
+        for path in audio_files(dir) {
+            if cancel.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                return Ok(summary);
+            }
+            let path = match path {
+                Ok(path) => path,
+                Err(issue) => {
+                    walk_issues.push(issue);
+                    continue;
+                }
+            };
+            let stamp = file_stamp(&path);
+            match known.remove(path.to_string_lossy().as_ref()) {
+                Some(stored) if is_unchanged(&stored, stamp) => {
+                    summary.unchanged += 1;
+                    report.processed += 1;
+                }
+                stored => pending.push((path, stored.map(|(id, _, _)| id), stamp)),
+            }
+            report.seen += 1;
+            if report.seen.is_multiple_of(SCAN_BATCH) {
+                progress(report.clone());
+            }
+        }
+        progress(report.clone());
+
+        for batch in pending.chunks(SCAN_BATCH) {
+            if cancel.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                return Ok(summary);
+            }
+            let probed: Vec<Result<ProbedTrack, FileIssue>> =
+                batch.par_iter().map(|(path, _, _)| probe_track(path, &self.artwork_dir)).collect();
+
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            for ((path, id, stamp), probed) in batch.iter().zip(probed) {
+                record_track(&tx, library_id, path, *id, probed, *stamp, &mut summary)?;
+            }
+            tx.commit()?;
+            drop(conn);
+
+            report.processed += batch.len();
+            report.failed = summary.failed;
+            progress(report.clone());
+        }
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (id, _, _) in known.into_values() {
+            tx.execute("DELETE FROM tracks_fts WHERE rowid = ?1", params![id])?;
+            tx.execute("DELETE FROM tracks WHERE id = ?1", params![id])?;
+            summary.removed += 1;
+        }
+        // Issues of files that are gone go with them; those the walk ran into are found afresh.
+        tx.execute(
+            "DELETE FROM scan_issues WHERE library_id = ?1 AND path NOT IN (SELECT path FROM tracks)",
+            params![library_id],
         )?;
+        for (path, issue) in walk_issues {
+            replace_issues(&tx, library_id, &path, &[issue])?;
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
 
-        // Clean up stale tracks for this library (FTS first, then tracks).
-        let stale_ids: Vec<i64> = {
-            let mut statement = conn.prepare("SELECT id FROM tracks WHERE library_id = ?1")?;
-            let ids: Vec<i64> =
-            statement.query_map(params![library_id], |r| r.get(0))?
+    /// Bring the tracks at `paths` up to date after they changed on disk. Files are probed
+    /// if new or changed, directories are scanned, and paths that no longer exist take their
+    /// tracks (and those of anything under them) with them. A cover image coming, changing
+    /// or going has the tracks in its folder probed again. Paths outside every library
+    /// folder are ignored. As in `scan_directory`, files are probed without holding the
+    /// DB lock and written in batches.
+    pub fn update_paths(&self, paths: &[PathBuf]) -> Result<IndexSummary> {
+        let libraries: Vec<(i64, PathBuf)> = {
+            let conn = self.conn.lock().unwrap();
+            let mut statement = conn.prepare("SELECT id, path FROM libraries")?;
+            let rows = statement.query_map([], |r| Ok((r.get(0)?, PathBuf::from(r.get::<_, String>(1)?))))?
                 .filter_map(|r| r.ok())
                 .collect();
-            ids
+            rows
         };
-        for id in &stale_ids {
-            conn.execute("DELETE FROM tracks_fts WHERE rowid = ?1", params![id])?;
+
+        // Sort out what is gone and what is there to look at, walking without the lock.
+        let mut gone = Vec::new();
+        let mut files = Vec::new();
+        let mut walk_issues = Vec::new();
+        let mut seen = HashSet::new();
+        let mut reprobe = HashSet::new();
+        for path in paths {
+            let Some(library_id) = libraries.iter()
+                .filter(|(_, root)| path.starts_with(root))
+                .max_by_key(|(_, root)| root.components().count())
+                .map(|(id, _)| *id)
+            else {
+                continue;
+            };
+            if artwork::is_folder_art(path) {
+                // A cover image came, changed or went: the tracks next to it look again.
+                let Some(dir) = path.parent() else { continue };
+                let Ok(entries) = std::fs::read_dir(dir) else { continue };
+                for file in entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| is_audio_file(p)) {
+                    reprobe.insert(file.clone());
+                    if seen.insert(file.clone()) {
+                        files.push((library_id, file));
+                    }
+                }
+                continue;
+            }
+            if !path.exists() {
+                gone.push(path);
+                continue;
+            }
+            for file in audio_files(path) {
+                match file {
+                    Ok(file) if seen.insert(file.clone()) => files.push((library_id, file)),
+                    Ok(_) => {}
+                    Err((path, issue)) => walk_issues.push((library_id, path, issue)),
+                }
+            }
         }
-        conn.execute("DELETE FROM tracks WHERE library_id = ?1", params![library_id])?;
 
-        let mut count = 0usize;
+        let mut summary = IndexSummary::default();
+        let pending = {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            for path in gone {
+                let path_str = path.to_string_lossy();
+                let prefix = format!("{path_str}{}", std::path::MAIN_SEPARATOR);
+                let ids: Vec<i64> = {
+                    let mut statement = tx.prepare(
+                        "SELECT id FROM tracks WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                    )?;
+                    let ids = statement.query_map(params![path_str, prefix], |r| r.get(0))?
+                        .filter_map(|r| r.ok())
+                        .collect();
+                    ids
+                };
+                for id in ids {
+                    tx.execute("DELETE FROM tracks_fts WHERE rowid = ?1", params![id])?;
+                    tx.execute("DELETE FROM tracks WHERE id = ?1", params![id])?;
+                    summary.removed += 1;
+                }
+                tx.execute(
+                    "DELETE FROM scan_issues WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                    params![path_str, prefix],
+                )?;
+            }
+            for (library_id, path, issue) in walk_issues {
+                replace_issues(&tx, library_id, &path, &[issue])?;
+            }
 
-        for entry in walkdir::WalkDir::new(dir)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-        {
-            let path = entry.path();
-            let ext = path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-            if !AUDIO_EXTENSIONS.contains(&ext.as_str()) {
-                continue;
+            let mut pending: Vec<(i64, PathBuf, Option<i64>, FileStamp)> = Vec::new();
+            for (library_id, file) in files {
+                let stored: Option<StoredFile> = tx.query_row(
+                    "SELECT id, CASE WHEN needs_reprobe = 0 THEN file_mtime_ns END, file_size
+                     FROM tracks WHERE path = ?1",
+                    params![file.to_string_lossy()],
+                    |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+                ).optional()?;
+                let stamp = file_stamp(&file);
+                match stored {
+                    Some(stored) if is_unchanged(&stored, stamp) && !reprobe.contains(&file) => {
+                        summary.unchanged += 1
+                    }
+                    stored => pending.push((library_id, file, stored.map(|(id, _, _)| id), stamp)),
+                }
             }
+            tx.commit()?;
+            pending
+        };
 
-            let (title, artist, duration_ms) = probe_track(path);
+        for batch in pending.chunks(SCAN_BATCH) {
+            let probed: Vec<Result<ProbedTrack, FileIssue>> =
+                batch.par_iter().map(|(_, path, _, _)| probe_track(path, &self.artwork_dir)).collect();
 
-            let filename = path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_string();
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            for ((library_id, path, id, stamp), probed) in batch.iter().zip(probed) {
+                record_track(&tx, *library_id, path, *id, probed, *stamp, &mut summary)?;
+            }
+            tx.commit()?;
+        }
+        Ok(summary)
+    }
 
-            // Filename fallback for title.
-            let title = title.unwrap_or_else(|| {
-                if filename.is_empty() { "Unknown Track".to_string() } else { filename.clone() }
-            });
-            let artist = artist.unwrap_or_else(|| UNKNOWN_ARTIST.to_string());
+    /// Problems the scans ran into, in one library or all of them, by path.
+    pub fn scan_issues(&self, library_id: Option<i64>) -> Result<Vec<ScanIssue>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, library_id, path, kind, message, found_at_ms FROM scan_issues
+             WHERE ?1 IS NULL OR library_id = ?1
+             ORDER BY path, kind",
+        )?;
+        let issues = stmt
+            .query_map(params![library_id], |row| {
+                let Some(kind) = ScanIssueKind::from_str(&row.get::<_, String>(3)?) else { return Ok(None) };
+                Ok(Some(ScanIssue {
+                    id: row.get(0)?,
+                    library_id: row.get(1)?,
+                    path: row.get(2)?,
+                    kind,
+                    message: row.get(4)?,
+                    found_at_ms: row.get::<_, i64>(5)? as u64,
+                }))
+            })?
+            .filter_map(|r| r.ok().flatten())
+            .collect();
+        Ok(issues)
+    }
 
-            // Upsert artist, get its id.
-            conn.execute(
-                "INSERT OR IGNORE INTO artists (name) VALUES (?1)",
-                params![artist],
-            )?;
-            let artist_id: i64 = conn.query_row(
-                "SELECT id FROM artists WHERE name = ?1",
-                params![artist],
-                |r| r.get(0),
-            )?;
+    /// Tracks matching `query`, best matches first, ignoring case and accents. See `query`
+    /// for the syntax; a query that doesn't parse fails with a `QueryError`.
+    ///
+    /// When nothing matches, words are taken to be misspelled: each one also matches words
+    /// in the library a typo or two away from it, and the closest spellings come first.
+    pub fn search(&self, query: &str) -> Result<Vec<TrackRecord>> {
+        let Some(compiled) = query::compile(query, &HashMap::new())? else { return Ok(vec![]) };
+        let words = compiled.words.clone();
+        let conn = self.conn.lock().unwrap();
+        let tracks = run_search(&conn, compiled, SEARCH_LIMIT)?;
+        if !tracks.is_empty() {
+            return Ok(tracks);
+        }
 
-            let rows = conn.execute(
-                "INSERT OR IGNORE INTO tracks (library_id, path, title, artist_id, duration_ms)
-                 VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![
-                    library_id,
-                    path.to_string_lossy().as_ref(),
-                    title,
-                    artist_id,
-                    duration_ms as i64,
-                ],
-            )?;
+        let mut alternatives = HashMap::new();
+        for word in &words {
+            let terms = similar_terms(&conn, word)?;
+            if !terms.is_empty() {
+                alternatives.insert(word.clone(), terms);
+            }
+        }
+        if alternatives.is_empty() {
+            return Ok(tracks);
+        }
+        let Some(compiled) = query::compile(query, &alternatives)? else { return Ok(tracks) };
+        let mut scored: Vec<(f64, TrackRecord)> = run_search(&conn, compiled, FUZZY_CANDIDATES)?
+            .into_iter()
+            .map(|track| (similarity(&words, &track), track))
+            .collect();
+        // Stable, so equally close tracks stay in album order.
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().take(SEARCH_LIMIT).map(|(_, track)| track).collect())
+    }
+
+    /// Cover art `id` as it was found, or its JPEG thumbnail. None when there is no such
+    /// art or its file has gone from the cache.
+    pub fn artwork(&self, id: i64, thumbnail: bool) -> Result<Option<Artwork>> {
+        let row: Option<(String, String)> = {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row("SELECT hash, mime FROM artwork WHERE id = ?1", params![id], |r| Ok((r.get(0)?, r.get(1)?)))
+                .optional()?
+        };
+        let Some((hash, mime)) = row else { return Ok(None) };
+        let file = artwork::artwork_file(&self.artwork_dir, &hash, &mime, thumbnail);
+        let data = match std::fs::read(&file) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", file)),
+        };
+        let mime = if thumbnail { "image/jpeg".to_string() } else { mime };
+        Ok(Some(Artwork { mime, data }))
+    }
+
+    /// Cover art of the track at `path`, if it is indexed and has any.
+    pub fn artwork_id(&self, path: &Path) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let id = conn
+            .query_row("SELECT artwork_id FROM tracks WHERE path = ?1", params![path.to_string_lossy()], |r| r.get(0))
+            .optional()?;
+        Ok(id.flatten())
+    }
+
+    pub fn all_track_paths(&self) -> Result<Vec<PathBuf>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT path FROM tracks")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|r| r.ok())
+            .map(PathBuf::from)
+            .collect();
+        Ok(paths)
+    }
+
+    /// Measure the loudness of every track that hasn't been analyzed yet and store the result.
+    /// Files that already carry a ReplayGain track or album gain are only marked as done,
+    /// and files that fail to decode are left for the next run.
+    /// The DB lock is released while decoding, and progress is saved per track, so an
+    /// interrupted run picks up where it left off. Returns the number of tracks processed.
+    pub fn analyze_loudness(&self, mut progress: impl FnMut(LoudnessProgress)) -> Result<usize> {
+        let pending: Vec<(i64, String)> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT id, path FROM tracks WHERE loudness_analyzed = 0")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .filter_map(|r| r.ok())
+                .collect();
+            rows
+        };
 
-            if rows > 0 {
-                let track_id = conn.last_insert_rowid();
+        let total = pending.len();
+        for (i, (id, path)) in pending.into_iter().enumerate() {
+            let path = Path::new(&path);
+            // Tagged files need no measuring; None leaves a file that didn't decode as it was.
+            let measured = if has_replay_gain_tags(path) { Some(None) } else { loudness::analyze_file(path).map(Some) };
+            if let Some(measured) = measured {
+                let conn = self.conn.lock().unwrap();
                 conn.execute(
-                    "INSERT INTO tracks_fts(rowid, title, artist, filename) VALUES (?1, ?2, ?3, ?4)",
-                    params![track_id, title, artist, filename],
+                    "UPDATE tracks SET loudness_analyzed = 1, loudness_lufs = ?2, true_peak = ?3 WHERE id = ?1",
+                    params![
+                        id,
+                        measured.and_then(|m| m.integrated_lufs),
+                        measured.map(|m| m.true_peak),
+                    ],
                 )?;
-                count += 1;
             }
+
+            progress(LoudnessProgress { analyzed: i + 1, total });
         }
 
-        Ok(count)
+        Ok(total)
     }
 
-    /// Full-text search over title and artist. Supports prefix matching.
-    pub fn search(&self, query: &str) -> Result<Vec<TrackRecord>> {
-        if query.trim().is_empty() {
-            return Ok(vec![]);
-        }
+    /// ReplayGain values computed by `analyze_loudness` for the track at `path`, if any.
+    pub fn analyzed_replay_gain(&self, path: &Path) -> Result<Option<ReplayGain>> {
+        let conn = self.conn.lock().unwrap();
+        let measured: Option<(Option<f64>, Option<f64>)> = conn
+            .query_row(
+                "SELECT loudness_lufs, true_peak FROM tracks WHERE path = ?1 AND loudness_analyzed = 1",
+                params![path.to_string_lossy().as_ref()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
 
-        // Split on any non-alphanumeric character so apostrophes, dashes, etc.
-        // don't produce tokens that FTS5 never indexed (it uses the same split rule).
-        let fts_query = query
-            .split(|c: char| !c.is_alphanumeric())
-            .filter(|w| !w.is_empty())
-            .map(|w| format!("{}*", w))
-            .collect::<Vec<_>>()
-            .join(" ");
+        Ok(measured.and_then(|(lufs, peak)| {
+            let loudness = Loudness { integrated_lufs: lufs, true_peak: peak? };
+            Some(ReplayGain {
+                track_gain_db: Some(loudness.gain_db()? as f32),
+                track_peak: Some(loudness.true_peak as f32),
+                ..ReplayGain::default()
+            })
+        }))
+    }
 
+    /// Stored value of an app setting, if it has been set.
+    pub fn setting(&self, key: &str) -> Result<Option<String>> {
         let conn = self.conn.lock().unwrap();
-        let mut statement =
-            conn.prepare(
-            "SELECT t.id, t.path, t.title, a.name, t.duration_ms
-                 FROM tracks_fts f
-                 JOIN tracks t ON t.id = f.rowid
-                 JOIN artists a ON a.id = t.artist_id
-                 WHERE tracks_fts MATCH ?1
-                 ORDER BY rank
-                 LIMIT 50",
-            )?;
+        let value = conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()?;
+        Ok(value)
+    }
 
-        let tracks = statement
-            .query_map(params![fts_query], |row| {
-                Ok(TrackRecord {
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Tracks `context` stands for, in the order they should play.
+    pub fn context_tracks(&self, context: &PlayContext) -> Result<Vec<PathBuf>> {
+        let paths = match context {
+            PlayContext::Artist { name } => {
+                let conn = self.conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT t.path FROM tracks t
+                     JOIN artists a ON a.id = t.artist_id
+                     WHERE a.name = ?1
+                     ORDER BY t.title COLLATE NOCASE, t.path",
+                )?;
+                let paths = stmt.query_map(params![name], |row| row.get::<_, String>(0))?
+                    .filter_map(|r| r.ok())
+                    .map(PathBuf::from)
+                    .collect();
+                paths
+            }
+            PlayContext::Album { title, album_artist } => {
+                let conn = self.conn.lock().unwrap();
+                let mut stmt = conn.prepare(
+                    "SELECT t.path FROM tracks t
+                     JOIN albums al ON al.id = t.album_id
+                     JOIN artists aa ON aa.id = al.album_artist_id
+                     WHERE al.title = ?1 AND aa.name = ?2
+                     ORDER BY t.disc_number, t.track_number, t.path",
+                )?;
+                let paths = stmt.query_map(params![title, album_artist], |row| row.get::<_, String>(0))?
+                    .filter_map(|r| r.ok())
+                    .map(PathBuf::from)
+                    .collect();
+                paths
+            }
+            PlayContext::Search { query } => {
+                self.search(query)?.into_iter().map(|t| PathBuf::from(t.path)).collect()
+            }
+            PlayContext::Playlist { id } => self.playlist_paths(*id)?,
+            PlayContext::SmartPlaylist { id } => self.smart_playlist_paths(*id)?,
+        };
+        Ok(paths)
+    }
+
+    /// The play queue as last saved; empty when none was.
+    pub fn load_queue(&self) -> Result<PlayQueue> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, section, path, context_index FROM queue_items ORDER BY section, position",
+        )?;
+        let rows: Vec<(String, QueueItem)> = stmt
+            .query_map([], |row| {
+                let item = QueueItem {
                     id: row.get(0)?,
-                    path: row.get(1)?,
-                    title: row.get(2)?,
-                    artist: row.get(3)?,
-                    duration_ms: row.get::<_, i64>(4)? as u64,
-                })
+                    path: PathBuf::from(row.get::<_, String>(2)?),
+                    context_index: row.get::<_, Option<i64>>(3)?.map(|i| i as usize),
+                };
+                Ok((row.get(1)?, item))
             })?
             .filter_map(|r| r.ok())
             .collect();
 
-        Ok(tracks)
+        let stored: Option<(String, String, bool)> = conn
+            .query_row("SELECT context, paths, shuffled FROM queue_context WHERE id = 0", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .optional()?;
+        let context = match stored {
+            Some((context, paths, shuffled)) => StoredContext {
+                context: Some(serde_json::from_str(&context).context("Corrupt stored play context")?),
+                paths: serde_json::from_str(&paths).context("Corrupt stored play context")?,
+                shuffled,
+            },
+            None => StoredContext { context: None, paths: Vec::new(), shuffled: false },
+        };
+
+        let (mut history, mut current, mut upcoming) = (Vec::new(), None, Vec::new());
+        for (section, item) in rows {
+            match section.as_str() {
+                "history" => history.push(item),
+                "current" => current = Some(item),
+                _ => upcoming.push(item),
+            }
+        }
+        Ok(PlayQueue::from_parts(history, current, upcoming, context))
     }
 
-    pub fn all_track_paths(&self) -> Result<Vec<PathBuf>> {
+    /// Replace the stored play queue with `queue`.
+    pub fn save_queue(&self, queue: &PlayQueue) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM queue_items", [])?;
+        tx.execute("DELETE FROM queue_context", [])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO queue_items (id, section, position, path, context_index)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let sections = [
+                ("history", queue.history()),
+                ("current", queue.current().map(std::slice::from_ref).unwrap_or_default()),
+                ("upcoming", queue.upcoming()),
+            ];
+            for (section, items) in sections {
+                for (position, item) in items.iter().enumerate() {
+                    insert.execute(params![
+                        item.id,
+                        section,
+                        position as i64,
+                        item.path.to_string_lossy(),
+                        item.context_index.map(|i| i as i64),
+                    ])?;
+                }
+            }
+        }
+        let stored = queue.stored_context();
+        if let Some(context) = &stored.context {
+            tx.execute(
+                "INSERT INTO queue_context (id, context, paths, shuffled) VALUES (0, ?1, ?2, ?3)",
+                params![serde_json::to_string(context)?, serde_json::to_string(&stored.paths)?, stored.shuffled],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Equalizer presets saved by the user, by name.
+    pub fn eq_presets(&self) -> Result<Vec<EqPreset>> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT path FROM tracks")?;
-        let paths = stmt
-            .query_map([], |row| row.get::<_, String>(0))?
+        let mut stmt = conn.prepare("SELECT name, preamp_db, bands FROM eq_presets ORDER BY name")?;
+        let rows: Vec<(String, f64, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
             .filter_map(|r| r.ok())
-            .map(PathBuf::from)
             .collect();
-        Ok(paths)
+
+        let mut presets = Vec::with_capacity(rows.len());
+        for (name, preamp_db, bands) in rows {
+            let bands: Vec<EqBand> = serde_json::from_str(&bands)
+                .with_context(|| format!("Corrupt bands in EQ preset {name:?}"))?;
+            presets.push(EqPreset { name, preamp_db: preamp_db as f32, bands });
+        }
+        Ok(presets)
+    }
+
+    /// Save `preset` under its name, replacing a user preset of the same name.
+    /// Built-in preset names are reserved.
+    pub fn save_eq_preset(&self, preset: &EqPreset) -> Result<()> {
+        let name = preset.name.trim();
+        if name.is_empty() {
+            anyhow::bail!("EQ preset name is empty");
+        }
+        if eq::builtin_preset(name).is_some() {
+            anyhow::bail!("{name:?} is a built-in EQ preset");
+        }
+        let bands = serde_json::to_string(&preset.bands)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO eq_presets (name, preamp_db, bands) VALUES (?1, ?2, ?3)
+             ON CONFLICT(name) DO UPDATE SET preamp_db = excluded.preamp_db, bands = excluded.bands",
+            params![name, preset.preamp_db as f64, bands],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_eq_preset(&self, name: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM eq_presets WHERE name = ?1", params![name])?;
+        Ok(())
     }
 
     pub fn list_libraries(&self) -> Result<Vec<LibraryRecord>> {
@@ -236,7 +846,8 @@ impl Library {
         Ok(records)
     }
 
-    pub fn delete_library(&self, id: i64) -> Result<()> {
+    /// Forget library folder `id` and its tracks. The summary counts the tracks removed.
+    pub fn delete_library(&self, id: i64) -> Result<IndexSummary> {
         let conn = self.conn.lock().unwrap();
         // Clean up FTS entries before cascading delete removes the track rows.
         let stale_ids: Vec<i64> = {
@@ -250,23 +861,426 @@ impl Library {
             conn.execute("DELETE FROM tracks_fts WHERE rowid = ?1", params![track_id])?;
         }
         conn.execute("DELETE FROM libraries WHERE id = ?1", params![id])?;
-        Ok(())
+        Ok(IndexSummary { removed: stale_ids.len(), ..IndexSummary::default() })
+    }
+}
+
+/// Id, mtime and size of a track as stored by the last scan.
+type StoredFile = (i64, Option<i64>, Option<i64>);
+
+/// Kind and description of a problem with a file.
+type FileIssue = (ScanIssueKind, String);
+
+/// Audio files at or under `path`, following symlinks, along with the entries the walk
+/// couldn't read.
+fn audio_files(path: &Path) -> impl Iterator<Item = Result<PathBuf, (PathBuf, FileIssue)>> {
+    let root = path.to_path_buf();
+    walkdir::WalkDir::new(path)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(move |entry| match entry {
+            Ok(entry) if entry.file_type().is_file() => Some(Ok(entry.into_path())),
+            Ok(_) => None,
+            Err(e) => {
+                let path = e.path().map_or_else(|| root.clone(), Path::to_path_buf);
+                Some(Err((path, (ScanIssueKind::Unreadable, e.to_string()))))
+            }
+        })
+        .filter(|entry| entry.as_ref().map_or(true, |path| is_audio_file(path)))
+}
+
+/// Whether `path` has the extension of a format the library plays.
+fn is_audio_file(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    AUDIO_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// Record `issues` as everything wrong with `path`, dropping what was recorded before.
+fn replace_issues(conn: &Connection, library_id: i64, path: &Path, issues: &[FileIssue]) -> Result<()> {
+    let path = path.to_string_lossy();
+    conn.execute("DELETE FROM scan_issues WHERE path = ?1", params![path])?;
+    let found_at_ms = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    for (kind, message) in issues {
+        conn.execute(
+            "INSERT INTO scan_issues (library_id, path, kind, message, found_at_ms) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![library_id, path, kind.as_str(), message, found_at_ms],
+        )?;
     }
+    Ok(())
 }
 
-fn probe_track(path: &Path) -> (Option<String>, Option<String>, u64) {
+fn is_unchanged(stored: &StoredFile, (mtime_ns, size): FileStamp) -> bool {
+    stored.1 == mtime_ns && stored.2 == Some(size)
+}
+
+/// Write a freshly probed file to the DB along with what is wrong with it, and count the
+/// outcome. Files that couldn't be probed are stored under their file name.
+fn record_track(
+    conn: &Connection,
+    library_id: i64,
+    path: &Path,
+    id: Option<i64>,
+    probed: Result<ProbedTrack, FileIssue>,
+    (mtime_ns, size): FileStamp,
+    summary: &mut IndexSummary,
+) -> Result<()> {
+    let mut issues = Vec::new();
+    let probed = match probed {
+        Ok(probed) => {
+            if let Some(codec) = probed.codec.as_deref().filter(|c| UNPLAYABLE_CODECS.contains(c)) {
+                issues.push((ScanIssueKind::UnsupportedCodec, format!("{codec} files can't be played")));
+            }
+            if probed.duration_ms == 0 {
+                issues.push((ScanIssueKind::ZeroDuration, "The file reports a duration of zero".to_string()));
+            }
+            probed
+        }
+        Err(issue) => {
+            summary.failed += 1;
+            issues.push(issue);
+            ProbedTrack::default()
+        }
+    };
+    replace_issues(conn, library_id, path, &issues)?;
+    let stored = store_track(conn, library_id, path, id, probed, mtime_ns, size)?;
+    match id {
+        Some(_) => summary.updated += 1,
+        None if stored => summary.added += 1,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Modification time (ns since the epoch, None when unavailable) and size of a file.
+type FileStamp = (Option<i64>, i64);
+
+fn file_stamp(path: &Path) -> FileStamp {
+    let Ok(metadata) = std::fs::metadata(path) else { return (None, 0) };
+    let mtime_ns = metadata.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64);
+    (mtime_ns, metadata.len() as i64)
+}
+
+/// Write `path` to the DB: as track `id` when it is already known, as a new track
+/// otherwise. Returns false when the file already belongs to another library.
+fn store_track(
+    conn: &Connection,
+    library_id: i64,
+    path: &Path,
+    id: Option<i64>,
+    probed: ProbedTrack,
+    mtime_ns: Option<i64>,
+    size: i64,
+) -> Result<bool> {
+    let filename = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string();
+
+    // Filename fallback for title.
+    let title = probed.title.clone().unwrap_or_else(|| {
+        if filename.is_empty() { "Unknown Track".to_string() } else { filename.clone() }
+    });
+    let artist = probed.artist.clone().unwrap_or_else(|| UNKNOWN_ARTIST.to_string());
+
+    let artist_id = upsert_named(conn, "artists", &artist)?;
+    let album_artist = probed.album.as_ref().map(|_| probed.album_artist.as_deref().unwrap_or(&artist));
+    let album_id = match (&probed.album, album_artist) {
+        (Some(album), Some(album_artist)) => Some(upsert_album(conn, album, upsert_named(conn, "artists", album_artist)?)?),
+        _ => None,
+    };
+    let genre_id = probed.genre.as_deref().map(|g| upsert_named(conn, "genres", g)).transpose()?;
+    let composer_id = probed.composer.as_deref().map(|c| upsert_named(conn, "artists", c)).transpose()?;
+    let artwork_id = probed.artwork.as_ref().map(|art| upsert_artwork(conn, art)).transpose()?;
+
+    let path_str = path.to_string_lossy();
+    let values = params![
+        library_id,
+        path_str.as_ref(),
+        title,
+        artist_id,
+        probed.duration_ms as i64,
+        album_id,
+        probed.track_number,
+        probed.disc_number,
+        probed.year,
+        genre_id,
+        composer_id,
+        probed.codec,
+        probed.sample_rate,
+        probed.bit_depth,
+        probed.channels,
+        probed.bitrate_kbps,
+        mtime_ns,
+        size,
+        artwork_id,
+    ];
+    let track_id = match id {
+        Some(id) => {
+            let mut update = values.to_vec();
+            update.push(&id);
+            // If the file changed its loudness has to be measured again; a re-probe for
+            // new cover art keeps it. (The old stamps are what the CASEs compare against.)
+            conn.execute(
+                "UPDATE tracks SET
+                    library_id = ?1, path = ?2, title = ?3, artist_id = ?4, duration_ms = ?5,
+                    album_id = ?6, track_number = ?7, disc_number = ?8, year = ?9, genre_id = ?10,
+                    composer_id = ?11, codec = ?12, sample_rate = ?13, bit_depth = ?14,
+                    channels = ?15, bitrate_kbps = ?16, file_mtime_ns = ?17, file_size = ?18,
+                    artwork_id = ?19, needs_reprobe = 0,
+                    loudness_analyzed = CASE WHEN file_mtime_ns IS ?17 AND file_size IS ?18
+                        THEN loudness_analyzed ELSE 0 END,
+                    loudness_lufs = CASE WHEN file_mtime_ns IS ?17 AND file_size IS ?18
+                        THEN loudness_lufs END,
+                    true_peak = CASE WHEN file_mtime_ns IS ?17 AND file_size IS ?18
+                        THEN true_peak END
+                 WHERE id = ?20",
+                update.as_slice(),
+            )?;
+            conn.execute("DELETE FROM tracks_fts WHERE rowid = ?1", params![id])?;
+            id
+        }
+        None => {
+            let rows = conn.execute(
+                "INSERT OR IGNORE INTO tracks (
+                    library_id, path, title, artist_id, duration_ms, album_id, track_number,
+                    disc_number, year, genre_id, composer_id, codec, sample_rate, bit_depth,
+                    channels, bitrate_kbps, file_mtime_ns, file_size, artwork_id, added_at_ms
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                    CAST(unixepoch('subsec') * 1000 AS INTEGER))",
+                values,
+            )?;
+            if rows == 0 {
+                return Ok(false);
+            }
+            conn.last_insert_rowid()
+        }
+    };
+    conn.execute(
+        "INSERT INTO tracks_fts(rowid, title, artist, album, album_artist, genre, composer, filename)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![track_id, title, artist, probed.album, album_artist, probed.genre, probed.composer, filename],
+    )?;
+    Ok(true)
+}
+
+/// Up to `limit` tracks matching `compiled`. Hits of its text terms come first, best
+/// first; the rest (say, for `year:1994`) follow in album order.
+fn run_search(conn: &Connection, compiled: query::CompiledQuery, limit: usize) -> Result<Vec<TrackRecord>> {
+    let mut params = compiled.params;
+    let (rank_join, rank_order) = match compiled.rank {
+        Some(rank) => {
+            params.push(rank.into());
+            let join = format!(
+                "LEFT JOIN (SELECT rowid, rank FROM tracks_fts WHERE tracks_fts MATCH ?{}) r ON r.rowid = t.id",
+                params.len()
+            );
+            (join, "r.rank IS NULL, r.rank,")
+        }
+        None => (String::new(), ""),
+    };
+    let mut statement = conn.prepare(&format!(
+        "{TRACK_SELECT}
+         {rank_join}
+         WHERE {}
+         ORDER BY {rank_order} lower(a.name), lower(al.title), t.disc_number, t.track_number, t.path
+         LIMIT {limit}",
+        compiled.predicate
+    ))?;
+
+    let tracks = statement
+        .query_map(rusqlite::params_from_iter(params), track_from_row)?
+        .filter_map(|r| r.ok())
+        .collect();
+    Ok(tracks)
+}
+
+/// Words in the search index within a typo of `word`, or two once it has six letters;
+/// none for words too short to tell a typo from another word. Only words starting with
+/// the same letter are looked at: a typo in the first letter is rare, and this way the
+/// index's range of terms narrows them down rather than all of them being compared.
+fn similar_terms(conn: &Connection, word: &str) -> Result<Vec<String>> {
+    // The index has its words without accents.
+    let word = fold_accents(word);
+    let length = word.chars().count();
+    let max_edits = match length {
+        0..=2 => return Ok(vec![]),
+        3..=5 => 1,
+        _ => 2,
+    };
+    let Some(first) = word.chars().next() else { return Ok(vec![]) };
+    let after = char::from_u32(first as u32 + 1).unwrap_or(char::MAX);
+    let mut statement = conn.prepare_cached(
+        "SELECT term FROM tracks_fts_terms WHERE term >= ?1 AND term < ?2 AND length(term) BETWEEN ?3 AND ?4",
+    )?;
+    let terms = statement
+        .query_map(
+            params![first.to_string(), after.to_string(), length - max_edits, length + max_edits],
+            |r| r.get::<_, String>(0),
+        )?
+        .filter_map(|r| r.ok())
+        .filter(|term| strsim::damerau_levenshtein(&word, term) <= max_edits)
+        .collect();
+    Ok(terms)
+}
+
+/// `word` in lower case without accents, as the search index's tokenizer has its words:
+/// "Café" is "cafe" and "Sơn" is "son".
+fn fold_accents(word: &str) -> String {
+    word.to_lowercase().nfd().filter(|&c| !is_combining_mark(c)).collect()
+}
+
+/// How closely the title, artist and album of `track` spell `words`, accents aside: for
+/// each word, its similarity (0 to 1) to the closest of their words, summed. A word
+/// that starts one of theirs counts as spelled right.
+fn similarity(words: &[String], track: &TrackRecord) -> f64 {
+    let fields = [Some(&track.title), Some(&track.artist), track.album.as_ref(), track.album_artist.as_ref()];
+    let theirs: Vec<String> = fields
+        .into_iter()
+        .flatten()
+        .flat_map(|field| {
+            fold_accents(field).split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(String::from).collect::<Vec<_>>()
+        })
+        .collect();
+    words
+        .iter()
+        .map(|word| {
+            let word = fold_accents(word);
+            theirs
+                .iter()
+                .map(|their| {
+                    if their.starts_with(word.as_str()) {
+                        1.0
+                    } else {
+                        strsim::normalized_damerau_levenshtein(&word, their)
+                    }
+                })
+                .fold(0.0, f64::max)
+        })
+        .sum()
+}
+
+/// Id of the row in `table` (`artists` or `genres`) called `name`, inserting it if needed.
+fn upsert_named(conn: &Connection, table: &str, name: &str) -> Result<i64> {
+    conn.execute(&format!("INSERT OR IGNORE INTO {table} (name) VALUES (?1)"), params![name])?;
+    let id = conn.query_row(&format!("SELECT id FROM {table} WHERE name = ?1"), params![name], |r| r.get(0))?;
+    Ok(id)
+}
+
+fn upsert_album(conn: &Connection, title: &str, album_artist_id: i64) -> Result<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO albums (title, album_artist_id) VALUES (?1, ?2)",
+        params![title, album_artist_id],
+    )?;
+    let id = conn.query_row(
+        "SELECT id FROM albums WHERE title = ?1 AND album_artist_id = ?2",
+        params![title, album_artist_id],
+        |r| r.get(0),
+    )?;
+    Ok(id)
+}
+
+fn upsert_artwork(conn: &Connection, art: &CachedArtwork) -> Result<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO artwork (hash, mime) VALUES (?1, ?2)",
+        params![art.hash, art.mime],
+    )?;
+    let id = conn.query_row("SELECT id FROM artwork WHERE hash = ?1", params![art.hash], |r| r.get(0))?;
+    Ok(id)
+}
+
+fn has_replay_gain_tags(path: &Path) -> bool {
+    use lofty::prelude::*;
+    get_tagged_file(path)
+        .and_then(|tagged| {
+            let tag = tagged.primary_tag().or_else(|| tagged.first_tag())?;
+            let gain = ReplayGain::from_tag(tag);
+            gain.track_gain_db.or(gain.album_gain_db)
+        })
+        .is_some()
+}
+
+/// Read tags and audio properties, or what kept them from being read. Cover art found
+/// along the way is stored in the cache at `artwork_dir`.
+fn probe_track(path: &Path, artwork_dir: &Path) -> Result<ProbedTrack, FileIssue> {
+    use lofty::error::ErrorKind;
     use lofty::prelude::*;
 
-    let Some(tagged) = 
-        get_tagged_file(path)
-    else {
-        return (None, None, 0);
+    // I/O errors past opening the file mostly come from reading beyond a truncated one.
+    let io_issue = |e: &std::io::Error| match e.kind() {
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::NotFound => ScanIssueKind::Unreadable,
+        _ => ScanIssueKind::Corrupt,
     };
+    let probe = lofty::probe::Probe::open(path)
+        .map_err(|e| (ScanIssueKind::Unreadable, e.to_string()))?
+        .guess_file_type()
+        .map_err(|e| (io_issue(&e), e.to_string()))?;
+    if probe.file_type().is_none() {
+        return Err((ScanIssueKind::UnsupportedCodec, "Unrecognized file format".to_string()));
+    }
+    let tagged = probe.read().map_err(|e| {
+        let kind = match e.kind() {
+            ErrorKind::Io(io) => io_issue(io),
+            ErrorKind::UnknownFormat => ScanIssueKind::UnsupportedCodec,
+            _ => ScanIssueKind::Corrupt,
+        };
+        (kind, e.to_string())
+    })?;
 
-    let duration_ms = tagged.properties().duration().as_millis() as u64;
+    let properties = tagged.properties();
     let tag = tagged.primary_tag().or_else(|| tagged.first_tag());
-    let title = tag.and_then(|t| t.title().as_deref().map(String::from));
-    let artist = tag.and_then(|t| t.artist().as_deref().map(String::from));
+    let text = |key: ItemKey| tag.and_then(|t| t.get_string(&key)).map(String::from);
+    // Art that can't be decoded is left out rather than failing the track.
+    let artwork = artwork::find_artwork(path, tag)
+        .and_then(|data| artwork::cache_artwork(artwork_dir, &data).ok());
+
+    Ok(ProbedTrack {
+        title: tag.and_then(|t| t.title().map(String::from)),
+        artist: tag.and_then(|t| t.artist().map(String::from)),
+        duration_ms: properties.duration().as_millis() as u64,
+        album: tag.and_then(|t| t.album().map(String::from)),
+        album_artist: text(ItemKey::AlbumArtist),
+        track_number: tag.and_then(|t| t.track()),
+        disc_number: tag.and_then(|t| t.disk()),
+        year: tag.and_then(|t| t.year()),
+        genre: tag.and_then(|t| t.genre().map(String::from)),
+        composer: text(ItemKey::Composer),
+        codec: codec_name(tagged.file_type(), properties.bit_depth()),
+        sample_rate: properties.sample_rate(),
+        bit_depth: properties.bit_depth(),
+        channels: properties.channels(),
+        bitrate_kbps: properties.audio_bitrate(),
+        artwork,
+    })
+}
 
-    (title, artist, duration_ms)
+/// Display name of the codec a file of `file_type` is encoded with.
+fn codec_name(file_type: lofty::file::FileType, bit_depth: Option<u8>) -> Option<String> {
+    use lofty::file::FileType;
+    let name = match file_type {
+        FileType::Aac => "AAC",
+        FileType::Aiff | FileType::Wav => "PCM",
+        FileType::Ape => "Monkey's Audio",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MP3",
+        // Only the lossless codec (ALAC) reports a bit depth in an MP4 container.
+        FileType::Mp4 if bit_depth.is_some() => "ALAC",
+        FileType::Mp4 => "AAC",
+        FileType::Mpc => "Musepack",
+        FileType::Opus => "Opus",
+        FileType::Vorbis => "Vorbis",
+        FileType::Speex => "Speex",
+        FileType::WavPack => "WavPack",
+        FileType::Custom(name) => name,
+        _ => return None,
+    };
+    Some(name.to_string())
 }
@@ -0,0 +1,247 @@
+//! EBU R128 / ITU-R BS.1770 loudness measurement, used to fill in ReplayGain values for
+//! files that don't carry them in their tags.
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// ReplayGain 2.0 reference level. Gain = reference - integrated loudness.
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+/// Blocks quieter than this never count towards the integrated loudness.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the absolute-gated loudness are dropped too.
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Oversampling factor of the true-peak meter.
+const OVERSAMPLING: usize = 4;
+/// FIR taps per polyphase branch of the true-peak interpolator.
+const TAPS_PER_PHASE: usize = 12;
+
+/// Result of analyzing one file.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Loudness {
+    /// Gated integrated loudness in LUFS; None for digital silence.
+    pub integrated_lufs: Option<f64>,
+    /// Linear true peak, 1.0 = full scale.
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// ReplayGain track gain in dB derived from the integrated loudness.
+    pub fn gain_db(&self) -> Option<f64> {
+        self.integrated_lufs.map(|lufs| REFERENCE_LUFS - lufs)
+    }
+}
+
+/// Decode `path` with symphonia and measure its loudness. None when the file can't be decoded.
+pub fn analyze_file(path: &Path) -> Option<Loudness> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+
+    let mut probed = crate::probe_format(path)?;
+    let track = probed.format.default_track()?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .ok()?;
+
+    let mut meter: Option<LoudnessMeter> = None;
+    let mut buf: Option<SampleBuffer<f32>> = None;
+    while let Ok(packet) = probed.format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupt frames, like the playback decoder does.
+            Err(Error::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+
+        let spec = *decoded.spec();
+        let meter = meter.get_or_insert_with(|| LoudnessMeter::new(spec.rate, spec.channels.count()));
+        if spec.rate != meter.sample_rate || spec.channels.count() != meter.channels {
+            // Mid-stream format changes are rare enough to treat as undecodable.
+            return None;
+        }
+        if buf.as_ref().is_none_or(|b| b.capacity() < decoded.capacity()) {
+            buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buf = buf.as_mut().unwrap();
+        buf.copy_interleaved_ref(decoded);
+        meter.push(buf.samples());
+    }
+
+    meter.map(LoudnessMeter::finish)
+}
+
+/// Second-order IIR section in direct form I.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    #[inline]
+    fn process(&mut self, x0: f64) -> f64 {
+        let y0 = self.b[0] * x0 + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0] - self.a[2] * self.y[1];
+        self.x = [x0, self.x[0]];
+        self.y = [y0, self.y[0]];
+        y0
+    }
+}
+
+/// The two K-weighting stages of BS.1770 (high shelf + high pass), derived for `rate`.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let fs = rate as f64;
+
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, high_pass]
+}
+
+/// BS.1770 channel weights, assuming the usual L R C LFE Ls Rs ordering.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (c, 3) if c >= 5 => 0.0,
+        (c, 4 | 5) if c >= 5 => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Windowed-sinc low-pass at the original Nyquist, split into polyphase branches.
+fn interpolation_filter() -> Vec<[f64; TAPS_PER_PHASE]> {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (len - 1) as f64 / 2.0;
+    let mut phases = vec![[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+    for n in 0..len {
+        let t = (n as f64 - center) / OVERSAMPLING as f64;
+        let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+        let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / (len - 1) as f64).cos();
+        phases[n % OVERSAMPLING][n / OVERSAMPLING] = sinc * window;
+    }
+    phases
+}
+
+/// Accumulates interleaved samples into 100 ms K-weighted energy segments and a true peak.
+struct LoudnessMeter {
+    sample_rate: u32,
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames per 100 ms segment.
+    segment_len: usize,
+    /// Weighted energy of the segment being filled.
+    segment_energy: f64,
+    segment_frames: usize,
+    /// Mean weighted power of each completed segment.
+    segments: Vec<f64>,
+    phases: Vec<[f64; TAPS_PER_PHASE]>,
+    /// Recent input samples per channel, newest first, for the interpolator.
+    history: Vec<[f64; TAPS_PER_PHASE]>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            sample_rate,
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            segment_len: (sample_rate as usize / 10).max(1),
+            segment_energy: 0.0,
+            segment_frames: 0,
+            segments: Vec::new(),
+            phases: interpolation_filter(),
+            history: vec![[0.0; TAPS_PER_PHASE]; channels],
+            peak: 0.0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        // Above 96 kHz the signal is already oversampled enough for a sample peak.
+        let oversample = self.sample_rate < 96_000;
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, &sample) in frame.iter().enumerate() {
+                let x = sample as f64;
+                let [shelf, high_pass] = &mut self.filters[c];
+                let y = high_pass.process(shelf.process(x));
+                self.segment_energy += self.weights[c] * y * y;
+
+                self.peak = self.peak.max(x.abs());
+                if oversample {
+                    let history = &mut self.history[c];
+                    history.copy_within(0..TAPS_PER_PHASE - 1, 1);
+                    history[0] = x;
+                    for phase in &self.phases {
+                        let y: f64 = phase.iter().zip(history.iter()).map(|(h, x)| h * x).sum();
+                        self.peak = self.peak.max(y.abs());
+                    }
+                }
+            }
+
+            self.segment_frames += 1;
+            if self.segment_frames == self.segment_len {
+                self.segments.push(self.segment_energy / self.segment_len as f64);
+                self.segment_energy = 0.0;
+                self.segment_frames = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> Loudness {
+        // 400 ms gating blocks overlapping by 75%, i.e. four consecutive segments.
+        let blocks: Vec<f64> = self.segments
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.0)
+            .collect();
+        let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+        let mean = |powers: &[f64]| powers.iter().sum::<f64>() / powers.len() as f64;
+
+        let above_absolute: Vec<f64> = blocks.into_iter()
+            .filter(|&p| p > 0.0 && loudness(p) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        let integrated_lufs = if above_absolute.is_empty() {
+            None
+        } else {
+            let relative_gate = loudness(mean(&above_absolute)) + RELATIVE_GATE_LU;
+            let gated: Vec<f64> = above_absolute.into_iter()
+                .filter(|&p| loudness(p) > relative_gate)
+                .collect();
+            (!gated.is_empty()).then(|| loudness(mean(&gated)))
+        };
+
+        Loudness { integrated_lufs, true_peak: self.peak }
+    }
+}
@@ -0,0 +1,329 @@
+//! Versioned upgrades of the library database.
+//!
+//! The schema version lives in `PRAGMA user_version`. `migrate` applies every
+//! step past it in order, each in its own transaction that also bumps the
+//! version, so a failed step leaves the database at the last good version.
+//! An existing database is copied aside before it is upgraded.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+/// One upgrade, from the version before it to the next.
+struct Migration {
+    description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+/// Every schema change, oldest first. Step `i` upgrades version `i` to `i + 1`;
+/// append new steps, never edit shipped ones.
+///
+/// Databases from before versioning report version 0 whatever their shape, so
+/// the first six steps tolerate finding their changes already made. Later steps
+/// can rely on the version they start from.
+const MIGRATIONS: &[Migration] = &[
+    Migration { description: "libraries, artists and tracks", apply: create_initial_schema },
+    Migration { description: "loudness analysis", apply: add_loudness_columns },
+    Migration { description: "settings", apply: create_settings },
+    Migration { description: "equalizer presets", apply: create_eq_presets },
+    Migration { description: "play queue and context", apply: create_queue },
+    Migration { description: "albums, genres and format metadata", apply: add_album_metadata },
+    Migration { description: "file stamps for incremental scans", apply: add_file_stamps },
+    Migration { description: "scan issues", apply: create_scan_issues },
+    Migration { description: "cover art", apply: create_artwork },
+    Migration { description: "search by album, genre and composer", apply: add_search_fields },
+    Migration { description: "accent-insensitive search", apply: fold_search_diacritics },
+    Migration { description: "playlists", apply: create_playlists },
+    Migration { description: "play counts and smart playlists", apply: create_smart_playlists },
+    Migration { description: "browse indexes", apply: create_browse_indexes },
+    Migration { description: "re-probe for cover art", apply: add_reprobe_marker },
+];
+
+/// The version a database is at once `migrate` is done with it.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Where the copy of `db_path` taken before upgrading it from `version` goes.
+pub fn backup_path(db_path: &Path, version: u32) -> PathBuf {
+    let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{version}.bak"));
+    db_path.with_file_name(name)
+}
+
+/// Bring the database at `db_path` (open as `conn`) up to `SCHEMA_VERSION`.
+pub(crate) fn migrate(conn: &mut Connection, db_path: &Path) -> Result<()> {
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Database is at schema version {version}, newer than this build supports ({SCHEMA_VERSION})"
+        );
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let empty: bool = conn.query_row("SELECT NOT EXISTS (SELECT 1 FROM sqlite_master)", [], |row| row.get(0))?;
+    if !empty {
+        let backup = backup_path(db_path, version);
+        if backup.exists() {
+            std::fs::remove_file(&backup)
+                .with_context(|| format!("Failed to replace old backup {:?}", backup))?;
+        }
+        conn.execute("VACUUM INTO ?1", params![backup.to_string_lossy()])
+            .with_context(|| format!("Failed to back up the database to {:?}", backup))?;
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = from + 1;
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx)
+            .and_then(|()| Ok(tx.execute_batch(&format!("PRAGMA user_version = {to}"))?))
+            .with_context(|| format!("Failed to upgrade the database to version {to} ({})", migration.description))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn create_initial_schema(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS libraries (
+            id   INTEGER PRIMARY KEY,
+            path TEXT UNIQUE NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS artists (
+            id   INTEGER PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS tracks (
+            id          INTEGER PRIMARY KEY,
+            library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+            path        TEXT UNIQUE NOT NULL,
+            title       TEXT NOT NULL,
+            artist_id   INTEGER NOT NULL REFERENCES artists(id),
+            duration_ms INTEGER NOT NULL
+        );
+        CREATE VIRTUAL TABLE IF NOT EXISTS tracks_fts USING fts5 (title, artist, filename);
+    ")?;
+    Ok(())
+}
+
+fn add_loudness_columns(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "tracks", "loudness_analyzed", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "tracks", "loudness_lufs", "REAL")?;
+    add_column_if_missing(conn, "tracks", "true_peak", "REAL")?;
+    Ok(())
+}
+
+fn create_settings(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS settings (
+            key   TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+    ")?;
+    Ok(())
+}
+
+fn create_eq_presets(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS eq_presets (
+            name      TEXT PRIMARY KEY,
+            preamp_db REAL NOT NULL,
+            bands     TEXT NOT NULL
+        );
+    ")?;
+    Ok(())
+}
+
+fn create_queue(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS queue_items (
+            id       INTEGER PRIMARY KEY,
+            section  TEXT NOT NULL CHECK (section IN ('history', 'current', 'upcoming')),
+            position INTEGER NOT NULL,
+            path     TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS queue_context (
+            id       INTEGER PRIMARY KEY CHECK (id = 0),
+            context  TEXT NOT NULL,
+            paths    TEXT NOT NULL,
+            shuffled INTEGER NOT NULL
+        );
+    ")?;
+    add_column_if_missing(conn, "queue_items", "context_index", "INTEGER")?;
+    Ok(())
+}
+
+fn add_album_metadata(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE IF NOT EXISTS albums (
+            id              INTEGER PRIMARY KEY,
+            title           TEXT NOT NULL,
+            album_artist_id INTEGER NOT NULL REFERENCES artists(id),
+            UNIQUE (title, album_artist_id)
+        );
+        CREATE TABLE IF NOT EXISTS genres (
+            id   INTEGER PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL
+        );
+    ")?;
+    for (column, decl) in [
+        ("album_id", "INTEGER REFERENCES albums(id)"),
+        ("track_number", "INTEGER"),
+        ("disc_number", "INTEGER"),
+        ("year", "INTEGER"),
+        ("genre_id", "INTEGER REFERENCES genres(id)"),
+        ("composer_id", "INTEGER REFERENCES artists(id)"),
+        ("codec", "TEXT"),
+        ("sample_rate", "INTEGER"),
+        ("bit_depth", "INTEGER"),
+        ("channels", "INTEGER"),
+        ("bitrate_kbps", "INTEGER"),
+    ] {
+        add_column_if_missing(conn, "tracks", column, decl)?;
+    }
+    Ok(())
+}
+
+fn add_file_stamps(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        ALTER TABLE tracks ADD COLUMN file_mtime_ns INTEGER;
+        ALTER TABLE tracks ADD COLUMN file_size     INTEGER;
+    ")?;
+    Ok(())
+}
+
+fn create_scan_issues(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE scan_issues (
+            id          INTEGER PRIMARY KEY,
+            library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+            path        TEXT NOT NULL,
+            kind        TEXT NOT NULL,
+            message     TEXT NOT NULL,
+            found_at_ms INTEGER NOT NULL
+        );
+        CREATE INDEX scan_issues_path ON scan_issues (path);
+    ")?;
+    Ok(())
+}
+
+fn create_artwork(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE artwork (
+            id   INTEGER PRIMARY KEY,
+            hash TEXT UNIQUE NOT NULL,
+            mime TEXT NOT NULL
+        );
+        ALTER TABLE tracks ADD COLUMN artwork_id INTEGER REFERENCES artwork(id);
+    ")?;
+    Ok(())
+}
+
+fn add_search_fields(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE VIRTUAL TABLE search_index USING fts5 (
+            title, artist, album, album_artist, genre, composer, filename
+        );
+        INSERT INTO search_index (rowid, title, artist, album, album_artist, genre, composer, filename)
+            SELECT t.id, t.title, a.name, al.title, aa.name, g.name, c.name, f.filename
+            FROM tracks t
+            JOIN tracks_fts f ON f.rowid = t.id
+            JOIN artists a ON a.id = t.artist_id
+            LEFT JOIN albums al ON al.id = t.album_id
+            LEFT JOIN artists aa ON aa.id = al.album_artist_id
+            LEFT JOIN genres g ON g.id = t.genre_id
+            LEFT JOIN artists c ON c.id = t.composer_id;
+        DROP TABLE tracks_fts;
+        ALTER TABLE search_index RENAME TO tracks_fts;
+    ")?;
+    Ok(())
+}
+
+/// Rebuild the search index with a tokenizer that drops every accent (the default one
+/// keeps some), and add a view of the words in it to look misspellings up in.
+fn fold_search_diacritics(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE VIRTUAL TABLE search_index USING fts5 (
+            title, artist, album, album_artist, genre, composer, filename,
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO search_index (rowid, title, artist, album, album_artist, genre, composer, filename)
+            SELECT rowid, title, artist, album, album_artist, genre, composer, filename FROM tracks_fts;
+        DROP TABLE tracks_fts;
+        ALTER TABLE search_index RENAME TO tracks_fts;
+        CREATE VIRTUAL TABLE tracks_fts_terms USING fts5vocab (tracks_fts, 'row');
+    ")?;
+    Ok(())
+}
+
+fn create_playlists(conn: &Connection) -> Result<()> {
+    conn.execute_batch("
+        CREATE TABLE playlists (
+            id            INTEGER PRIMARY KEY,
+            name          TEXT NOT NULL,
+            created_at_ms INTEGER NOT NULL,
+            updated_at_ms INTEGER NOT NULL
+        );
+        CREATE TABLE playlist_items (
+            id          INTEGER PRIMARY KEY,
+            playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+            position    INTEGER NOT NULL,
+            path        TEXT NOT NULL
+        );
+        CREATE INDEX playlist_items_order ON playlist_items (playlist_id, position);
+    ")?;
+    Ok(())
+}
+
+fn create_smart_playlists(conn: &Connection) -> Result<()> {
+    // When a track was added isn't known for what is already indexed; the file's
+    // modification time is the closest guess.
+    conn.execute_batch("
+        ALTER TABLE tracks ADD COLUMN added_at_ms INTEGER;
+        ALTER TABLE tracks ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE tracks ADD COLUMN last_played_at_ms INTEGER;
+        UPDATE tracks SET added_at_ms = IFNULL(file_mtime_ns / 1000000, CAST(unixepoch('subsec') * 1000 AS INTEGER));
+        CREATE TABLE smart_playlists (
+            id            INTEGER PRIMARY KEY,
+            name          TEXT NOT NULL,
+            rules         TEXT NOT NULL,
+            created_at_ms INTEGER NOT NULL,
+            updated_at_ms INTEGER NOT NULL
+        );
+    ")?;
+    Ok(())
+}
+
+fn create_browse_indexes(conn: &Connection) -> Result<()> {
+    // Browsing an artist, album, genre or year, and the counts next to each, look
+    // tracks up by these.
+    conn.execute_batch("
+        CREATE INDEX tracks_artist ON tracks (artist_id);
+        CREATE INDEX tracks_album ON tracks (album_id);
+        CREATE INDEX tracks_genre ON tracks (genre_id);
+        CREATE INDEX tracks_year ON tracks (year);
+    ")?;
+    Ok(())
+}
+
+fn add_reprobe_marker(conn: &Connection) -> Result<()> {
+    // Incremental scans skip files that haven't changed, so tracks indexed before there
+    // was cover art were never looked at for it. Marked tracks are probed by the next
+    // scan whatever their stamps say; the stamps stay, so their loudness is kept.
+    conn.execute_batch("
+        ALTER TABLE tracks ADD COLUMN needs_reprobe INTEGER NOT NULL DEFAULT 0;
+        UPDATE tracks SET needs_reprobe = 1 WHERE artwork_id IS NULL;
+    ")?;
+    Ok(())
+}
+
+/// `ALTER TABLE .. ADD COLUMN` unless `table` already has `column`.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!("SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?1"))?
+        .exists(params![column])?;
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use rodio::cpal::traits::HostTrait;
+use rodio::dynamic_mixer::{self, DynamicMixerController};
+use rodio::{DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Format every software output mixes down to.
+const MIX_CHANNELS: u16 = 2;
+const MIX_SAMPLE_RATE: u32 = 44_100;
+/// Frames pulled from the mixer per iteration of a software output thread (10 ms).
+const MIX_BLOCK_FRAMES: usize = MIX_SAMPLE_RATE as usize / 100;
+/// How often a `DeviceOutput` on a picked device checks that the device is still present.
+const PRESENCE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where the player's sinks send their samples.
+pub trait AudioOutput {
+    /// Create a sink that plays into this output.
+    fn new_sink(&self) -> Result<Sink>;
+
+    /// Name of the hardware device behind this output, if it is one the user picked.
+    fn device_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// True once the device behind this output has gone away, e.g. unplugged.
+    fn device_lost(&self) -> bool {
+        false
+    }
+}
+
+/// Names of the output devices of the default audio host.
+pub fn output_devices() -> Result<Vec<String>> {
+    let devices = rodio::cpal::default_host()
+        .output_devices()
+        .context("Failed to enumerate output devices")?
+        .filter_map(|d| d.name().ok())
+        .collect();
+    Ok(devices)
+}
+
+/// True when an output device called `name` is currently present.
+fn device_present(name: &str) -> bool {
+    output_devices()
+        .map(|devices| devices.iter().any(|d| d == name))
+        .unwrap_or(false)
+}
+
+/// A sound card, through rodio and cpal.
+pub struct DeviceOutput {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    name: Option<String>,
+    /// Set by the presence check once the device is gone
+    lost: Arc<AtomicBool>,
+    /// Dropping it stops the presence check
+    _watch: Option<mpsc::Sender<()>>,
+}
+
+impl DeviceOutput {
+    /// Open a stream on the output device called `name`, or the default device when None.
+    pub fn open(name: Option<&str>) -> Result<Self> {
+        let (stream, handle) = match name {
+            None => OutputStream::try_default().context("No default output device available")?,
+            Some(name) => {
+                let device = rodio::cpal::default_host()
+                    .output_devices()
+                    .context("Failed to enumerate output devices")?
+                    .find(|d| d.name().is_ok_and(|n| n == name))
+                    .with_context(|| format!("Output device {name:?} not found"))?;
+                OutputStream::try_from_device(&device)
+                    .with_context(|| format!("Failed to open output device {name:?}"))?
+            }
+        };
+        let lost = Arc::new(AtomicBool::new(false));
+        let watch = name.map(|name| watch_device(name.to_string(), Arc::clone(&lost)));
+        Ok(Self { _stream: stream, handle, name: name.map(String::from), lost, _watch: watch })
+    }
+}
+
+/// Check every `PRESENCE_CHECK_INTERVAL` that device `name` is still there, setting `lost`
+/// once it isn't, until the returned sender is dropped. Enumerating devices can block
+/// for a good while on some hosts, so it is kept off the player's thread.
+fn watch_device(name: String, lost: Arc<AtomicBool>) -> mpsc::Sender<()> {
+    let (stop, stopped) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(PRESENCE_CHECK_INTERVAL) {
+            if !device_present(&name) {
+                lost.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    });
+    stop
+}
+
+impl AudioOutput for DeviceOutput {
+    fn new_sink(&self) -> Result<Sink> {
+        Sink::try_new(&self.handle).context("Failed to create sink")
+    }
+
+    fn device_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn device_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+/// Mixes every sink into one stream that a background thread drains at a fixed pace.
+struct SoftwareOutput {
+    mixer: Arc<DynamicMixerController<f32>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SoftwareOutput {
+    /// Start draining the mix at `speed` times real time, handing each block to `consume`.
+    /// Non-finite or non-positive speeds drain as fast as possible.
+    fn spawn(speed: f32, mut consume: impl FnMut(&[f32]) + Send + 'static) -> Self {
+        let (mixer, mut mix) = dynamic_mixer::mixer::<f32>(MIX_CHANNELS, MIX_SAMPLE_RATE);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let thread = std::thread::spawn(move || {
+            let block_time = Duration::from_secs_f64(MIX_BLOCK_FRAMES as f64 / MIX_SAMPLE_RATE as f64);
+            let block_time = if speed.is_finite() && speed > 0.0 { Some(block_time.div_f32(speed)) } else { None };
+            let mut block = Vec::with_capacity(MIX_BLOCK_FRAMES * MIX_CHANNELS as usize);
+            let mut deadline = Instant::now();
+
+            while !stop_flag.load(Ordering::Relaxed) {
+                block.clear();
+                block.extend(mix.by_ref().take(MIX_BLOCK_FRAMES * MIX_CHANNELS as usize));
+                if block.is_empty() {
+                    // No sinks at the moment; idle for a block's worth of real time.
+                    std::thread::sleep(Duration::from_millis(10));
+                    deadline = Instant::now();
+                    continue;
+                }
+                consume(&block);
+
+                if let Some(block_time) = block_time {
+                    deadline += block_time;
+                    let now = Instant::now();
+                    if deadline > now {
+                        std::thread::sleep(deadline - now);
+                    } else {
+                        deadline = now;
+                    }
+                }
+            }
+        });
+
+        Self { mixer, stop, thread: Some(thread) }
+    }
+
+    fn new_sink(&self) -> Sink {
+        let (sink, queue) = Sink::new_idle();
+        self.mixer.add(queue);
+        sink
+    }
+}
+
+impl Drop for SoftwareOutput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Discards all samples. Keeps the player running on machines without a sound card,
+/// and at a `speed` above 1.0 plays through tracks faster than real time.
+pub struct NullOutput {
+    inner: SoftwareOutput,
+}
+
+impl NullOutput {
+    /// `speed` is a multiple of real time; non-finite values consume as fast as possible,
+    /// which also spins through silence while idle, so it is meant for tests.
+    pub fn new(speed: f32) -> Self {
+        Self { inner: SoftwareOutput::spawn(speed, |_| {}) }
+    }
+}
+
+impl AudioOutput for NullOutput {
+    fn new_sink(&self) -> Result<Sink> {
+        Ok(self.inner.new_sink())
+    }
+}
+
+/// Writes everything the player outputs to a 16-bit stereo WAV file.
+pub struct WavOutput {
+    inner: SoftwareOutput,
+}
+
+impl WavOutput {
+    /// Create (or truncate) the WAV file at `path`. `speed` works as in `NullOutput::new`.
+    /// The file is finalized when the output is dropped.
+    pub fn create(path: &Path, speed: f32) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: MIX_CHANNELS,
+            sample_rate: MIX_SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = Some(
+            hound::WavWriter::create(path, spec)
+                .with_context(|| format!("Failed to create {:?}", path))?,
+        );
+
+        let inner = SoftwareOutput::spawn(speed, move |block| {
+            let Some(w) = writer.as_mut() else { return };
+            for &sample in block {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                if w.write_sample(sample).is_err() {
+                    eprintln!("WAV output: write failed, dropping the rest of the stream");
+                    writer = None;
+                    return;
+                }
+            }
+        });
+        Ok(Self { inner })
+    }
+}
+
+impl AudioOutput for WavOutput {
+    fn new_sink(&self) -> Result<Sink> {
+        Ok(self.inner.new_sink())
+    }
+}
@@ -0,0 +1,276 @@
+//! Playlists the user puts together by hand. Items refer to tracks by path, like the
+//! play queue does, so a playlist keeps its entries while a file is briefly missing
+//! from the library (moved, or on a drive that isn't mounted).
+
+use anyhow::{bail, Result};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use crate::library::{track_from_row, Library, TrackRecord, TRACK_SELECT};
+use crate::smart_playlist::SmartPlaylist;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Playlist {
+    pub id: i64,
+    pub name: String,
+    pub track_count: usize,
+    /// Total of the entries that are in the library
+    pub duration_ms: u64,
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistItem {
+    /// Stays the same while the item is moved around; use it to move or remove the item
+    pub id: i64,
+    pub path: String,
+    /// None while the file isn't in the library
+    pub track: Option<TrackRecord>,
+}
+
+/// A playlist was changed by some client; sent so the others can follow.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlaylistEvent {
+    /// Created, renamed or edited
+    PlaylistChanged { playlist: Playlist },
+    PlaylistDeleted { id: i64 },
+    SmartPlaylistChanged { playlist: SmartPlaylist },
+    SmartPlaylistDeleted { id: i64 },
+}
+
+const PLAYLIST_SELECT: &str = "
+    SELECT p.id, p.name, COUNT(i.id), IFNULL(SUM(t.duration_ms), 0), p.created_at_ms, p.updated_at_ms
+    FROM playlists p
+    LEFT JOIN playlist_items i ON i.playlist_id = p.id
+    LEFT JOIN tracks t ON t.path = i.path";
+
+fn playlist_from_row(row: &rusqlite::Row) -> rusqlite::Result<Playlist> {
+    Ok(Playlist {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        track_count: row.get::<_, i64>(2)? as usize,
+        duration_ms: row.get::<_, i64>(3)? as u64,
+        created_at_ms: row.get::<_, i64>(4)? as u64,
+        updated_at_ms: row.get::<_, i64>(5)? as u64,
+    })
+}
+
+impl Library {
+    /// Every playlist, by name.
+    pub fn playlists(&self) -> Result<Vec<Playlist>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!("{PLAYLIST_SELECT} GROUP BY p.id ORDER BY lower(p.name), p.id"))?;
+        let playlists = stmt.query_map([], playlist_from_row)?.filter_map(|r| r.ok()).collect();
+        Ok(playlists)
+    }
+
+    pub fn playlist(&self, id: i64) -> Result<Option<Playlist>> {
+        let conn = self.conn.lock().unwrap();
+        playlist_by_id(&conn, id)
+    }
+
+    /// The entries of playlist `id`, in order.
+    pub fn playlist_items(&self, id: i64) -> Result<Vec<PlaylistItem>> {
+        let conn = self.conn.lock().unwrap();
+        existing(&conn, id)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, path FROM playlist_items WHERE playlist_id = ?1 ORDER BY position",
+        )?;
+        let items: Vec<(i64, String)> = stmt
+            .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let mut stmt = conn.prepare(&format!(
+            "{TRACK_SELECT} WHERE t.path IN (SELECT path FROM playlist_items WHERE playlist_id = ?1)"
+        ))?;
+        let tracks: HashMap<String, TrackRecord> = stmt
+            .query_map(params![id], track_from_row)?
+            .filter_map(|r| r.ok())
+            .map(|track| (track.path.clone(), track))
+            .collect();
+        let items = items
+            .into_iter()
+            .map(|(item_id, path)| {
+                // The same track can be in a playlist more than once.
+                let track = tracks.get(&path).cloned();
+                PlaylistItem { id: item_id, path, track }
+            })
+            .collect();
+        Ok(items)
+    }
+
+    /// Paths of the entries of playlist `id` that are in the library, in order.
+    pub(crate) fn playlist_paths(&self, id: i64) -> Result<Vec<PathBuf>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT i.path FROM playlist_items i
+             JOIN tracks t ON t.path = i.path
+             WHERE i.playlist_id = ?1
+             ORDER BY i.position",
+        )?;
+        let paths = stmt
+            .query_map(params![id], |row| row.get::<_, String>(0))?
+            .filter_map(|r| r.ok())
+            .map(PathBuf::from)
+            .collect();
+        Ok(paths)
+    }
+
+    /// A new, empty playlist called `name`.
+    pub fn create_playlist(&self, name: &str) -> Result<Playlist> {
+        let conn = self.conn.lock().unwrap();
+        insert_playlist(&conn, name, &[])
+    }
+
+    pub fn rename_playlist(&self, id: i64, name: &str) -> Result<Playlist> {
+        let name = playlist_name(name)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE playlists SET name = ?2, updated_at_ms = ?3 WHERE id = ?1",
+            params![id, name, now_ms()],
+        )?;
+        existing(&conn, id)
+    }
+
+    /// Delete playlist `id` and its entries. Deleting one that doesn't exist is not an error.
+    pub fn delete_playlist(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM playlists WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Insert `paths` into playlist `id` before the entry at `index`, or at the end when
+    /// `index` is None or past it. Every path has to be of a track in the library.
+    pub fn add_to_playlist(&self, id: i64, paths: &[PathBuf], index: Option<usize>) -> Result<Playlist> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        existing(&tx, id)?;
+        for path in paths {
+            let indexed = tx
+                .prepare_cached("SELECT 1 FROM tracks WHERE path = ?1")?
+                .exists(params![path.to_string_lossy()])?;
+            if !indexed {
+                bail!("Not in the library: {}", path.display());
+            }
+        }
+
+        let mut order = item_order(&tx, id)?;
+        let index = index.unwrap_or(order.len()).min(order.len());
+        let mut added = Vec::with_capacity(paths.len());
+        for path in paths {
+            tx.execute(
+                "INSERT INTO playlist_items (playlist_id, position, path) VALUES (?1, -1, ?2)",
+                params![id, path.to_string_lossy()],
+            )?;
+            added.push(tx.last_insert_rowid());
+        }
+        order.splice(index..index, added);
+        write_order(&tx, id, &order)?;
+        let playlist = existing(&tx, id)?;
+        tx.commit()?;
+        Ok(playlist)
+    }
+
+    /// Take the entries `item_ids` out of playlist `id`. Ids that aren't in it are ignored.
+    pub fn remove_from_playlist(&self, id: i64, item_ids: &[i64]) -> Result<Playlist> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        existing(&tx, id)?;
+        let mut order = item_order(&tx, id)?;
+        order.retain(|item| !item_ids.contains(item));
+        tx.execute(
+            &format!(
+                "DELETE FROM playlist_items WHERE playlist_id = ?1 AND id IN ({})",
+                vec!["?"; item_ids.len()].join(", ")
+            ),
+            params_from_iter(std::iter::once(id).chain(item_ids.iter().copied())),
+        )?;
+        write_order(&tx, id, &order)?;
+        let playlist = existing(&tx, id)?;
+        tx.commit()?;
+        Ok(playlist)
+    }
+
+    /// Move entry `item_id` of playlist `id` to `index` (clamped to the end).
+    pub fn move_playlist_item(&self, id: i64, item_id: i64, index: usize) -> Result<Playlist> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        existing(&tx, id)?;
+        let mut order = item_order(&tx, id)?;
+        let Some(from) = order.iter().position(|&item| item == item_id) else {
+            bail!("Playlist {id} has no item {item_id}");
+        };
+        let item = order.remove(from);
+        order.insert(index.min(order.len()), item);
+        write_order(&tx, id, &order)?;
+        let playlist = existing(&tx, id)?;
+        tx.commit()?;
+        Ok(playlist)
+    }
+}
+
+fn playlist_by_id(conn: &Connection, id: i64) -> Result<Option<Playlist>> {
+    let playlist = conn
+        .query_row(&format!("{PLAYLIST_SELECT} WHERE p.id = ?1 GROUP BY p.id"), params![id], playlist_from_row)
+        .optional()?;
+    Ok(playlist)
+}
+
+/// Playlist `id`, or an error saying there is none.
+fn existing(conn: &Connection, id: i64) -> Result<Playlist> {
+    match playlist_by_id(conn, id)? {
+        Some(playlist) => Ok(playlist),
+        None => bail!("No playlist with id {id}"),
+    }
+}
+
+/// A new playlist named `name` holding `paths`, in order. The paths have to be of tracks
+/// in the library.
+pub(crate) fn insert_playlist(conn: &Connection, name: &str, paths: &[PathBuf]) -> Result<Playlist> {
+    let name = playlist_name(name)?;
+    conn.execute(
+        "INSERT INTO playlists (name, created_at_ms, updated_at_ms) VALUES (?1, ?2, ?2)",
+        params![name, now_ms()],
+    )?;
+    let id = conn.last_insert_rowid();
+    let mut insert = conn.prepare_cached("INSERT INTO playlist_items (playlist_id, position, path) VALUES (?1, ?2, ?3)")?;
+    for (position, path) in paths.iter().enumerate() {
+        insert.execute(params![id, position as i64, path.to_string_lossy()])?;
+    }
+    existing(conn, id)
+}
+
+pub(crate) fn playlist_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("A playlist needs a name");
+    }
+    Ok(name)
+}
+
+/// Item ids of playlist `id`, in order.
+fn item_order(conn: &Connection, id: i64) -> Result<Vec<i64>> {
+    let mut stmt = conn.prepare("SELECT id FROM playlist_items WHERE playlist_id = ?1 ORDER BY position")?;
+    let ids = stmt.query_map(params![id], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+    Ok(ids)
+}
+
+/// Number the items of playlist `id` in the order of `items`, and mark it changed.
+fn write_order(conn: &Connection, id: i64, items: &[i64]) -> Result<()> {
+    let mut update = conn.prepare_cached("UPDATE playlist_items SET position = ?2 WHERE id = ?1")?;
+    for (position, item) in items.iter().enumerate() {
+        update.execute(params![item, position as i64])?;
+    }
+    conn.execute("UPDATE playlists SET updated_at_ms = ?2 WHERE id = ?1", params![id, now_ms()])?;
+    Ok(())
+}
+
+pub(crate) fn now_ms() -> i64 {
+    std::time::SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as i64)
+}
@@ -0,0 +1,411 @@
+//! Playlist files: M3U/M3U8 (with `#EXTINF` lines), PLS and XSPF. Importing one makes
+//! a playlist of the entries that can be found in the library; exporting writes a
+//! playlist out, with paths relative to the file if asked so it can travel with the music.
+
+use anyhow::{bail, Context, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::escape::{escape, unescape};
+use quick_xml::events::Event;
+use quick_xml::name::QName;
+use quick_xml::Reader;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+use crate::library::Library;
+use crate::playlist::{insert_playlist, Playlist};
+
+/// What has to be escaped in a path segment of an XSPF location.
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b':').add(b'<').add(b'>')
+    .add(b'?').add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{').add(b'|').add(b'}');
+
+/// A kind of playlist file, as told by its extension. Files are written as UTF-8 and
+/// read as UTF-8 when they are, Latin-1 otherwise (as old M3U files often are).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The format the extension of `path` stands for.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "m3u" => Some(Self::M3u),
+            "m3u8" => Some(Self::M3u8),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a playlist file, as the file has it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PlaylistFileEntry {
+    /// Path or URI; None for an XSPF track given only by its tags
+    pub location: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration_ms: Option<u64>,
+}
+
+/// What importing a playlist file made of it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaylistImport {
+    pub playlist: Playlist,
+    /// Entries found in the library, and so in the playlist
+    pub resolved: usize,
+    /// Entries left out because no track in the library matched them
+    pub unresolved: Vec<UnresolvedEntry>,
+}
+
+/// An entry of an imported playlist file that no track in the library matched.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnresolvedEntry {
+    /// Position of the entry in the file, from 0
+    pub index: usize,
+    #[serde(flatten)]
+    pub entry: PlaylistFileEntry,
+}
+
+impl Library {
+    /// Make a playlist of the playlist file at `file`, named `name` or else after the
+    /// file. Each entry is looked for in the library by its absolute path, then by its
+    /// path relative to the file, then by title, artist and album.
+    pub fn import_playlist(&self, file: &Path, name: Option<&str>) -> Result<PlaylistImport> {
+        let Some(format) = PlaylistFormat::from_path(file) else {
+            bail!("Not a playlist file: {}", file.display());
+        };
+        let bytes = std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let entries = parse(&decode(bytes), format).with_context(|| format!("Failed to read {}", file.display()))?;
+
+        let file = std::path::absolute(file)?;
+        let base = file.parent().unwrap_or(Path::new("/"));
+        let stem = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut paths = Vec::new();
+        let mut unresolved = Vec::new();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (index, entry) in entries.into_iter().enumerate() {
+            match resolve(&tx, base, &entry)? {
+                Some(path) => paths.push(path),
+                None => unresolved.push(UnresolvedEntry { index, entry }),
+            }
+        }
+        let playlist = insert_playlist(&tx, name.unwrap_or(&stem), &paths)?;
+        tx.commit()?;
+        Ok(PlaylistImport { playlist, resolved: paths.len(), unresolved })
+    }
+
+    /// Write playlist `id` to `file`, in the format its extension names. With `relative`,
+    /// entries are written relative to the file's folder where they can be.
+    pub fn export_playlist(&self, id: i64, file: &Path, relative: bool) -> Result<()> {
+        let Some(format) = PlaylistFormat::from_path(file) else {
+            bail!("Not a playlist file: {}", file.display());
+        };
+        let Some(playlist) = self.playlist(id)? else { bail!("No playlist with id {id}") };
+        let file = std::path::absolute(file)?;
+        let base = file.parent().unwrap_or(Path::new("/"));
+
+        let entries: Vec<(String, PlaylistFileEntry)> = self
+            .playlist_items(id)?
+            .into_iter()
+            .map(|item| {
+                let path = Path::new(&item.path);
+                let written = if relative { relative_path(path, base) } else { None };
+                let location = match format {
+                    PlaylistFormat::Xspf => match &written {
+                        Some(written) => written
+                            .iter()
+                            .map(|segment| utf8_percent_encode(&segment.to_string_lossy(), SEGMENT).to_string())
+                            .collect::<Vec<_>>()
+                            .join("/"),
+                        None => Url::from_file_path(path).map_or_else(|_| item.path.clone(), String::from),
+                    },
+                    _ => written.map_or_else(|| item.path.clone(), |p| p.to_string_lossy().into_owned()),
+                };
+                let entry = match item.track {
+                    Some(track) => PlaylistFileEntry {
+                        location: None,
+                        title: Some(track.title),
+                        artist: Some(track.artist),
+                        album: track.album,
+                        duration_ms: Some(track.duration_ms),
+                    },
+                    None => PlaylistFileEntry::default(),
+                };
+                (location, entry)
+            })
+            .collect();
+
+        let text = match format {
+            PlaylistFormat::M3u | PlaylistFormat::M3u8 => write_m3u(&entries),
+            PlaylistFormat::Pls => write_pls(&entries),
+            PlaylistFormat::Xspf => write_xspf(&playlist.name, &entries),
+        };
+        std::fs::write(&file, text).with_context(|| format!("Failed to write {}", file.display()))?;
+        Ok(())
+    }
+}
+
+/// The text of a playlist file. Old M3U files are often in Latin-1 rather than UTF-8.
+fn decode(bytes: Vec<u8>) -> String {
+    let text = String::from_utf8(bytes).unwrap_or_else(|e| e.into_bytes().iter().map(|&b| b as char).collect());
+    text.strip_prefix('\u{feff}').map(String::from).unwrap_or(text)
+}
+
+fn parse(text: &str, format: PlaylistFormat) -> Result<Vec<PlaylistFileEntry>> {
+    match format {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok(parse_m3u(text)),
+        PlaylistFormat::Pls => Ok(parse_pls(text)),
+        PlaylistFormat::Xspf => parse_xspf(text),
+    }
+}
+
+fn parse_m3u(text: &str) -> Vec<PlaylistFileEntry> {
+    let mut entries = Vec::new();
+    // What the last #EXTINF line said about the entry that follows it
+    let mut info: Option<PlaylistFileEntry> = None;
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            // `#EXTINF:<seconds>[ attributes],<artist> - <title>`
+            let (length, display) = extinf.split_once(',').unwrap_or((extinf, ""));
+            let (artist, title) = split_display_title(display);
+            info = Some(PlaylistFileEntry { title, artist, duration_ms: seconds_to_ms(length), ..Default::default() });
+        } else if !line.starts_with('#') {
+            entries.push(PlaylistFileEntry { location: Some(line.to_string()), ..info.take().unwrap_or_default() });
+        }
+    }
+    entries
+}
+
+fn parse_pls(text: &str) -> Vec<PlaylistFileEntry> {
+    // Keys are numbered from 1 and needn't come in order.
+    let mut entries: BTreeMap<u32, PlaylistFileEntry> = BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let (field, number) = key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len()));
+        let Ok(number) = number.parse() else { continue };
+        let entry = entries.entry(number).or_default();
+        match field {
+            "file" => entry.location = Some(value.to_string()),
+            "title" => (entry.artist, entry.title) = split_display_title(value),
+            "length" => entry.duration_ms = seconds_to_ms(value),
+            _ => {}
+        }
+    }
+    entries.into_values().filter(|entry| entry.location.is_some()).collect()
+}
+
+fn parse_xspf(text: &str) -> Result<Vec<PlaylistFileEntry>> {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut entries = Vec::new();
+    let mut track: Option<PlaylistFileEntry> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.local_name().as_ref() == b"track" => track = Some(PlaylistFileEntry::default()),
+            Event::End(e) if e.local_name().as_ref() == b"track" => entries.extend(track.take()),
+            Event::Start(e) if track.is_some() => {
+                let name = e.name().as_ref().to_vec();
+                let local = e.local_name().as_ref().to_vec();
+                let text = reader.read_text(QName(&name))?;
+                let text = unescape(text.trim())?.into_owned();
+                let entry = track.as_mut().unwrap();
+                match local.as_slice() {
+                    // A track may list several locations; the first is the one to use.
+                    b"location" if entry.location.is_none() => entry.location = Some(xspf_location(&text)),
+                    b"title" => entry.title = Some(text),
+                    b"creator" => entry.artist = Some(text),
+                    b"album" => entry.album = Some(text),
+                    b"duration" => entry.duration_ms = text.parse().ok(),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// An XSPF location as a path where it is a relative reference, which is percent-encoded.
+/// URIs are kept for `local_path` to deal with.
+fn xspf_location(location: &str) -> String {
+    match Url::parse(location) {
+        Err(url::ParseError::RelativeUrlWithoutBase) => percent_decode_str(location).decode_utf8_lossy().into_owned(),
+        _ => location.to_string(),
+    }
+}
+
+/// "Artist - Title" as artist and title, or all of it as the title when there is no
+/// separator. Empty parts are None.
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let non_empty = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+    match display.split_once(" - ") {
+        Some((artist, title)) => (non_empty(artist), non_empty(title)),
+        None => (None, non_empty(display)),
+    }
+}
+
+/// A length in seconds, which can be followed by attributes; -1 means unknown.
+fn seconds_to_ms(length: &str) -> Option<u64> {
+    let seconds: f64 = length.split_whitespace().next()?.parse().ok()?;
+    (seconds > 0.0).then(|| (seconds * 1000.0).round() as u64)
+}
+
+/// The indexed track `entry` stands for: the one at its path, the one at its path
+/// taken relative to `base`, or the one with its tags.
+fn resolve(conn: &Connection, base: &Path, entry: &PlaylistFileEntry) -> Result<Option<PathBuf>> {
+    if let Some(path) = entry.location.as_deref().and_then(local_path) {
+        let path = if path.is_absolute() { path } else { normalize(&base.join(path)) };
+        let indexed = conn
+            .prepare_cached("SELECT 1 FROM tracks WHERE path = ?1")?
+            .exists(params![path.to_string_lossy()])?;
+        if indexed {
+            return Ok(Some(path));
+        }
+    }
+    tag_match(conn, entry)
+}
+
+/// The file `location` points at; None for anything but a path or a `file:` URI.
+fn local_path(location: &str) -> Option<PathBuf> {
+    if location.starts_with("file:") {
+        return Url::parse(location).ok()?.to_file_path().ok();
+    }
+    if location.contains("://") {
+        return None;
+    }
+    // Playlists written on Windows use backslashes, even in relative paths.
+    if cfg!(windows) {
+        Some(PathBuf::from(location))
+    } else {
+        Some(PathBuf::from(location.replace('\\', "/")))
+    }
+}
+
+/// `path` with `.` and `..` worked out, without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// The track titled like `entry` and by its artist, if it names one. Of several, the one
+/// on its album wins, then the one closest in length.
+fn tag_match(conn: &Connection, entry: &PlaylistFileEntry) -> Result<Option<PathBuf>> {
+    let Some(title) = &entry.title else { return Ok(None) };
+    let mut stmt = conn.prepare_cached(
+        "SELECT t.path, t.duration_ms, IFNULL(al.title = ?3 COLLATE NOCASE, 0)
+         FROM tracks t
+         JOIN artists a ON a.id = t.artist_id
+         LEFT JOIN albums al ON al.id = t.album_id
+         WHERE t.title = ?1 COLLATE NOCASE AND (?2 IS NULL OR a.name = ?2 COLLATE NOCASE)",
+    )?;
+    let candidates: Vec<(String, i64, bool)> = stmt
+        .query_map(params![title, entry.artist, entry.album], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .filter_map(|r| r.ok())
+        .collect();
+    let length = entry.duration_ms.map(|ms| ms as i64);
+    let best = candidates.into_iter().min_by_key(|(path, duration_ms, on_album)| {
+        (!on_album, length.map_or(0, |length| (duration_ms - length).abs()), path.clone())
+    });
+    Ok(best.map(|(path, _, _)| PathBuf::from(path)))
+}
+
+/// `path` relative to the folder `base`, or None when they share no root (different
+/// drives on Windows, say).
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    if path.first() != base.first() {
+        return None;
+    }
+    let shared = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in shared..base.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[shared..]);
+    Some(relative)
+}
+
+/// "Artist - Title", as M3U and PLS files show an entry.
+fn display_title(entry: &PlaylistFileEntry) -> Option<String> {
+    match (&entry.artist, &entry.title) {
+        (Some(artist), Some(title)) => Some(format!("{artist} - {title}")),
+        (None, Some(title)) => Some(title.clone()),
+        _ => None,
+    }
+}
+
+/// Whole seconds, or -1 when the length isn't known.
+fn seconds(entry: &PlaylistFileEntry) -> i64 {
+    entry.duration_ms.map_or(-1, |ms| ((ms + 500) / 1000) as i64)
+}
+
+fn write_m3u(entries: &[(String, PlaylistFileEntry)]) -> String {
+    let mut text = String::from("#EXTM3U\n");
+    for (location, entry) in entries {
+        if let Some(display) = display_title(entry) {
+            text.push_str(&format!("#EXTINF:{},{display}\n", seconds(entry)));
+        }
+        text.push_str(location);
+        text.push('\n');
+    }
+    text
+}
+
+fn write_pls(entries: &[(String, PlaylistFileEntry)]) -> String {
+    let mut text = String::from("[playlist]\n");
+    for (number, (location, entry)) in (1..).zip(entries) {
+        text.push_str(&format!("File{number}={location}\n"));
+        if let Some(display) = display_title(entry) {
+            text.push_str(&format!("Title{number}={display}\n"));
+        }
+        text.push_str(&format!("Length{number}={}\n", seconds(entry)));
+    }
+    text.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    text
+}
+
+fn write_xspf(name: &str, entries: &[(String, PlaylistFileEntry)]) -> String {
+    let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    text.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    text.push_str(&format!("  <title>{}</title>\n  <trackList>\n", escape(name)));
+    for (location, entry) in entries {
+        text.push_str("    <track>\n");
+        text.push_str(&format!("      <location>{}</location>\n", escape(location.as_str())));
+        let fields = [("title", &entry.title), ("creator", &entry.artist), ("album", &entry.album)];
+        for (element, value) in fields {
+            if let Some(value) = value {
+                text.push_str(&format!("      <{element}>{}</{element}>\n", escape(value.as_str())));
+            }
+        }
+        if let Some(duration_ms) = entry.duration_ms {
+            text.push_str(&format!("      <duration>{duration_ms}</duration>\n"));
+        }
+        text.push_str("    </track>\n");
+    }
+    text.push_str("  </trackList>\n</playlist>\n");
+    text
+}
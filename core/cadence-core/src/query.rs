@@ -0,0 +1,499 @@
+//! The search query language.
+//!
+//! Words match the start of words in a track's title, artist, album, album artist,
+//! composer or file name, and all of them have to match. On top of that:
+//!
+//! - `"a phrase"` matches those words in a row
+//! - `artist:`, `album:`, `albumartist:`, `title:`, `genre:`, `composer:` and
+//!   `filename:` match a word (or a quoted phrase) in that field only
+//! - `year:`, `duration:`, `track:`, `disc:`, `bitrate:`, `samplerate:`, `bitdepth:`
+//!   and `channels:` take a number (`year:1994`), a range (`year:1990..1999`,
+//!   `year:2000..`) or a comparison (`bitdepth:>=24`). Durations are in seconds or
+//!   `m:ss` (`duration:..2:30`), bitrates in kbps and sample rates in Hz.
+//! - `codec:` (or `format:`) matches the codec name (`codec:flac`), or `lossless`/`lossy`
+//! - `-term` or `NOT term` excludes matches, `a OR b` matches either, and parentheses
+//!   group terms
+//!
+//! Matching ignores case and accents: `bjork` finds "Björk".
+//!
+//! A query compiles to an SQL predicate over `tracks t`; text terms become FTS5
+//! queries against `tracks_fts`.
+
+use rusqlite::types::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Codecs that keep every bit of the original audio.
+const LOSSLESS_CODECS: &[&str] = &["ALAC", "FLAC", "Monkey's Audio", "PCM", "WavPack"];
+
+/// FTS5 columns bare words are looked up in.
+const FREE_TEXT_COLUMNS: &str = "{title artist album album_artist composer filename}";
+
+/// Names `field:` can take. Anything else before a colon is part of a word, so titles
+/// like "Re: Stacks" can be searched for as they are written.
+const FIELDS: &[&str] = &[
+    "title", "artist", "album", "albumartist", "album_artist", "genre", "composer", "filename", "file", "year",
+    "duration", "length", "track", "disc", "bitrate", "samplerate", "sample_rate", "bitdepth", "bit_depth",
+    "channels", "codec", "format",
+];
+
+/// Deepest that parentheses and exclusions can nest. Parsing and compiling recurse
+/// once per level, and queries come from any connected client.
+const MAX_NESTING: usize = 64;
+
+/// A query that couldn't be parsed, and where in it (in characters from the start).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// A query ready to run: `predicate` goes in the WHERE clause with `params` numbered from
+/// ?1. `rank` is an FTS5 query over the terms that have to match, to order hits by.
+#[derive(Debug, Default)]
+pub(crate) struct CompiledQuery {
+    pub predicate: String,
+    pub params: Vec<Value>,
+    pub rank: Option<String>,
+    /// The words outside phrases that have to match, lowercased: the ones a misspelling
+    /// can be looked for in.
+    pub words: Vec<String>,
+}
+
+/// Parse `query` and compile it. A query with no terms at all (only punctuation, say)
+/// compiles to None.
+///
+/// Each word in `alternatives` (lowercased) also matches the terms listed for it, unless
+/// it is in a phrase or excluded; that is how misspelled words are let through.
+pub(crate) fn compile(
+    query: &str,
+    alternatives: &HashMap<String, Vec<String>>,
+) -> Result<Option<CompiledQuery>, QueryError> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser { tokens, next: 0, end: query.chars().count(), depth: 0 };
+    let Some(expr) = parser.parse()? else { return Ok(None) };
+
+    let mut compiler = Compiler { alternatives, compiled: CompiledQuery::default(), rank: Vec::new() };
+    compiler.compiled.predicate = compiler.expr(&expr, false);
+    let mut compiled = compiler.compiled;
+    if !compiler.rank.is_empty() {
+        compiled.rank = Some(compiler.rank.join(" OR "));
+    }
+    Ok(Some(compiled))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    Or,
+    /// `field:value`, `value` or `"value"`
+    Term { field: Option<String>, value: String, quoted: bool },
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((start, Token::LParen));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((start, Token::RParen));
+                i += 1;
+            }
+            '-' if chars.get(i + 1).is_some_and(|c| !c.is_whitespace() && *c != ')') => {
+                tokens.push((start, Token::Not));
+                i += 1;
+            }
+            _ => {
+                // A word, possibly `field:` followed by a word or a quoted phrase.
+                let mut field = None;
+                let mut word = String::new();
+                while i < chars.len() && !chars[i].is_whitespace() && !matches!(chars[i], '(' | ')' | '"') {
+                    if chars[i] == ':' && field.is_none() && is_field_name(&word) {
+                        field = Some(std::mem::take(&mut word));
+                    } else {
+                        word.push(chars[i]);
+                    }
+                    i += 1;
+                }
+                if chars.get(i) == Some(&'"') && word.is_empty() {
+                    let (phrase, after) = quoted(&chars, i)?;
+                    tokens.push((start, Token::Term { field, value: phrase, quoted: true }));
+                    i = after;
+                    continue;
+                }
+                let token = match (field.is_some(), word.as_str()) {
+                    (false, "OR") => Token::Or,
+                    (false, "NOT") => Token::Not,
+                    _ => Token::Term { field, value: word, quoted: false },
+                };
+                tokens.push((start, token));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// The quoted phrase starting at `chars[start]`, and the index just past it.
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize), QueryError> {
+    let close = chars[start + 1..]
+        .iter()
+        .position(|c| *c == '"')
+        .ok_or_else(|| QueryError { position: start, message: "Unterminated quote".to_string() })?;
+    let end = start + 1 + close;
+    Ok((chars[start + 1..end].iter().collect(), end + 1))
+}
+
+fn is_field_name(name: &str) -> bool {
+    FIELDS.iter().any(|field| field.eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    /// Words to look up in FTS5 `columns` (a column filter), as a phrase if `quoted`
+    Text { columns: &'static str, words: Vec<String>, quoted: bool },
+    /// An SQL predicate and its parameters
+    Sql(String, Vec<Value>),
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Length of the query, where errors at its end point
+    end: usize,
+    /// How many parentheses and exclusions the term being parsed is inside
+    depth: usize,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Option<Expr>, QueryError> {
+        if self.tokens.is_empty() {
+            return Ok(None);
+        }
+        let expr = self.or()?;
+        if let Some((position, _)) = self.tokens.get(self.next) {
+            return Err(QueryError { position: *position, message: "Unmatched closing parenthesis".to_string() });
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(position, _)| *position)
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        QueryError { position: self.position(), message: message.to_string() }
+    }
+
+    fn or(&mut self) -> Result<Option<Expr>, QueryError> {
+        if self.peek() == Some(&Token::Or) {
+            return Err(self.error("OR needs a term on both sides"));
+        }
+        let mut branches = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            if matches!(self.peek(), None | Some(Token::Or | Token::RParen)) {
+                return Err(self.error("OR needs a term on both sides"));
+            }
+            branches.push(self.and()?);
+        }
+        // Terms that turned out empty (bare punctuation) drop out of an OR; an OR of
+        // nothing but those is empty too.
+        let mut branches: Vec<Expr> = branches.into_iter().flatten().collect();
+        Ok(match branches.len() {
+            0 => None,
+            1 => branches.pop(),
+            _ => Some(Expr::Or(branches)),
+        })
+    }
+
+    fn and(&mut self) -> Result<Option<Expr>, QueryError> {
+        let mut terms = Vec::new();
+        while !matches!(self.peek(), None | Some(Token::Or | Token::RParen)) {
+            if let Some(term) = self.unary()? {
+                terms.push(term);
+            }
+        }
+        Ok(match terms.len() {
+            0 => None,
+            1 => terms.pop(),
+            _ => Some(Expr::And(terms)),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Option<Expr>, QueryError> {
+        if self.depth > MAX_NESTING {
+            return Err(self.error("Too many nested parentheses or exclusions"));
+        }
+        self.depth += 1;
+        let expr = self.nested();
+        self.depth -= 1;
+        expr
+    }
+
+    /// A term, an exclusion or a parenthesized group; see `unary`.
+    fn nested(&mut self) -> Result<Option<Expr>, QueryError> {
+        let (position, token) = self.tokens[self.next].clone();
+        self.next += 1;
+        match token {
+            Token::Not => {
+                if matches!(self.peek(), None | Some(Token::Or | Token::RParen)) {
+                    return Err(self.error("Nothing to exclude"));
+                }
+                Ok(self.unary()?.map(|expr| Expr::Not(Box::new(expr))))
+            }
+            Token::LParen => {
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(QueryError { position, message: "Unmatched opening parenthesis".to_string() });
+                }
+                self.next += 1;
+                Ok(expr)
+            }
+            Token::Term { field, value, quoted } => term(field.as_deref(), &value, quoted, position),
+            Token::Or | Token::RParen => unreachable!("and() stops at OR and closing parentheses"),
+        }
+    }
+}
+
+/// Compile one `field:value` (or bare `value`) term at `position`.
+fn term(field: Option<&str>, value: &str, quoted: bool, position: usize) -> Result<Option<Expr>, QueryError> {
+    let value_position = position + field.map_or(0, |f| f.chars().count() + 1);
+    let error = |message: String| QueryError { position: value_position, message };
+    let Some(field) = field else { return Ok(text(FREE_TEXT_COLUMNS, value, quoted)) };
+
+    let text_column = match field.to_lowercase().as_str() {
+        "title" => Some("title"),
+        "artist" => Some("artist"),
+        "album" => Some("album"),
+        "albumartist" | "album_artist" => Some("album_artist"),
+        "genre" => Some("genre"),
+        "composer" => Some("composer"),
+        "filename" | "file" => Some("filename"),
+        _ => None,
+    };
+    if let Some(column) = text_column {
+        if value.is_empty() {
+            return Err(error(format!("{field}: needs a value")));
+        }
+        return Ok(text(column, value, quoted));
+    }
+
+    let numeric = match field.to_lowercase().as_str() {
+        "year" => Some(("t.year", Unit::Plain)),
+        "duration" | "length" => Some(("t.duration_ms", Unit::Duration)),
+        "track" => Some(("t.track_number", Unit::Plain)),
+        "disc" => Some(("t.disc_number", Unit::Plain)),
+        "bitrate" => Some(("t.bitrate_kbps", Unit::Plain)),
+        "samplerate" | "sample_rate" => Some(("t.sample_rate", Unit::Plain)),
+        "bitdepth" | "bit_depth" => Some(("t.bit_depth", Unit::Plain)),
+        "channels" => Some(("t.channels", Unit::Plain)),
+        _ => None,
+    };
+    if let Some((column, unit)) = numeric {
+        let (min, max) = range(value, unit).map_err(error)?;
+        let (predicate, params) = match (min, max) {
+            (Some(min), Some(max)) => (format!("{column} BETWEEN ? AND ?"), vec![min.into(), max.into()]),
+            (Some(min), None) => (format!("{column} >= ?"), vec![min.into()]),
+            (None, Some(max)) => (format!("{column} <= ?"), vec![max.into()]),
+            (None, None) => return Err(error(format!("{field}: needs a value"))),
+        };
+        return Ok(Some(Expr::Sql(predicate, params)));
+    }
+
+    match field.to_lowercase().as_str() {
+        "codec" | "format" => {
+            let predicate = match value.to_lowercase().as_str() {
+                "" => return Err(error(format!("{field}: needs a value"))),
+                "lossless" => format!("t.codec IN ({})", codec_list()),
+                "lossy" => format!("t.codec NOT IN ({})", codec_list()),
+                _ => return Ok(Some(Expr::Sql("t.codec = ? COLLATE NOCASE".to_string(), vec![value.to_string().into()]))),
+            };
+            Ok(Some(Expr::Sql(predicate, vec![])))
+        }
+        _ => unreachable!("tokenize only takes known fields"),
+    }
+}
+
+fn codec_list() -> String {
+    LOSSLESS_CODECS.iter().map(|c| format!("'{}'", c.replace('\'', "''"))).collect::<Vec<_>>().join(", ")
+}
+
+/// A match of `value` in `columns`: the phrase if `quoted`, otherwise each of its words
+/// as a prefix. Words are split on anything but letters and digits, as FTS5 splits what
+/// it indexes; with no words left there is nothing to match.
+fn text(columns: &'static str, value: &str, quoted: bool) -> Option<Expr> {
+    let words: Vec<String> = value.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(String::from).collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(Expr::Text { columns, words, quoted })
+}
+
+#[derive(Clone, Copy)]
+enum Unit {
+    Plain,
+    /// Seconds or `[h:]m:ss`, compared in milliseconds
+    Duration,
+}
+
+/// Bounds (inclusive) of `value`: `n`, `a..b`, `a..`, `..b`, `>n`, `>=n`, `<n` or `<=n`.
+fn range(value: &str, unit: Unit) -> Result<(Option<i64>, Option<i64>), String> {
+    let number = |text: &str| -> Result<i64, String> {
+        let parsed = match unit {
+            Unit::Plain => text.parse::<i64>().ok(),
+            Unit::Duration => duration_ms(text),
+        };
+        parsed.ok_or_else(|| format!("\"{text}\" is not a valid {}", match unit {
+            Unit::Plain => "number",
+            Unit::Duration => "duration",
+        }))
+    };
+    // The smallest step a value can take, for exclusive bounds and exact durations.
+    let step = match unit {
+        Unit::Plain => 1,
+        Unit::Duration => 1000,
+    };
+    let offset = |n: i64, by: i64| n.checked_add(by).ok_or_else(|| format!("\"{value}\" is out of range"));
+
+    if let Some((min, max)) = value.split_once("..") {
+        let min = (!min.is_empty()).then(|| number(min)).transpose()?;
+        let max = (!max.is_empty()).then(|| number(max)).transpose()?;
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                return Err(format!("Range \"{value}\" is empty"));
+            }
+        }
+        // `..2:30` takes in 2:30.999 as well.
+        return Ok((min, max.map(|max| offset(max, step - 1)).transpose()?));
+    }
+    if let Some(n) = value.strip_prefix(">=") {
+        return Ok((Some(number(n)?), None));
+    }
+    if let Some(n) = value.strip_prefix("<=") {
+        return Ok((None, Some(offset(number(n)?, step - 1)?)));
+    }
+    if let Some(n) = value.strip_prefix('>') {
+        return Ok((Some(offset(number(n)?, step)?), None));
+    }
+    if let Some(n) = value.strip_prefix('<') {
+        return Ok((None, Some(offset(number(n)?, -1)?)));
+    }
+    if value.is_empty() {
+        return Ok((None, None));
+    }
+    let n = number(value)?;
+    Ok((Some(n), Some(offset(n, step - 1)?)))
+}
+
+/// `[h:]m:ss` or whole seconds, in milliseconds.
+fn duration_ms(text: &str) -> Option<i64> {
+    let mut seconds: i64 = 0;
+    for (i, part) in text.split(':').enumerate() {
+        if i > 2 || part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        if i > 0 && part.len() != 2 {
+            return None;
+        }
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    seconds.checked_mul(1000)
+}
+
+struct Compiler<'a> {
+    alternatives: &'a HashMap<String, Vec<String>>,
+    compiled: CompiledQuery,
+    /// FTS5 queries of the text terms that have to match
+    rank: Vec<String>,
+}
+
+impl Compiler<'_> {
+    /// SQL for `expr`, pushing its parameters onto `compiled.params` and, unless
+    /// `negated`, its FTS5 queries onto `rank`.
+    fn expr(&mut self, expr: &Expr, negated: bool) -> String {
+        let mut join = |exprs: &[Expr], op: &str| {
+            let parts: Vec<String> = exprs.iter().map(|e| self.expr(e, negated)).collect();
+            format!("({})", parts.join(op))
+        };
+        match expr {
+            Expr::And(exprs) => join(exprs, " AND "),
+            Expr::Or(exprs) => join(exprs, " OR "),
+            Expr::Not(inner) => format!("NOT {}", self.expr(inner, !negated)),
+            Expr::Text { columns, words, quoted } => {
+                let query = self.fts_query(columns, words, *quoted, negated);
+                if !negated {
+                    self.rank.push(query.clone());
+                }
+                let params = &mut self.compiled.params;
+                params.push(query.into());
+                format!("t.id IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH ?{})", params.len())
+            }
+            Expr::Sql(predicate, values) => {
+                // Number the placeholders, and make a missing value (a track without a year,
+                // say) fail the test instead of making it unknown, so NOT works on it too.
+                let params = &mut self.compiled.params;
+                let mut numbered = String::new();
+                for (i, part) in predicate.split('?').enumerate() {
+                    if i > 0 {
+                        params.push(values[i - 1].clone());
+                        numbered += &format!("?{}", params.len());
+                    }
+                    numbered += part;
+                }
+                format!("IFNULL({numbered}, 0)")
+            }
+        }
+    }
+
+    /// The FTS5 query for a text term, noting its words for respelling unless `negated`:
+    /// fuzzy matching may widen what a query finds, never what it excludes.
+    fn fts_query(&mut self, columns: &str, words: &[String], quoted: bool, negated: bool) -> String {
+        let query = if quoted {
+            format!("\"{}\"", words.join(" "))
+        } else {
+            let words: Vec<String> = words
+                .iter()
+                .map(|word| {
+                    let lowercase = word.to_lowercase();
+                    let alternatives = if negated { None } else { self.alternatives.get(&lowercase) };
+                    if !negated {
+                        self.compiled.words.push(lowercase);
+                    }
+                    match alternatives {
+                        Some(terms) if !terms.is_empty() => {
+                            let terms: Vec<String> = terms.iter().map(|t| format!("\"{}\"", t.replace('"', "\"\""))).collect();
+                            format!("(\"{word}\"* OR {})", terms.join(" OR "))
+                        }
+                        _ => format!("\"{word}\"*"),
+                    }
+                })
+                .collect();
+            words.join(" ")
+        };
+        format!("{columns} : ({query})")
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{bail, Result};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Played tracks kept in the history beyond this are forgotten, oldest first.
+pub const HISTORY_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueueItem {
+    /// Stable handle clients use to move or remove the item
+    pub id: i64,
+    pub path: PathBuf,
+    /// Position in the play context when the item came from one; None when queued by hand
+    pub context_index: Option<usize>,
+}
+
+/// Where a run of tracks came from, for playing it through in order or shuffled.
+/// `Library::context_tracks` resolves it to the tracks it stands for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlayContext {
+    /// Every track by an artist
+    Artist { name: String },
+    /// An album, in disc and track order
+    Album { title: String, album_artist: String },
+    /// The results of a library search, in the order they were listed
+    Search { query: String },
+    /// A playlist's entries that are in the library, in playlist order
+    Playlist { id: i64 },
+    /// What a smart playlist's rules pick at the time it is played
+    SmartPlaylist { id: i64 },
+}
+
+/// What has played, what is playing and what is up next.
+/// `Library::save_queue` / `Library::load_queue` persist it across sessions.
+///
+/// Upcoming tracks queued by hand play before the rest of the play context.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlayQueue {
+    /// Played tracks, oldest first
+    history: Vec<QueueItem>,
+    current: Option<QueueItem>,
+    /// Tracks to play after the current one, in order
+    upcoming: Vec<QueueItem>,
+    context: Option<PlayContext>,
+    /// Every track of the context, in its own order
+    #[serde(skip)]
+    context_paths: Vec<PathBuf>,
+    /// Whether the context's upcoming tracks are in shuffled order
+    shuffled: bool,
+    #[serde(skip)]
+    next_id: i64,
+}
+
+/// Something that changed outside of a client's request.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueEvent {
+    QueueChanged { queue: PlayQueue },
+}
+
+/// A queue's context as stored alongside its items.
+pub(crate) struct StoredContext {
+    pub context: Option<PlayContext>,
+    pub paths: Vec<PathBuf>,
+    pub shuffled: bool,
+}
+
+impl PlayQueue {
+    /// Rebuild a queue from its stored parts.
+    pub(crate) fn from_parts(
+        history: Vec<QueueItem>,
+        current: Option<QueueItem>,
+        upcoming: Vec<QueueItem>,
+        context: StoredContext,
+    ) -> Self {
+        let next_id = history.iter()
+            .chain(current.iter())
+            .chain(upcoming.iter())
+            .map(|item| item.id + 1)
+            .max()
+            .unwrap_or(0);
+        Self {
+            history,
+            current,
+            upcoming,
+            context: context.context,
+            context_paths: context.paths,
+            shuffled: context.shuffled,
+            next_id,
+        }
+    }
+
+    pub(crate) fn stored_context(&self) -> StoredContext {
+        StoredContext { context: self.context.clone(), paths: self.context_paths.clone(), shuffled: self.shuffled }
+    }
+
+    pub fn history(&self) -> &[QueueItem] {
+        &self.history
+    }
+
+    pub fn current(&self) -> Option<&QueueItem> {
+        self.current.as_ref()
+    }
+
+    pub fn upcoming(&self) -> &[QueueItem] {
+        &self.upcoming
+    }
+
+    /// The context the current run of tracks is played from, if any.
+    pub fn context(&self) -> Option<&PlayContext> {
+        self.context.as_ref()
+    }
+
+    /// The item `advance` would move to.
+    pub fn peek_next(&self) -> Option<&QueueItem> {
+        self.upcoming.first()
+    }
+
+    fn item(&mut self, path: PathBuf, context_index: Option<usize>) -> QueueItem {
+        let id = self.next_id;
+        self.next_id += 1;
+        QueueItem { id, path, context_index }
+    }
+
+    /// Add `paths` after the other tracks queued by hand, ahead of the rest of the context.
+    pub fn enqueue(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        let at = self.upcoming.iter()
+            .position(|item| item.context_index.is_some())
+            .unwrap_or(self.upcoming.len());
+        let items: Vec<QueueItem> = paths.into_iter().map(|path| self.item(path, None)).collect();
+        self.upcoming.splice(at..at, items);
+    }
+
+    /// Insert `paths`, in order, ahead of the other upcoming tracks.
+    pub fn play_next(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        let items: Vec<QueueItem> = paths.into_iter().map(|path| self.item(path, None)).collect();
+        self.upcoming.splice(0..0, items);
+    }
+
+    /// Move the upcoming item `id` to `index` among the upcoming tracks (clamped to the end).
+    pub fn move_item(&mut self, id: i64, index: usize) -> Result<()> {
+        let Some(from) = self.upcoming.iter().position(|item| item.id == id) else {
+            bail!("No upcoming queue item with id {id}");
+        };
+        let item = self.upcoming.remove(from);
+        self.upcoming.insert(index.min(self.upcoming.len()), item);
+        Ok(())
+    }
+
+    /// Drop the upcoming item `id`. Returns whether there was one.
+    pub fn remove(&mut self, id: i64) -> bool {
+        let len = self.upcoming.len();
+        self.upcoming.retain(|item| item.id != id);
+        self.upcoming.len() != len
+    }
+
+    /// Drop every upcoming track and the context. History and the current track stay.
+    pub fn clear(&mut self) {
+        self.upcoming.clear();
+        self.clear_context();
+    }
+
+    /// Make `path` the current track, moving the previous one to the history.
+    /// Tracks queued by hand are kept; the rest of any context is dropped.
+    pub fn play_now(&mut self, path: PathBuf) -> &QueueItem {
+        let item = self.item(path, None);
+        self.retire_current();
+        self.clear_context();
+        self.current.insert(item)
+    }
+
+    /// Start playing `paths`, which `context` resolved to, at `start`. The remaining
+    /// tracks follow in order, or shuffled when `shuffle` is set. Tracks queued by
+    /// hand still play first.
+    pub fn play_context(&mut self, context: PlayContext, paths: Vec<PathBuf>, start: usize, shuffle: bool) -> Result<&QueueItem> {
+        if start >= paths.len() {
+            bail!("Start index {start} is out of range for a context of {} tracks", paths.len());
+        }
+        self.clear_context();
+        self.context = Some(context);
+        self.context_paths = paths;
+
+        let item = self.item(self.context_paths[start].clone(), Some(start));
+        self.retire_current();
+        self.current = Some(item);
+        self.shuffled = shuffle;
+        self.fill_from_context();
+        Ok(self.current.as_ref().unwrap())
+    }
+
+    /// Switch the rest of the context between shuffled and in-order playback.
+    /// Unshuffling continues in context order after the current track.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        if shuffle == self.shuffled {
+            return;
+        }
+        self.shuffled = shuffle;
+        if self.context.is_some() {
+            self.upcoming.retain(|item| item.context_index.is_none());
+            self.fill_from_context();
+        }
+    }
+
+    /// Append the context's tracks that follow the current one, in context or shuffled order.
+    fn fill_from_context(&mut self) {
+        let current = self.current.as_ref().and_then(|item| item.context_index);
+        let mut indices: Vec<usize> = if self.shuffled {
+            (0..self.context_paths.len()).filter(|&i| Some(i) != current).collect()
+        } else {
+            let first = current.map_or(0, |i| i + 1);
+            (first..self.context_paths.len()).collect()
+        };
+        if self.shuffled {
+            indices.shuffle(&mut rand::thread_rng());
+        }
+        for index in indices {
+            let item = self.item(self.context_paths[index].clone(), Some(index));
+            self.upcoming.push(item);
+        }
+    }
+
+    fn clear_context(&mut self) {
+        self.upcoming.retain(|item| item.context_index.is_none());
+        self.context = None;
+        self.context_paths.clear();
+    }
+
+    /// Move on to the next upcoming track, if there is one.
+    pub fn advance(&mut self) -> Option<&QueueItem> {
+        if self.upcoming.is_empty() {
+            return None;
+        }
+        let next = self.upcoming.remove(0);
+        self.retire_current();
+        Some(self.current.insert(next))
+    }
+
+    /// Jump to the upcoming item `id`. The tracks skipped over go to the history.
+    pub fn skip_to(&mut self, id: i64) -> Result<&QueueItem> {
+        let Some(index) = self.upcoming.iter().position(|item| item.id == id) else {
+            bail!("No upcoming queue item with id {id}");
+        };
+        let skipped: Vec<QueueItem> = self.upcoming.drain(..index).collect();
+        self.retire_current();
+        self.history.extend(skipped);
+        self.trim_history();
+        Ok(self.advance().expect("the target item is upcoming"))
+    }
+
+    /// Step back to the last played track, putting the current one back at the
+    /// front of the upcoming tracks.
+    pub fn back(&mut self) -> Option<&QueueItem> {
+        let previous = self.history.pop()?;
+        if let Some(current) = self.current.take() {
+            self.upcoming.insert(0, current);
+        }
+        Some(self.current.insert(previous))
+    }
+
+    /// Forget the current track, e.g. once playback stopped at the end of the queue.
+    pub fn finish(&mut self) {
+        self.retire_current();
+    }
+
+    fn retire_current(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.history.push(current);
+            self.trim_history();
+        }
+    }
+
+    fn trim_history(&mut self) {
+        let excess = self.history.len().saturating_sub(HISTORY_LIMIT);
+        self.history.drain(..excess);
+    }
+}
@@ -0,0 +1,84 @@
+use lofty::tag::{ItemKey, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Which ReplayGain value the player normalizes to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+/// Pre-amp range accepted by the player, in dB.
+pub const PREAMP_RANGE_DB: (f32, f32) = (-15.0, 15.0);
+
+/// Opus R128 gains are relative to -23 LUFS; ReplayGain 2.0 targets -18 LUFS.
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+
+/// Loudness normalization values read from a file's tags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ReplayGain {
+    pub track_gain_db: Option<f32>,
+    /// Linear sample peak, 1.0 = full scale
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Read REPLAYGAIN_* items, falling back to R128_*_GAIN (Opus) when they're missing.
+    pub(crate) fn from_tag(tag: &Tag) -> Self {
+        let gain = |key: ItemKey| tag.get_string(&key).and_then(parse_gain_db);
+        let peak = |key: ItemKey| tag.get_string(&key).and_then(parse_peak);
+        let r128 = |key: &str| {
+            tag.get_string(&ItemKey::Unknown(key.to_string()))
+                .and_then(|v| v.trim().parse::<i16>().ok())
+                .map(|q78| q78 as f32 / 256.0 + R128_TO_REPLAYGAIN_DB)
+        };
+
+        Self {
+            track_gain_db: gain(ItemKey::ReplayGainTrackGain).or_else(|| r128("R128_TRACK_GAIN")),
+            track_peak: peak(ItemKey::ReplayGainTrackPeak),
+            album_gain_db: gain(ItemKey::ReplayGainAlbumGain).or_else(|| r128("R128_ALBUM_GAIN")),
+            album_peak: peak(ItemKey::ReplayGainAlbumPeak),
+        }
+    }
+
+    /// Linear gain for `mode` with `preamp_db` added, lowered where needed so the
+    /// known peak doesn't clip. Each mode falls back to the other's values when missing.
+    pub fn linear_gain(&self, mode: ReplayGainMode, preamp_db: f32) -> f32 {
+        let (gain_db, peak) = match mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                self.track_gain_db.or(self.album_gain_db),
+                self.track_peak.or(self.album_peak),
+            ),
+            ReplayGainMode::Album => (
+                self.album_gain_db.or(self.track_gain_db),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+        let Some(gain_db) = gain_db else { return 1.0 };
+
+        let gain = 10f32.powf((gain_db + preamp_db) / 20.0);
+        match peak {
+            Some(peak) if peak > 0.0 => gain.min(1.0 / peak),
+            _ => gain,
+        }
+    }
+}
+
+/// Parse values like "-6.54 dB" or "+1.2dB".
+fn parse_gain_db(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    number.trim().parse().ok()
+}
+
+fn parse_peak(value: &str) -> Option<f32> {
+    value.trim().parse().ok().filter(|p: &f32| p.is_finite() && *p >= 0.0)
+}
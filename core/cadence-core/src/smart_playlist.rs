@@ -0,0 +1,384 @@
+//! Smart playlists: saved rules that pick tracks from the library, such as "genre is
+//! Jazz, added in the last 30 days and played fewer than 3 times, in random order, 100
+//! of them". The rules are stored as JSON and compiled to SQL each time the playlist is
+//! read or played, so it always reflects the library as it is. Only the track counts
+//! shown in listings are kept around, until the database next changes.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::browse::SortOrder;
+use crate::library::{track_from_row, Library, TrackRecord, TRACK_SELECT};
+use crate::playlist::{now_ms, playlist_name};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// One test on a track, or a combination of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rule {
+    /// Every one of `rules` holds; true when there are none
+    All { rules: Vec<Rule> },
+    /// At least one of `rules` holds
+    Any { rules: Vec<Rule> },
+    Not { rule: Box<Rule> },
+    /// Compares without regard to case
+    Text { field: TextField, op: TextOp, value: String },
+    Number { field: NumberField, op: NumberOp, value: i64 },
+    /// `field` falls within the last `days` days
+    InLast { field: DateField, days: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Genre,
+    Composer,
+    Codec,
+    Path,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextOp {
+    Is,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberField {
+    Year,
+    DurationMs,
+    PlayCount,
+    TrackNumber,
+    DiscNumber,
+    BitrateKbps,
+    SampleRate,
+    BitDepth,
+    Channels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateField {
+    /// When the track was first indexed
+    Added,
+    LastPlayed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartSort {
+    /// By artist, then album in disc and track order
+    #[default]
+    Artist,
+    Album,
+    Title,
+    Year,
+    Added,
+    LastPlayed,
+    PlayCount,
+    /// A fresh shuffle each time the playlist is read
+    Random,
+}
+
+/// What a smart playlist picks, in what order and how many.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartPlaylistRules {
+    pub rule: Rule,
+    #[serde(default)]
+    pub sort: SmartSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Most tracks to pick; all that match when None
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SmartPlaylist {
+    pub id: i64,
+    pub name: String,
+    pub rules: SmartPlaylistRules,
+    /// Tracks the rules pick right now
+    pub track_count: usize,
+    pub created_at_ms: u64,
+    pub updated_at_ms: u64,
+}
+
+/// Track counts of smart playlists, kept until the database changes, so listing the
+/// playlists doesn't run all their rules every time. Rules on dates pick different tracks
+/// as time passes, so their counts aren't kept.
+#[derive(Debug, Default)]
+pub(crate) struct TrackCounts {
+    /// `total_changes()` of the connection the counts are from
+    stamp: i64,
+    counts: HashMap<i64, usize>,
+}
+
+impl Library {
+    /// Every smart playlist, by name.
+    pub fn smart_playlists(&self) -> Result<Vec<SmartPlaylist>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id FROM smart_playlists ORDER BY lower(name), id")?;
+        let ids: Vec<i64> = stmt.query_map([], |row| row.get(0))?.filter_map(|r| r.ok()).collect();
+        ids.into_iter().map(|id| existing(&conn, &self.smart_counts, id)).collect()
+    }
+
+    pub fn smart_playlist(&self, id: i64) -> Result<Option<SmartPlaylist>> {
+        let conn = self.conn.lock().unwrap();
+        smart_playlist_by_id(&conn, &self.smart_counts, id)
+    }
+
+    /// The tracks smart playlist `id` picks from the library as it is now.
+    pub fn smart_playlist_tracks(&self, id: i64) -> Result<Vec<TrackRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let rules = existing(&conn, &self.smart_counts, id)?.rules;
+        let (sql, params) = select(&rules, TRACK_SELECT);
+        let mut stmt = conn.prepare(&sql)?;
+        let tracks = stmt.query_map(params_from_iter(params), track_from_row)?.filter_map(|r| r.ok()).collect();
+        Ok(tracks)
+    }
+
+    /// Paths of the tracks smart playlist `id` picks, in order.
+    pub(crate) fn smart_playlist_paths(&self, id: i64) -> Result<Vec<PathBuf>> {
+        Ok(self.smart_playlist_tracks(id)?.into_iter().map(|t| PathBuf::from(t.path)).collect())
+    }
+
+    pub fn create_smart_playlist(&self, name: &str, rules: &SmartPlaylistRules) -> Result<SmartPlaylist> {
+        let name = playlist_name(name)?;
+        let rules = serialized(rules)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO smart_playlists (name, rules, created_at_ms, updated_at_ms) VALUES (?1, ?2, ?3, ?3)",
+            params![name, rules, now_ms()],
+        )?;
+        existing(&conn, &self.smart_counts, conn.last_insert_rowid())
+    }
+
+    /// Rename smart playlist `id` and replace its rules.
+    pub fn update_smart_playlist(&self, id: i64, name: &str, rules: &SmartPlaylistRules) -> Result<SmartPlaylist> {
+        let name = playlist_name(name)?;
+        let rules = serialized(rules)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE smart_playlists SET name = ?2, rules = ?3, updated_at_ms = ?4 WHERE id = ?1",
+            params![id, name, rules, now_ms()],
+        )?;
+        existing(&conn, &self.smart_counts, id)
+    }
+
+    /// Deleting one that doesn't exist is not an error.
+    pub fn delete_smart_playlist(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM smart_playlists WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Count a play of the track at `path`, now. Paths not in the library are ignored.
+    pub fn record_play(&self, path: &std::path::Path) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE tracks SET play_count = play_count + 1, last_played_at_ms = ?2 WHERE path = ?1",
+            params![path.to_string_lossy(), now_ms()],
+        )?;
+        Ok(())
+    }
+}
+
+fn smart_playlist_by_id(conn: &Connection, counts: &Mutex<TrackCounts>, id: i64) -> Result<Option<SmartPlaylist>> {
+    let row: Option<(String, String, i64, i64)> = conn
+        .query_row(
+            "SELECT name, rules, created_at_ms, updated_at_ms FROM smart_playlists WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?;
+    let Some((name, rules, created_at_ms, updated_at_ms)) = row else { return Ok(None) };
+    let rules: SmartPlaylistRules =
+        serde_json::from_str(&rules).with_context(|| format!("Smart playlist {id} has unreadable rules"))?;
+
+    let track_count = track_count(conn, counts, id, &rules)?;
+
+    Ok(Some(SmartPlaylist {
+        id,
+        name,
+        rules,
+        track_count,
+        created_at_ms: created_at_ms as u64,
+        updated_at_ms: updated_at_ms as u64,
+    }))
+}
+
+/// How many tracks the `rules` of smart playlist `id` pick, from `counts` when nothing
+/// changed since it was counted.
+fn track_count(conn: &Connection, counts: &Mutex<TrackCounts>, id: i64, rules: &SmartPlaylistRules) -> Result<usize> {
+    let stamp = conn.query_row("SELECT total_changes()", [], |row| row.get(0))?;
+    let mut counts = counts.lock().unwrap();
+    if counts.stamp != stamp {
+        *counts = TrackCounts { stamp, counts: HashMap::new() };
+    }
+    if let Some(&count) = counts.counts.get(&id) {
+        return Ok(count);
+    }
+
+    // No need to sort the tracks to count them.
+    let mut params = Vec::new();
+    let predicate = compile(&rules.rule, &mut params, now_ms());
+    let count: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({TRACK_SELECT} WHERE {predicate})"),
+        params_from_iter(params),
+        |row| row.get(0),
+    )?;
+    let count = rules.limit.map_or(count as usize, |limit| limit.min(count as usize));
+    if !uses_dates(&rules.rule) {
+        counts.counts.insert(id, count);
+    }
+    Ok(count)
+}
+
+/// Whether `rule` compares dates with the current time, so that what it picks changes
+/// as time passes.
+fn uses_dates(rule: &Rule) -> bool {
+    match rule {
+        Rule::All { rules } | Rule::Any { rules } => rules.iter().any(uses_dates),
+        Rule::Not { rule } => uses_dates(rule),
+        Rule::InLast { .. } => true,
+        Rule::Text { .. } | Rule::Number { .. } => false,
+    }
+}
+
+/// Smart playlist `id`, or an error saying there is none.
+fn existing(conn: &Connection, counts: &Mutex<TrackCounts>, id: i64) -> Result<SmartPlaylist> {
+    match smart_playlist_by_id(conn, counts, id)? {
+        Some(playlist) => Ok(playlist),
+        None => bail!("No smart playlist with id {id}"),
+    }
+}
+
+fn serialized(rules: &SmartPlaylistRules) -> Result<String> {
+    if rules.limit == Some(0) {
+        bail!("A smart playlist's limit has to be at least 1");
+    }
+    Ok(serde_json::to_string(rules)?)
+}
+
+/// `select` (which reads `tracks t` with the joins of `TRACK_SELECT`) narrowed down to
+/// what `rules` pick, in their order, and its parameters.
+fn select(rules: &SmartPlaylistRules, select: &str) -> (String, Vec<Value>) {
+    let mut params = Vec::new();
+    let predicate = compile(&rules.rule, &mut params, now_ms());
+    let direction = match rules.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    let order = match rules.sort {
+        SmartSort::Artist => format!("lower(a.name) {direction}, lower(al.title), t.disc_number, t.track_number, t.path"),
+        SmartSort::Album => format!("lower(al.title) {direction}, t.disc_number, t.track_number, t.path"),
+        SmartSort::Title => format!("lower(t.title) {direction}, t.path"),
+        SmartSort::Year => format!("t.year {direction} NULLS LAST, lower(al.title), t.disc_number, t.track_number, t.path"),
+        SmartSort::Added => format!("t.added_at_ms {direction}, t.path"),
+        SmartSort::LastPlayed => format!("t.last_played_at_ms {direction} NULLS LAST, t.path"),
+        SmartSort::PlayCount => format!("t.play_count {direction}, t.path"),
+        SmartSort::Random => "random()".to_string(),
+    };
+    let limit = rules.limit.map_or(-1, |limit| limit as i64);
+    (format!("{select} WHERE {predicate} ORDER BY {order} LIMIT {limit}"), params)
+}
+
+/// SQL for `rule` as of `now_ms`, pushing its parameters onto `params` and numbering
+/// them to match. A missing value (a track without a year, say) fails a test rather
+/// than making it unknown, so `Not` works on it too.
+fn compile(rule: &Rule, params: &mut Vec<Value>, now_ms: i64) -> String {
+    let mut param = |value: Value| {
+        params.push(value);
+        format!("?{}", params.len())
+    };
+    match rule {
+        Rule::All { rules } | Rule::Any { rules } if rules.is_empty() => {
+            if matches!(rule, Rule::All { .. }) { "1".to_string() } else { "0".to_string() }
+        }
+        Rule::All { rules } => {
+            let parts: Vec<String> = rules.iter().map(|r| compile(r, params, now_ms)).collect();
+            format!("({})", parts.join(" AND "))
+        }
+        Rule::Any { rules } => {
+            let parts: Vec<String> = rules.iter().map(|r| compile(r, params, now_ms)).collect();
+            format!("({})", parts.join(" OR "))
+        }
+        Rule::Not { rule } => format!("NOT {}", compile(rule, params, now_ms)),
+        Rule::Text { field, op, value } => {
+            let column = match field {
+                TextField::Title => "t.title",
+                TextField::Artist => "a.name",
+                TextField::Album => "al.title",
+                TextField::AlbumArtist => "aa.name",
+                TextField::Genre => "g.name",
+                TextField::Composer => "c.name",
+                TextField::Codec => "t.codec",
+                TextField::Path => "t.path",
+            };
+            let escaped = value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            let (comparison, value) = match op {
+                TextOp::Is => ("= {} COLLATE NOCASE", value.clone()),
+                TextOp::Contains => ("LIKE {} ESCAPE '\\'", format!("%{escaped}%")),
+                TextOp::StartsWith => ("LIKE {} ESCAPE '\\'", format!("{escaped}%")),
+                TextOp::EndsWith => ("LIKE {} ESCAPE '\\'", format!("%{escaped}")),
+            };
+            let placeholder = param(value.into());
+            format!("IFNULL({column} {}, 0)", comparison.replace("{}", &placeholder))
+        }
+        Rule::Number { field, op, value } => {
+            let column = match field {
+                NumberField::Year => "t.year",
+                NumberField::DurationMs => "t.duration_ms",
+                NumberField::PlayCount => "t.play_count",
+                NumberField::TrackNumber => "t.track_number",
+                NumberField::DiscNumber => "t.disc_number",
+                NumberField::BitrateKbps => "t.bitrate_kbps",
+                NumberField::SampleRate => "t.sample_rate",
+                NumberField::BitDepth => "t.bit_depth",
+                NumberField::Channels => "t.channels",
+            };
+            let op = match op {
+                NumberOp::Eq => "=",
+                NumberOp::Ne => "<>",
+                NumberOp::Lt => "<",
+                NumberOp::Le => "<=",
+                NumberOp::Gt => ">",
+                NumberOp::Ge => ">=",
+            };
+            format!("IFNULL({column} {op} {}, 0)", param((*value).into()))
+        }
+        Rule::InLast { field, days } => {
+            let column = match field {
+                DateField::Added => "t.added_at_ms",
+                DateField::LastPlayed => "t.last_played_at_ms",
+            };
+            format!("IFNULL({column} >= {}, 0)", param((now_ms - *days as i64 * DAY_MS).into()))
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use rodio::source::SeekError;
+use rodio::{Sample, Source};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Shared state between the player and a source that has been handed to the sink.
+#[derive(Debug)]
+pub(crate) struct TrackControl {
+    /// Set by the audio thread when the first sample of the track is pulled.
+    started: AtomicBool,
+    /// When set before the track starts, the track ends immediately without producing samples.
+    cancelled: AtomicBool,
+    /// Length of a requested fade-out in ms; 0 while none has been requested.
+    fade_out_ms: AtomicU64,
+    /// Linear gain applied to every sample, stored as f32 bits.
+    gain: AtomicU32,
+    /// Audio time handed to the sink so far, in microseconds. Follows seeks.
+    position_us: AtomicU64,
+}
+
+impl TrackControl {
+    pub(crate) fn new(gain: f32) -> Self {
+        Self {
+            started: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            fade_out_ms: AtomicU64::new(0),
+            gain: AtomicU32::new(gain.to_bits()),
+            position_us: AtomicU64::new(0),
+        }
+    }
+
+    /// True once the sink has started pulling samples from this track.
+    pub(crate) fn has_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    /// Position in the track, counted from the samples the sink has pulled.
+    pub(crate) fn position(&self) -> Duration {
+        Duration::from_micros(self.position_us.load(Ordering::Relaxed))
+    }
+
+    /// Drop the track from the sink queue. Has no effect once it has started playing.
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Change the gain stage of the track, effective from the next sample.
+    pub(crate) fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Fade the track out over `duration` starting now, then end it.
+    pub(crate) fn fade_out(&self, duration: Duration) {
+        self.fade_out_ms.store((duration.as_millis() as u64).max(1), Ordering::SeqCst);
+    }
+}
+
+/// Progress of a fade-out, in samples.
+struct FadeOut {
+    remaining: u64,
+    total: u64,
+}
+
+/// Counts samples to turn them into a track position. Sample rate and channel count
+/// can change between frames, so the count restarts from `base` whenever they do.
+struct Clock {
+    base: Duration,
+    samples: u64,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Clock {
+    fn elapsed(&self) -> Duration {
+        let samples_per_sec = self.sample_rate as u64 * self.channels as u64;
+        if samples_per_sec == 0 {
+            return self.base;
+        }
+        self.base + Duration::from_micros(self.samples * 1_000_000 / samples_per_sec)
+    }
+
+    fn restart(&mut self, base: Duration, sample_rate: u32, channels: u16) {
+        *self = Self { base, samples: 0, sample_rate, channels };
+    }
+}
+
+/// Wraps a track's decoder so the player can tell exactly when the sink moves on to it
+/// and how far it got, adjust its gain and fade it out on request.
+pub(crate) struct Tracked<S> {
+    inner: S,
+    control: Arc<TrackControl>,
+    fade_out: Option<FadeOut>,
+    clock: Clock,
+}
+
+impl<S: Source> Tracked<S>
+where
+    S::Item: Sample,
+{
+    pub(crate) fn new(inner: S, control: Arc<TrackControl>) -> Self {
+        let clock = Clock {
+            base: Duration::ZERO,
+            samples: 0,
+            sample_rate: inner.sample_rate(),
+            channels: inner.channels(),
+        };
+        Self { inner, control, fade_out: None, clock }
+    }
+
+    /// Count one sample pulled by the sink, publishing the position on frame boundaries.
+    #[inline]
+    fn tick(&mut self) {
+        let (sample_rate, channels) = (self.inner.sample_rate(), self.inner.channels());
+        if sample_rate != self.clock.sample_rate || channels != self.clock.channels {
+            self.clock.restart(self.clock.elapsed(), sample_rate, channels);
+        }
+        self.clock.samples += 1;
+        if self.clock.samples.is_multiple_of(self.clock.channels.max(1) as u64) {
+            self.publish();
+        }
+    }
+
+    fn publish(&self) {
+        let micros = self.clock.elapsed().as_micros() as u64;
+        self.control.position_us.store(micros, Ordering::Relaxed);
+    }
+}
+
+impl<S> Iterator for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if !self.control.started.load(Ordering::Relaxed) {
+            if self.control.cancelled.load(Ordering::SeqCst) {
+                return None;
+            }
+            self.control.started.store(true, Ordering::SeqCst);
+        }
+
+        if self.fade_out.is_none() {
+            let fade_ms = self.control.fade_out_ms.load(Ordering::Relaxed);
+            if fade_ms > 0 {
+                let samples_per_sec = self.inner.sample_rate() as u64 * self.inner.channels() as u64;
+                let total = (fade_ms * samples_per_sec / 1000).max(1);
+                self.fade_out = Some(FadeOut { remaining: total, total });
+            }
+        }
+
+        let mut gain = f32::from_bits(self.control.gain.load(Ordering::Relaxed));
+        if let Some(fade) = &mut self.fade_out {
+            if fade.remaining == 0 {
+                return None;
+            }
+            gain *= fade.remaining as f32 / fade.total as f32;
+            fade.remaining -= 1;
+        }
+
+        let sample = self.inner.next()?;
+        self.tick();
+        Some(if gain == 1.0 { sample } else { sample.amplify(gain) })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.clock.restart(pos, self.inner.sample_rate(), self.inner.channels());
+        self.publish();
+        Ok(())
+    }
+}
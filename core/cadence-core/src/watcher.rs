@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::library::{IndexSummary, Library};
+
+/// How long a folder has to be quiet before its changes are applied. Long enough to
+/// cover a copy of an album or a tag editor rewriting a batch of files.
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Keeps the library in step with its folders on disk. Changes are debounced, applied
+/// with `Library::update_paths`, and reported to `on_change` when they touched any track.
+/// What goes wrong along the way is reported to `on_error`.
+pub struct LibraryWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+}
+
+impl LibraryWatcher {
+    /// Start watching every library folder in `library`.
+    pub fn start(
+        library: Arc<Library>,
+        on_change: impl Fn(IndexSummary) + Send + 'static,
+        on_error: impl Fn(anyhow::Error) + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let roots = library.list_libraries()?;
+        let on_error = Arc::new(on_error);
+        let report = Arc::clone(&on_error);
+        let debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| {
+            let paths: Vec<PathBuf> = match result {
+                Ok(events) => events.into_iter().map(|event| event.path).collect(),
+                Err(e) => {
+                    report(anyhow::Error::new(e).context("Library watcher error"));
+                    return;
+                }
+            };
+            match library.update_paths(&paths) {
+                Ok(summary) if summary.changed() => on_change(summary),
+                Ok(_) => {}
+                Err(e) => report(e.context("Failed to apply library changes")),
+            }
+        })
+        .context("Failed to start the library watcher")?;
+
+        let mut watcher = Self { debouncer };
+        for root in roots {
+            // A folder that is missing (e.g. an unmounted drive) is not fatal; it is
+            // picked up again once it is re-indexed.
+            if let Err(e) = watcher.watch(Path::new(&root.path)) {
+                on_error(e);
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// Watch a library folder (recursively), e.g. once it was added.
+    pub fn watch(&mut self, dir: &Path) -> Result<()> {
+        self.debouncer
+            .watcher()
+            .watch(dir, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch {:?}", dir))
+    }
+
+    /// Stop watching a library folder, e.g. once it was removed.
+    pub fn unwatch(&mut self, dir: &Path) -> Result<()> {
+        self.debouncer
+            .watcher()
+            .unwatch(dir)
+            .with_context(|| format!("Failed to stop watching {:?}", dir))
+    }
+}
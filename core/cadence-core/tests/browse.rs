@@ -0,0 +1,105 @@
+use cadence_core::{AlbumSort, ArtistSort, GenreSort, Library, PageRequest, SortOrder, TrackFilter, TrackSort};
+use lofty::prelude::*;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::temp_dir;
+
+/// A silent WAV tagged as `title` by `artist`, on `album` (by `album_artist`) as track
+/// `track` of disc `disc`.
+fn track(dir: &Path, title: &str, artist: &str, (album, album_artist): (&str, &str), (disc, track): (u32, u32), year: u32, genre: &str) {
+    common::track(dir, title, 1, |tag| {
+        tag.set_title(title.into());
+        tag.set_artist(artist.into());
+        tag.set_album(album.into());
+        tag.insert_text(ItemKey::AlbumArtist, album_artist.into());
+        tag.set_disk(disc);
+        tag.set_track(track);
+        tag.set_year(year);
+        tag.set_genre(genre.into());
+    });
+}
+
+fn library(name: &str) -> (PathBuf, Library) {
+    let dir = temp_dir(name);
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    track(&music, "Intro", "Abba", ("Gold", "Abba"), (1, 1), 1992, "Pop");
+    track(&music, "Outro", "Abba", ("Gold", "Abba"), (2, 1), 1992, "Pop");
+    track(&music, "Middle", "Abba", ("Gold", "Abba"), (1, 2), 1992, "Pop");
+    track(&music, "Alone", "beatles", ("Help", "beatles"), (1, 1), 1965, "Rock");
+    track(&music, "Zebra", "Cream", ("Wheels", "Cream"), (1, 1), 1968, "Rock");
+    track(&music, "Guest", "Abba", ("Wheels", "Cream"), (1, 2), 1968, "Rock");
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+    (dir, library)
+}
+
+fn page(cursor: Option<String>, limit: usize, order: SortOrder) -> PageRequest {
+    PageRequest { cursor, limit: Some(limit), order }
+}
+
+#[test]
+fn listings_page_through_everything_in_order() {
+    let (dir, library) = library("paging");
+
+    // Names sort without regard to case, and each page picks up after the last one.
+    let mut names = Vec::new();
+    let mut cursor = None;
+    loop {
+        let artists = library.artists(ArtistSort::Name, &page(cursor, 2, SortOrder::Asc)).unwrap();
+        names.extend(artists.items.into_iter().map(|a| a.name));
+        cursor = artists.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(names, ["Abba", "beatles", "Cream"]);
+
+    let busiest = library.artists(ArtistSort::TrackCount, &page(None, 1, SortOrder::Desc)).unwrap();
+    assert_eq!((busiest.items[0].name.as_str(), busiest.items[0].track_count), ("Abba", 4));
+
+    let newest = library.albums(None, AlbumSort::Year, &page(None, 10, SortOrder::Desc)).unwrap();
+    let titles: Vec<_> = newest.items.iter().map(|a| a.title.as_str()).collect();
+    assert_eq!(titles, ["Gold", "Wheels", "Help"]);
+    assert_eq!(newest.next_cursor, None);
+
+    let genres = library.genres(GenreSort::TrackCount, &page(None, 10, SortOrder::Desc)).unwrap();
+    let genres: Vec<_> = genres.items.iter().map(|g| (g.name.as_str(), g.track_count)).collect();
+    assert_eq!(genres, [("Pop", 3), ("Rock", 3)]);
+
+    let years = library.years(&PageRequest::default()).unwrap();
+    let years: Vec<_> = years.items.iter().map(|y| (y.year, y.track_count)).collect();
+    assert_eq!(years, [(1965, 1), (1968, 2), (1992, 3)]);
+
+    assert!(library.artists(ArtistSort::Name, &page(Some("nonsense".into()), 2, SortOrder::Asc)).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn artists_lead_to_their_albums_and_albums_to_their_tracks() {
+    let (dir, library) = library("drill-down");
+    let artists = library.artists(ArtistSort::Name, &PageRequest::default()).unwrap();
+    let abba = artists.items.iter().find(|a| a.name == "Abba").unwrap();
+    assert_eq!(abba.album_count, 1);
+
+    // A guest appearance on someone else's album counts too.
+    let albums = library.albums(Some(abba.id), AlbumSort::Title, &PageRequest::default()).unwrap();
+    let titles: Vec<_> = albums.items.iter().map(|a| a.title.as_str()).collect();
+    assert_eq!(titles, ["Gold", "Wheels"]);
+    let gold = &albums.items[0];
+    assert_eq!((gold.track_count, gold.year, gold.album_artist.as_str()), (3, Some(1992), "Abba"));
+
+    let filter = TrackFilter { album_id: Some(gold.id), ..Default::default() };
+    let first = library.tracks(&filter, TrackSort::Album, &page(None, 2, SortOrder::Asc)).unwrap();
+    let rest = library.tracks(&filter, TrackSort::Album, &page(first.next_cursor, 2, SortOrder::Asc)).unwrap();
+    let order: Vec<_> = first.items.iter().chain(&rest.items).map(|t| t.title.as_str()).collect();
+    assert_eq!(order, ["Intro", "Middle", "Outro"]);
+    assert_eq!(rest.next_cursor, None);
+
+    let filter = TrackFilter { year: Some(1968), ..Default::default() };
+    let tracks = library.tracks(&filter, TrackSort::Title, &PageRequest::default()).unwrap();
+    let titles: Vec<_> = tracks.items.iter().map(|t| t.title.as_str()).collect();
+    assert_eq!(titles, ["Guest", "Zebra"]);
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,118 @@
+//! Helpers shared by the integration tests. Each test crate uses some of them.
+#![allow(dead_code)]
+
+use cadence_core::{Library, TrackRecord};
+use lofty::config::WriteOptions;
+use lofty::prelude::*;
+use lofty::tag::{Tag, TagType};
+use std::path::{Path, PathBuf};
+
+/// A fresh, empty folder for test `name`, apart from those of other test crates.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let suite = module_path!().split("::").next().unwrap_or("test");
+    let dir = std::env::temp_dir().join(format!("cadence-{suite}-{name}-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `ms` milliseconds of 16-bit silence at `path`.
+pub fn write_silence(path: &Path, channels: u16, sample_rate: u32, ms: u32) {
+    let spec = hound::WavSpec { channels, sample_rate, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for _ in 0..sample_rate as u64 * ms as u64 / 1000 * channels as u64 {
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// Give the file at `path` an ID3v2 tag filled in by `fill`.
+pub fn tag(path: &Path, fill: impl FnOnce(&mut Tag)) {
+    let mut tag = Tag::new(TagType::Id3v2);
+    fill(&mut tag);
+    tag.save_to_path(path, WriteOptions::default()).unwrap();
+}
+
+/// `seconds` of silence at `dir`/`name`.wav (creating `dir`), tagged by `fill`.
+pub fn track(dir: &Path, name: &str, seconds: u32, fill: impl FnOnce(&mut Tag)) -> PathBuf {
+    std::fs::create_dir_all(dir).unwrap();
+    let path = dir.join(format!("{name}.wav"));
+    write_silence(&path, 1, 1_000, seconds * 1_000);
+    tag(&path, fill);
+    path
+}
+
+/// A track to write with `tagged`; fields left unset stay out of its tag.
+pub struct TaggedTrack {
+    dir: PathBuf,
+    name: String,
+    seconds: u32,
+    tag: Tag,
+}
+
+/// A second of silence at `dir`/`title`.wav, titled `title`, once `write` is called.
+pub fn tagged(dir: &Path, title: &str) -> TaggedTrack {
+    let mut tag = Tag::new(TagType::Id3v2);
+    tag.set_title(title.into());
+    TaggedTrack { dir: dir.to_path_buf(), name: title.to_string(), seconds: 1, tag }
+}
+
+impl TaggedTrack {
+    /// Name the file `name`.wav rather than after the title.
+    pub fn file_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn seconds(mut self, seconds: u32) -> Self {
+        self.seconds = seconds;
+        self
+    }
+
+    pub fn artist(mut self, artist: &str) -> Self {
+        self.tag.set_artist(artist.into());
+        self
+    }
+
+    pub fn album(mut self, album: &str) -> Self {
+        self.tag.set_album(album.into());
+        self
+    }
+
+    pub fn genre(mut self, genre: &str) -> Self {
+        self.tag.set_genre(genre.into());
+        self
+    }
+
+    pub fn year(mut self, year: u32) -> Self {
+        self.tag.set_year(year);
+        self
+    }
+
+    pub fn write(self) -> PathBuf {
+        track(&self.dir, &self.name, self.seconds, |tag| *tag = self.tag)
+    }
+}
+
+/// The titles of `tracks`, in their order.
+pub fn titles(tracks: impl IntoIterator<Item = TrackRecord>) -> Vec<String> {
+    tracks.into_iter().map(|t| t.title).collect()
+}
+
+/// The titles of `tracks`, sorted, for results whose order isn't the point.
+pub fn sorted_titles(tracks: impl IntoIterator<Item = TrackRecord>) -> Vec<String> {
+    let mut titles = titles(tracks);
+    titles.sort();
+    titles
+}
+
+/// The titles of playlist `id`'s entries in order; entries not in the library show
+/// as "(path)".
+pub fn playlist_titles(library: &Library, id: i64) -> Vec<String> {
+    library
+        .playlist_items(id)
+        .unwrap()
+        .into_iter()
+        .map(|item| item.track.map_or_else(|| format!("({})", item.path), |t| t.title))
+        .collect()
+}
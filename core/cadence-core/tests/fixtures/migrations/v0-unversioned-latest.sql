@@ -0,0 +1,74 @@
+-- The last schema created without versioning: every table up to albums and genres,
+-- but user_version was never set.
+CREATE TABLE libraries (
+    id   INTEGER PRIMARY KEY,
+    path TEXT UNIQUE NOT NULL
+);
+CREATE TABLE artists (
+    id   INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE albums (
+    id              INTEGER PRIMARY KEY,
+    title           TEXT NOT NULL,
+    album_artist_id INTEGER NOT NULL REFERENCES artists(id),
+    UNIQUE (title, album_artist_id)
+);
+CREATE TABLE genres (
+    id   INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE tracks (
+    id          INTEGER PRIMARY KEY,
+    library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+    path        TEXT UNIQUE NOT NULL,
+    title       TEXT NOT NULL,
+    artist_id   INTEGER NOT NULL REFERENCES artists(id),
+    duration_ms INTEGER NOT NULL,
+    loudness_analyzed INTEGER NOT NULL DEFAULT 0,
+    loudness_lufs     REAL,
+    true_peak         REAL,
+    album_id     INTEGER REFERENCES albums(id),
+    track_number INTEGER,
+    disc_number  INTEGER,
+    year         INTEGER,
+    genre_id     INTEGER REFERENCES genres(id),
+    composer_id  INTEGER REFERENCES artists(id),
+    codec        TEXT,
+    sample_rate  INTEGER,
+    bit_depth    INTEGER,
+    channels     INTEGER,
+    bitrate_kbps INTEGER
+);
+CREATE VIRTUAL TABLE tracks_fts USING fts5 (title, artist, filename);
+CREATE TABLE settings (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE eq_presets (
+    name      TEXT PRIMARY KEY,
+    preamp_db REAL NOT NULL,
+    bands     TEXT NOT NULL
+);
+CREATE TABLE queue_items (
+    id       INTEGER PRIMARY KEY,
+    section  TEXT NOT NULL CHECK (section IN ('history', 'current', 'upcoming')),
+    position INTEGER NOT NULL,
+    path     TEXT NOT NULL,
+    context_index INTEGER
+);
+CREATE TABLE queue_context (
+    id       INTEGER PRIMARY KEY CHECK (id = 0),
+    context  TEXT NOT NULL,
+    paths    TEXT NOT NULL,
+    shuffled INTEGER NOT NULL
+);
+INSERT INTO libraries (id, path) VALUES (1, '/music');
+INSERT INTO artists (id, name) VALUES (1, 'Unknown Artist'), (2, 'Singer');
+INSERT INTO tracks (id, library_id, path, title, artist_id, duration_ms)
+    VALUES (1, 1, '/music/song.flac', 'Song', 2, 180000);
+INSERT INTO tracks_fts (rowid, title, artist, filename) VALUES (1, 'Song', 'Singer', 'song');
+UPDATE tracks SET loudness_analyzed = 1, loudness_lufs = -9.5, true_peak = 0.98 WHERE id = 1;
+INSERT INTO settings (key, value) VALUES ('volume', '0.5');
+INSERT INTO eq_presets (name, preamp_db, bands) VALUES ('Mine', -2.0, '[]');
+INSERT INTO queue_items (id, section, position, path) VALUES (7, 'current', 0, '/music/song.flac');
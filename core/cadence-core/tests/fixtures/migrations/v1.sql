@@ -0,0 +1,24 @@
+-- Version 1: libraries, artists and tracks.
+CREATE TABLE libraries (
+    id   INTEGER PRIMARY KEY,
+    path TEXT UNIQUE NOT NULL
+);
+CREATE TABLE artists (
+    id   INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE tracks (
+    id          INTEGER PRIMARY KEY,
+    library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+    path        TEXT UNIQUE NOT NULL,
+    title       TEXT NOT NULL,
+    artist_id   INTEGER NOT NULL REFERENCES artists(id),
+    duration_ms INTEGER NOT NULL
+);
+CREATE VIRTUAL TABLE tracks_fts USING fts5 (title, artist, filename);
+INSERT INTO libraries (id, path) VALUES (1, '/music');
+INSERT INTO artists (id, name) VALUES (1, 'Unknown Artist'), (2, 'Singer');
+INSERT INTO tracks (id, library_id, path, title, artist_id, duration_ms)
+    VALUES (1, 1, '/music/song.flac', 'Song', 2, 180000);
+INSERT INTO tracks_fts (rowid, title, artist, filename) VALUES (1, 'Song', 'Singer', 'song');
+PRAGMA user_version = 1;
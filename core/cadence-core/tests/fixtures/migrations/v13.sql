@@ -0,0 +1,125 @@
+-- Version 13: play counts and smart playlists.
+CREATE TABLE libraries (
+    id   INTEGER PRIMARY KEY,
+    path TEXT UNIQUE NOT NULL
+);
+CREATE TABLE artists (
+    id   INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE albums (
+    id              INTEGER PRIMARY KEY,
+    title           TEXT NOT NULL,
+    album_artist_id INTEGER NOT NULL REFERENCES artists(id),
+    UNIQUE (title, album_artist_id)
+);
+CREATE TABLE genres (
+    id   INTEGER PRIMARY KEY,
+    name TEXT UNIQUE NOT NULL
+);
+CREATE TABLE artwork (
+    id   INTEGER PRIMARY KEY,
+    hash TEXT UNIQUE NOT NULL,
+    mime TEXT NOT NULL
+);
+CREATE TABLE tracks (
+    id          INTEGER PRIMARY KEY,
+    library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+    path        TEXT UNIQUE NOT NULL,
+    title       TEXT NOT NULL,
+    artist_id   INTEGER NOT NULL REFERENCES artists(id),
+    duration_ms INTEGER NOT NULL,
+    loudness_analyzed INTEGER NOT NULL DEFAULT 0,
+    loudness_lufs     REAL,
+    true_peak         REAL,
+    album_id     INTEGER REFERENCES albums(id),
+    track_number INTEGER,
+    disc_number  INTEGER,
+    year         INTEGER,
+    genre_id     INTEGER REFERENCES genres(id),
+    composer_id  INTEGER REFERENCES artists(id),
+    codec        TEXT,
+    sample_rate  INTEGER,
+    bit_depth    INTEGER,
+    channels     INTEGER,
+    bitrate_kbps INTEGER,
+    file_mtime_ns INTEGER,
+    file_size     INTEGER,
+    artwork_id    INTEGER REFERENCES artwork(id),
+    added_at_ms       INTEGER,
+    play_count        INTEGER NOT NULL DEFAULT 0,
+    last_played_at_ms INTEGER
+);
+CREATE VIRTUAL TABLE tracks_fts USING fts5 (
+    title, artist, album, album_artist, genre, composer, filename,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+CREATE VIRTUAL TABLE tracks_fts_terms USING fts5vocab (tracks_fts, 'row');
+CREATE TABLE settings (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE eq_presets (
+    name      TEXT PRIMARY KEY,
+    preamp_db REAL NOT NULL,
+    bands     TEXT NOT NULL
+);
+CREATE TABLE queue_items (
+    id       INTEGER PRIMARY KEY,
+    section  TEXT NOT NULL CHECK (section IN ('history', 'current', 'upcoming')),
+    position INTEGER NOT NULL,
+    path     TEXT NOT NULL,
+    context_index INTEGER
+);
+CREATE TABLE queue_context (
+    id       INTEGER PRIMARY KEY CHECK (id = 0),
+    context  TEXT NOT NULL,
+    paths    TEXT NOT NULL,
+    shuffled INTEGER NOT NULL
+);
+CREATE TABLE scan_issues (
+    id          INTEGER PRIMARY KEY,
+    library_id  INTEGER NOT NULL REFERENCES libraries(id) ON DELETE CASCADE,
+    path        TEXT NOT NULL,
+    kind        TEXT NOT NULL,
+    message     TEXT NOT NULL,
+    found_at_ms INTEGER NOT NULL
+);
+CREATE INDEX scan_issues_path ON scan_issues (path);
+CREATE TABLE playlists (
+    id            INTEGER PRIMARY KEY,
+    name          TEXT NOT NULL,
+    created_at_ms INTEGER NOT NULL,
+    updated_at_ms INTEGER NOT NULL
+);
+CREATE TABLE playlist_items (
+    id          INTEGER PRIMARY KEY,
+    playlist_id INTEGER NOT NULL REFERENCES playlists(id) ON DELETE CASCADE,
+    position    INTEGER NOT NULL,
+    path        TEXT NOT NULL
+);
+CREATE INDEX playlist_items_order ON playlist_items (playlist_id, position);
+CREATE TABLE smart_playlists (
+    id            INTEGER PRIMARY KEY,
+    name          TEXT NOT NULL,
+    rules         TEXT NOT NULL,
+    created_at_ms INTEGER NOT NULL,
+    updated_at_ms INTEGER NOT NULL
+);
+INSERT INTO libraries (id, path) VALUES (1, '/music');
+INSERT INTO artists (id, name) VALUES (1, 'Unknown Artist'), (2, 'Singer');
+INSERT INTO tracks (id, library_id, path, title, artist_id, duration_ms)
+    VALUES (1, 1, '/music/song.flac', 'Song', 2, 180000);
+INSERT INTO tracks_fts (rowid, title, artist, filename) VALUES (1, 'Song', 'Singer', 'song');
+UPDATE tracks SET loudness_analyzed = 1, loudness_lufs = -9.5, true_peak = 0.98 WHERE id = 1;
+INSERT INTO settings (key, value) VALUES ('volume', '0.5');
+INSERT INTO eq_presets (name, preamp_db, bands) VALUES ('Mine', -2.0, '[]');
+INSERT INTO queue_items (id, section, position, path) VALUES (7, 'current', 0, '/music/song.flac');
+INSERT INTO scan_issues (library_id, path, kind, message, found_at_ms)
+    VALUES (1, '/music/broken.flac', 'corrupt', 'Bad header', 0);
+INSERT INTO playlists (id, name, created_at_ms, updated_at_ms) VALUES (1, 'Mix', 0, 0);
+INSERT INTO playlist_items (playlist_id, position, path) VALUES (1, 0, '/music/song.flac');
+UPDATE tracks SET added_at_ms = 0, play_count = 3, last_played_at_ms = 0 WHERE id = 1;
+INSERT INTO smart_playlists (id, name, rules, created_at_ms, updated_at_ms)
+    VALUES (1, 'Played', '{"rule":{"type":"number","field":"play_count","op":"gt","value":0},"sort":"title","order":"asc","limit":null}', 0, 0);
+PRAGMA user_version = 13;
@@ -0,0 +1,249 @@
+use cadence_core::watcher::DEBOUNCE;
+use cadence_core::{IndexSummary, Library, LibraryWatcher, ScanIssueKind};
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+mod common;
+use common::{tag, temp_dir, write_silence};
+
+fn png(width: u32, height: u32, shade: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    image::RgbImage::from_pixel(width, height, image::Rgb([shade, 0, 0]))
+        .write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png)
+        .unwrap();
+    data
+}
+
+#[test]
+fn indexing_reads_album_and_format_metadata() {
+    let dir = temp_dir("metadata");
+    let tagged = dir.join("tagged.wav");
+    write_silence(&tagged, 2, 48_000, 100);
+    tag(&tagged, |tag| {
+        tag.set_title("Song".into());
+        tag.set_artist("Singer".into());
+        tag.set_album("Record".into());
+        tag.insert_text(ItemKey::AlbumArtist, "Band".into());
+        tag.insert_text(ItemKey::Composer, "Writer".into());
+        tag.set_track(3);
+        tag.set_disk(2);
+        tag.set_year(1999);
+        tag.set_genre("Jazz".into());
+    });
+    write_silence(&dir.join("bare.wav"), 1, 22_050, 100);
+
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    assert_eq!(library.index_directory(&dir).unwrap().added, 2);
+
+    let song = library.search("song").unwrap().remove(0);
+    assert_eq!(song.artist, "Singer");
+    assert_eq!(song.album.as_deref(), Some("Record"));
+    assert_eq!(song.album_artist.as_deref(), Some("Band"));
+    assert_eq!(song.composer.as_deref(), Some("Writer"));
+    assert_eq!((song.track_number, song.disc_number, song.year), (Some(3), Some(2), Some(1999)));
+    assert_eq!(song.genre.as_deref(), Some("Jazz"));
+    assert_eq!(song.codec.as_deref(), Some("PCM"));
+    assert_eq!((song.sample_rate, song.bit_depth, song.channels), (Some(48_000), Some(16), Some(2)));
+
+    let bare = library.search("bare").unwrap().remove(0);
+    assert_eq!(bare.album, None);
+    assert_eq!(bare.genre, None);
+    assert_eq!((bare.sample_rate, bare.channels), (Some(22_050), Some(1)));
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn rescans_only_touch_changed_files_and_keep_ids() {
+    let dir = temp_dir("rescan");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    for name in ["keep", "change", "vanish"] {
+        write_silence(&music.join(format!("{name}.wav")), 1, 8_000, 100);
+    }
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    let first = library.index_directory(&music).unwrap();
+    assert_eq!(first, IndexSummary { added: 3, ..Default::default() });
+    let id_of = |title: &str| library.search(title).unwrap()[0].id;
+    let (keep, change) = (id_of("keep"), id_of("change"));
+
+    // A different size marks the file as changed whatever its mtime.
+    write_silence(&music.join("change.wav"), 2, 8_000, 100);
+    std::fs::remove_file(music.join("vanish.wav")).unwrap();
+    write_silence(&music.join("new.wav"), 1, 8_000, 100);
+
+    let second = library.index_directory(&music).unwrap();
+    assert_eq!(second, IndexSummary { added: 1, updated: 1, removed: 1, unchanged: 1, ..Default::default() });
+    assert_eq!(id_of("keep"), keep);
+    let changed = library.search("change").unwrap().remove(0);
+    assert_eq!((changed.id, changed.channels), (change, Some(2)));
+    assert!(library.search("vanish").unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn updates_apply_to_changed_paths_only() {
+    let dir = temp_dir("update");
+    let music = dir.join("music");
+    std::fs::create_dir_all(music.join("album")).unwrap();
+    write_silence(&music.join("album/one.wav"), 1, 8_000, 100);
+    write_silence(&music.join("loose.wav"), 1, 8_000, 100);
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+
+    write_silence(&music.join("album/two.wav"), 1, 8_000, 100);
+    std::fs::remove_file(music.join("loose.wav")).unwrap();
+    let summary = library
+        .update_paths(&[music.join("album/two.wav"), music.join("loose.wav"), dir.join("elsewhere.wav")])
+        .unwrap();
+    assert_eq!(summary, IndexSummary { added: 1, removed: 1, ..Default::default() });
+
+    // A removed folder takes everything under it along.
+    std::fs::remove_dir_all(music.join("album")).unwrap();
+    let summary = library.update_paths(&[music.join("album")]).unwrap();
+    assert_eq!(summary.removed, 2);
+    assert!(library.all_track_paths().unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn watcher_picks_up_new_files() {
+    let dir = temp_dir("watch");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    let library = Arc::new(Library::open(&dir.join("library.db")).unwrap());
+    library.index_directory(&music).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let _watcher = LibraryWatcher::start(
+        Arc::clone(&library),
+        move |summary| {
+            tx.send(summary).ok();
+        },
+        |e| panic!("{e:#}"),
+    )
+    .unwrap();
+    write_silence(&music.join("new.wav"), 1, 8_000, 100);
+
+    let summary = rx.recv_timeout(DEBOUNCE * 5).expect("no change reported");
+    assert_eq!(summary.added, 1);
+    assert_eq!(library.search("new").unwrap().len(), 1);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn scan_reports_progress_and_can_be_cancelled() {
+    let dir = temp_dir("progress");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    for i in 0..3 {
+        write_silence(&music.join(format!("track{i}.wav")), 1, 8_000, 100);
+    }
+    std::fs::write(music.join("broken.flac"), b"not audio").unwrap();
+    let library = Library::open(&dir.join("library.db")).unwrap();
+
+    // Cancelled once the walk is done, before anything was written.
+    let cancel = AtomicBool::new(false);
+    let summary = library.scan_directory(&music, |_| cancel.store(true, Ordering::Relaxed), &cancel).unwrap();
+    assert!(summary.cancelled);
+    assert!(library.all_track_paths().unwrap().is_empty());
+
+    let mut reports = Vec::new();
+    let summary = library.scan_directory(&music, |p| reports.push(p), &AtomicBool::new(false)).unwrap();
+    assert_eq!(summary, IndexSummary { added: 4, failed: 1, ..Default::default() });
+    let last = reports.last().unwrap();
+    assert_eq!((last.seen, last.processed, last.failed), (4, 4, 1));
+    // Unreadable files are still listed, under their file name.
+    assert_eq!(library.search("broken").unwrap().len(), 1);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn scan_issues_are_recorded_and_cleared_once_fixed() {
+    let dir = temp_dir("issues");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    std::fs::write(music.join("broken.flac"), b"not audio").unwrap();
+    // A single sample, too short to last a millisecond.
+    let spec = hound::WavSpec { channels: 1, sample_rate: 8_000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::create(music.join("blip.wav"), spec).unwrap();
+    writer.write_sample(0i16).unwrap();
+    writer.finalize().unwrap();
+    write_silence(&music.join("fine.wav"), 1, 8_000, 100);
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+
+    let issues = library.scan_issues(None).unwrap();
+    let found: Vec<(&str, ScanIssueKind)> = issues
+        .iter()
+        .map(|issue| (Path::new(&issue.path).file_name().unwrap().to_str().unwrap(), issue.kind))
+        .collect();
+    assert_eq!(found, [("blip.wav", ScanIssueKind::ZeroDuration), ("broken.flac", ScanIssueKind::Corrupt)]);
+    assert!(!issues[1].message.is_empty());
+
+    std::fs::remove_file(music.join("broken.flac")).unwrap();
+    write_silence(&music.join("blip.wav"), 1, 8_000, 100);
+    library.index_directory(&music).unwrap();
+    assert!(library.scan_issues(Some(issues[0].library_id)).unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn cover_art_is_found_cached_once_and_thumbnailed() {
+    let dir = temp_dir("artwork");
+    let music = dir.join("music");
+    let (embedded, folder) = (music.join("embedded"), music.join("folder"));
+    std::fs::create_dir_all(&embedded).unwrap();
+    std::fs::create_dir_all(&folder).unwrap();
+
+    let cover = png(600, 400, 200);
+    for name in ["one", "two"] {
+        let path = embedded.join(format!("{name}.wav"));
+        write_silence(&path, 1, 8_000, 100);
+        tag(&path, |tag| {
+            tag.set_title(name.into());
+            tag.push_picture(Picture::new_unchecked(PictureType::CoverFront, Some(MimeType::Png), None, cover.clone()));
+        });
+    }
+    write_silence(&folder.join("three.wav"), 1, 8_000, 100);
+    std::fs::write(folder.join("Cover.png"), png(100, 100, 50)).unwrap();
+
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+    let art = |name: &str| library.search(name).unwrap().remove(0).artwork_id.unwrap();
+
+    // The same picture embedded in two files is stored once.
+    assert_eq!(art("one"), art("two"));
+    assert_ne!(art("one"), art("three"));
+    assert_eq!(std::fs::read_dir(dir.join("artwork")).unwrap().count(), 4);
+
+    let original = library.artwork(art("one"), false).unwrap().unwrap();
+    assert_eq!((original.mime.as_str(), original.data), ("image/png", cover));
+    let thumbnail = library.artwork(art("one"), true).unwrap().unwrap();
+    assert_eq!(thumbnail.mime, "image/jpeg");
+    let thumbnail = image::load_from_memory(&thumbnail.data).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (256, 171));
+
+    assert_eq!(library.artwork_id(&folder.join("three.wav")).unwrap(), Some(art("three")));
+    assert!(library.artwork(art("three") + 100, false).unwrap().is_none());
+
+    // Replacing a folder's cover (or taking it away) reaches the tracks next to it.
+    let three = art("three");
+    std::fs::write(folder.join("Cover.png"), png(100, 100, 90)).unwrap();
+    let summary = library.update_paths(&[folder.join("Cover.png")]).unwrap();
+    assert_eq!(summary.updated, 1);
+    assert_ne!(art("three"), three);
+    std::fs::remove_file(folder.join("Cover.png")).unwrap();
+    library.update_paths(&[folder.join("Cover.png")]).unwrap();
+    assert_eq!(library.search("three").unwrap()[0].artwork_id, None);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,50 @@
+use cadence_core::loudness::{self, REFERENCE_LUFS};
+use cadence_core::Library;
+use std::path::Path;
+
+mod common;
+use common::temp_dir;
+
+/// Write `seconds` of a stereo 1 kHz sine at `dbfs` (peak level), 48 kHz float.
+fn write_sine(path: &Path, seconds: u32, dbfs: f32) {
+    let spec = hound::WavSpec { channels: 2, sample_rate: 48_000, bits_per_sample: 32, sample_format: hound::SampleFormat::Float };
+    let amplitude = 10f32.powf(dbfs / 20.0);
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for n in 0..48_000 * seconds {
+        let sample = (n as f32 / 48_000.0 * 1_000.0 * std::f32::consts::TAU).sin() * amplitude;
+        writer.write_sample(sample).unwrap();
+        writer.write_sample(sample).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+#[test]
+fn a_sine_measures_as_its_level() {
+    let dir = temp_dir("sine");
+    let path = dir.join("sine.wav");
+    write_sine(&path, 5, -20.0);
+
+    // BS.1770 is calibrated so a 1 kHz sine in both channels reads its peak level.
+    let measured = loudness::analyze_file(&path).unwrap();
+    let lufs = measured.integrated_lufs.unwrap();
+    assert!((lufs + 20.0).abs() < 0.1, "{lufs}");
+    assert!((measured.true_peak - 0.1).abs() < 0.001, "{}", measured.true_peak);
+    assert!((measured.gain_db().unwrap() - (REFERENCE_LUFS + 20.0)).abs() < 0.1);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn files_that_fail_to_decode_are_tried_again() {
+    let dir = temp_dir("failed");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    write_sine(&music.join("sine.wav"), 1, -20.0);
+    std::fs::write(music.join("broken.mp3"), b"not really an mp3").unwrap();
+
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+    assert_eq!(library.analyze_loudness(|_| {}).unwrap(), 2);
+    assert!(library.analyzed_replay_gain(&music.join("sine.wav")).unwrap().is_some());
+    assert_eq!(library.analyze_loudness(|_| {}).unwrap(), 1);
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,169 @@
+use cadence_core::migrations::{backup_path, SCHEMA_VERSION};
+use cadence_core::Library;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+mod common;
+use common::temp_dir;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/migrations");
+
+fn temp_db(name: &str) -> PathBuf {
+    temp_dir(name).join("cadence.db")
+}
+
+/// A database at the fixture's version, holding its rows.
+fn db_from_fixture(fixture: &str) -> PathBuf {
+    let db = temp_db(fixture);
+    let sql = std::fs::read_to_string(Path::new(FIXTURES).join(format!("{fixture}.sql"))).unwrap();
+    Connection::open(&db).unwrap().execute_batch(&sql).unwrap();
+    db
+}
+
+fn remove(db: &Path) {
+    std::fs::remove_dir_all(db.parent().unwrap()).ok();
+}
+
+fn user_version(conn: &Connection) -> u32 {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+}
+
+/// Every table with its column names, sorted, to compare schemas regardless of column order.
+fn schema(conn: &Connection) -> Vec<(String, Vec<String>)> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name").unwrap();
+    let tables: Vec<String> = stmt.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+    tables
+        .into_iter()
+        .map(|table| {
+            let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1) ORDER BY name").unwrap();
+            let columns = stmt.query_map([&table], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+            (table, columns)
+        })
+        .collect()
+}
+
+/// Every index by name, with its table.
+fn indexes(conn: &Connection) -> Vec<(String, String)> {
+    let mut stmt = conn
+        .prepare("SELECT name, tbl_name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL ORDER BY name")
+        .unwrap();
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(Result::unwrap).collect()
+}
+
+#[test]
+fn fresh_database_starts_at_the_current_version_without_a_backup() {
+    let db = temp_db("fresh");
+    Library::open(&db).unwrap();
+    assert_eq!(user_version(&Connection::open(&db).unwrap()), SCHEMA_VERSION);
+    assert!(!backup_path(&db, 0).exists());
+    remove(&db);
+}
+
+#[test]
+fn every_historic_version_upgrades_to_the_current_schema() {
+    let fresh = temp_db("reference");
+    Library::open(&fresh).unwrap();
+    let expected = schema(&Connection::open(&fresh).unwrap());
+    let expected_indexes = indexes(&Connection::open(&fresh).unwrap());
+
+    for (fixture, version) in [
+        ("v0-baseline", 0),
+        ("v0-unversioned-latest", 0),
+        ("v1", 1),
+        ("v2", 2),
+        ("v3", 3),
+        ("v4", 4),
+        ("v5", 5),
+        ("v6", 6),
+        ("v7", 7),
+        ("v8", 8),
+        ("v9", 9),
+        ("v10", 10),
+        ("v11", 11),
+        ("v12", 12),
+        ("v13", 13),
+        ("v14", 14),
+    ] {
+        let db = db_from_fixture(fixture);
+        let library = Library::open(&db).unwrap_or_else(|e| panic!("{fixture}: {e:#}"));
+
+        let conn = Connection::open(&db).unwrap();
+        assert_eq!(user_version(&conn), SCHEMA_VERSION, "{fixture}");
+        assert_eq!(schema(&conn), expected, "{fixture}");
+        assert_eq!(indexes(&conn), expected_indexes, "{fixture}");
+
+        // Rows from before the upgrade are still there and usable.
+        let found = library.search("song").unwrap();
+        assert_eq!(found.len(), 1, "{fixture}");
+        assert_eq!(found[0].artist, "Singer", "{fixture}");
+        if version >= 3 {
+            assert_eq!(library.setting("volume").unwrap().as_deref(), Some("0.5"), "{fixture}");
+        }
+        if version >= 5 {
+            assert_eq!(library.load_queue().unwrap().current().unwrap().id, 7, "{fixture}");
+        }
+
+        // The backup is the database as it was before the upgrade.
+        let backup = Connection::open(backup_path(&db, version)).unwrap();
+        assert_eq!(user_version(&backup), version, "{fixture}");
+        let tracks: i64 = backup.query_row("SELECT COUNT(*) FROM tracks", [], |row| row.get(0)).unwrap();
+        assert_eq!(tracks, 1, "{fixture}");
+        remove(&db);
+    }
+    remove(&fresh);
+}
+
+#[test]
+fn upgrading_finds_cover_art_without_losing_measured_loudness() {
+    let db = db_from_fixture("v8");
+    let music = db.parent().unwrap().join("music");
+    let song = common::track(&music, "song", 1, |_| {});
+    image::RgbImage::from_pixel(8, 8, image::Rgb([200, 0, 0])).save(music.join("cover.png")).unwrap();
+
+    // The fixture's track as the last scan before cover art left it: stamped and analyzed.
+    let metadata = std::fs::metadata(&song).unwrap();
+    let mtime_ns = metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64;
+    let conn = Connection::open(&db).unwrap();
+    conn.execute("UPDATE libraries SET path = ?1", [music.to_string_lossy()]).unwrap();
+    conn.execute(
+        "UPDATE tracks SET path = ?1, file_mtime_ns = ?2, file_size = ?3",
+        rusqlite::params![song.to_string_lossy(), mtime_ns, metadata.len() as i64],
+    )
+    .unwrap();
+    drop(conn);
+
+    let library = Library::open(&db).unwrap();
+    let measured = library.analyzed_replay_gain(&song).unwrap();
+    assert!(measured.is_some());
+
+    // The first scan probes the unchanged file for its cover and keeps its loudness...
+    assert_eq!(library.index_directory(&music).unwrap().updated, 1);
+    assert!(library.artwork_id(&song).unwrap().is_some());
+    assert_eq!(library.analyzed_replay_gain(&song).unwrap(), measured);
+    // ...and later ones skip it again.
+    assert_eq!(library.index_directory(&music).unwrap().unchanged, 1);
+    remove(&db);
+}
+
+#[test]
+fn reopening_an_upgraded_database_changes_nothing() {
+    let db = db_from_fixture("v1");
+    Library::open(&db).unwrap();
+    std::fs::remove_file(backup_path(&db, 1)).unwrap();
+
+    Library::open(&db).unwrap();
+    assert!(!backup_path(&db, SCHEMA_VERSION).exists());
+    remove(&db);
+}
+
+#[test]
+fn database_from_a_newer_build_is_refused() {
+    let db = temp_db("newer");
+    Library::open(&db).unwrap();
+    Connection::open(&db).unwrap()
+        .execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+        .unwrap();
+    assert!(Library::open(&db).is_err());
+    remove(&db);
+}
@@ -0,0 +1,172 @@
+use cadence_core::output::{NullOutput, WavOutput};
+use cadence_core::eq::BandKind;
+use cadence_core::{EqBand, EqSettings, Player, PlayerEvent};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::temp_dir;
+
+/// Consume samples as fast as possible so tests don't wait in real time.
+const UNTHROTTLED: f32 = f32::INFINITY;
+
+/// Write a mono 44.1 kHz sine tone lasting `ms` milliseconds.
+fn write_tone(path: &Path, ms: u32, freq: f32) {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 44_100,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).unwrap();
+    for n in 0..44_100 * ms / 1000 {
+        let t = n as f32 / 44_100.0;
+        let sample = (t * freq * std::f32::consts::TAU).sin() * 0.5;
+        writer.write_sample((sample * i16::MAX as f32) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+}
+
+/// Poll `player` until `done` holds, collecting events on the way.
+fn run_until(player: &mut Player, mut done: impl FnMut(&Player, &[PlayerEvent]) -> bool) -> Vec<PlayerEvent> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut events = Vec::new();
+    while Instant::now() < deadline {
+        events.extend(player.poll_events());
+        if done(player, &events) {
+            return events;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    panic!("timed out waiting for the player");
+}
+
+#[test]
+fn plays_a_track_to_the_end_on_the_null_output() {
+    let dir = temp_dir("end");
+    let track = dir.join("end.wav");
+    write_tone(&track, 500, 440.0);
+
+    let mut player = Player::with_output(Box::new(NullOutput::new(UNTHROTTLED))).unwrap();
+    let info = player.load_and_play(track.clone(), None).unwrap();
+    assert_eq!(info.duration_ms, Some(500));
+    assert!(!info.duration_estimated);
+    assert!(player.current_track().is_some());
+
+    run_until(&mut player, |p, _| p.is_finished());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn queued_track_takes_over_without_a_command() {
+    let dir = temp_dir("queued");
+    let first = dir.join("first.wav");
+    let second = dir.join("second.wav");
+    write_tone(&first, 300, 440.0);
+    write_tone(&second, 300, 660.0);
+
+    let mut player = Player::with_output(Box::new(NullOutput::new(UNTHROTTLED))).unwrap();
+    player.load_and_play(first.clone(), None).unwrap();
+    player.queue_next(second.clone(), None).unwrap();
+    assert_eq!(player.next_track().map(|t| t.path.clone()), Some(second.clone()));
+
+    let events = run_until(&mut player, |_, events| !events.is_empty());
+    match &events[0] {
+        PlayerEvent::TrackChanged { track } => assert_eq!(track.path, second),
+        other => panic!("unexpected event {other:?}"),
+    }
+    assert_eq!(player.current_track().unwrap().info.path, second);
+    assert!(player.next_track().is_none());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn wav_output_records_what_was_played() {
+    let dir = temp_dir("recording");
+    let track = dir.join("source.wav");
+    let recording = dir.join("recording.wav");
+    write_tone(&track, 200, 440.0);
+
+    let mut player = Player::with_output(Box::new(WavOutput::create(&recording, UNTHROTTLED).unwrap())).unwrap();
+    player.load_and_play(track.clone(), None).unwrap();
+    run_until(&mut player, |p, _| p.is_finished());
+    drop(player);
+
+    let mut reader = hound::WavReader::open(&recording).unwrap();
+    assert_eq!(reader.spec().channels, 2);
+    assert!(reader.samples::<i16>().any(|s| s.unwrap() != 0));
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn position_follows_consumed_samples() {
+    let dir = temp_dir("position");
+    let track = dir.join("position.wav");
+    write_tone(&track, 1000, 440.0);
+
+    let mut player = Player::with_output(Box::new(NullOutput::new(UNTHROTTLED))).unwrap();
+    player.load_and_play(track.clone(), None).unwrap();
+    player.pause();
+    player.seek(400).unwrap();
+    assert_eq!(player.current_position_ms(), 400);
+
+    player.resume();
+    run_until(&mut player, |p, _| p.is_finished());
+    // Unthrottled, a wall clock would be far behind; the sample count lands on the end.
+    let end = player.current_position_ms();
+    assert!((990..=1000).contains(&end));
+
+    // Nothing left to consume, so the position holds still.
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(player.current_position_ms(), end);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+/// Root mean square of a recording's samples, ignoring the silence the output
+/// records while no track is playing.
+fn rms(path: &Path) -> f64 {
+    let mut reader = hound::WavReader::open(path).unwrap();
+    let samples: Vec<f64> = reader.samples::<i16>()
+        .map(|s| s.unwrap())
+        .filter(|&s| s != 0)
+        .map(|s| s as f64 / i16::MAX as f64)
+        .collect();
+    (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+/// Play `track` into a WAV recording (`name`, next to it) with `eq` applied and return
+/// the recording's RMS.
+fn record_with_eq(track: &Path, eq: EqSettings, name: &str) -> f64 {
+    let recording = track.with_file_name(name);
+    let mut player = Player::with_output(Box::new(WavOutput::create(&recording, UNTHROTTLED).unwrap())).unwrap();
+    player.set_equalizer(eq);
+    player.load_and_play(track.to_path_buf(), None).unwrap();
+    run_until(&mut player, |p, _| p.is_finished());
+    drop(player);
+
+    let level = rms(&recording);
+    std::fs::remove_file(recording).ok();
+    level
+}
+
+#[test]
+fn equalizer_cuts_the_band_it_is_set_to() {
+    let dir = temp_dir("eq");
+    let track = dir.join("eq.wav");
+    write_tone(&track, 500, 1000.0);
+
+    let flat = record_with_eq(&track, EqSettings::default(), "eq-flat.wav");
+    let cut = EqSettings {
+        enabled: true,
+        preamp_db: 0.0,
+        bands: vec![EqBand { kind: BandKind::Peaking, freq_hz: 1000.0, gain_db: -12.0, q: 1.0 }],
+    };
+    let cut = record_with_eq(&track, cut, "eq-cut.wav");
+
+    // -12 dB is a quarter of the amplitude.
+    let ratio = cut / flat;
+    assert!((0.2..0.3).contains(&ratio), "ratio {ratio}");
+    std::fs::remove_dir_all(&dir).ok();
+}
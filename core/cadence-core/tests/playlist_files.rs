@@ -0,0 +1,134 @@
+use cadence_core::Library;
+use std::path::PathBuf;
+
+mod common;
+use common::{playlist_titles, tagged, temp_dir};
+
+/// A library of three tracks and a folder for playlists next to it.
+fn setup(name: &str) -> (PathBuf, Library, [PathBuf; 3]) {
+    let dir = temp_dir(name);
+    let music = dir.join("music");
+    let miles = music.join("Miles Davis");
+    let so_what = tagged(&miles, "So What").artist("Miles Davis").album("Kind of Blue").write();
+    let freddie = tagged(&miles, "Freddie Freeloader").artist("Miles Davis").album("Kind of Blue").write();
+    let paranoid = tagged(&music.join("Black Sabbath"), "Paranoid").artist("Black Sabbath").album("Paranoid").write();
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+    std::fs::create_dir_all(dir.join("lists")).unwrap();
+    (dir, library, [so_what, freddie, paranoid])
+}
+
+#[test]
+fn entries_resolve_by_path_then_relative_path_then_tags() {
+    let (dir, library, [so_what, ..]) = setup("import");
+
+    let m3u = dir.join("lists").join("Late night.m3u8");
+    std::fs::write(
+        &m3u,
+        format!(
+            "\u{feff}#EXTM3U\n\
+             #EXTINF:1,Miles Davis - So What\n{}\n\
+             #EXTINF:-1,Freddie Freeloader\n../music/Miles Davis/Freddie Freeloader.wav\n\
+             #EXTINF:1 tvg-id=\"x\",Black Sabbath - Paranoid\nC:\\Old PC\\Paranoid.mp3\n\
+             \n#EXTINF:200,Nobody - Missing\nmissing.mp3\nhttp://radio.example/stream\n",
+            so_what.display()
+        ),
+    )
+    .unwrap();
+    let import = library.import_playlist(&m3u, None).unwrap();
+    assert_eq!(import.playlist.name, "Late night");
+    assert_eq!(import.resolved, 3);
+    assert_eq!(playlist_titles(&library, import.playlist.id), ["So What", "Freddie Freeloader", "Paranoid"]);
+    let unresolved: Vec<_> = import.unresolved.iter().map(|u| (u.index, u.entry.title.as_deref())).collect();
+    assert_eq!(unresolved, [(3, Some("Missing")), (4, None)]);
+    assert_eq!(import.unresolved[0].entry.duration_ms, Some(200_000));
+
+    // Windows-style relative paths, entries out of order, in Latin-1.
+    let pls = dir.join("lists").join("old.pls");
+    let mut text = b"[playlist]\r\nFile2=../music/Black Sabbath/Paranoid.wav\r\nTitle1=Caf\xe9\r\n".to_vec();
+    text.extend_from_slice(b"File1=..\\music\\Miles Davis\\So What.wav\r\nNumberOfEntries=3\r\nFile3=gone.mp3\r\nVersion=2\r\n");
+    std::fs::write(&pls, text).unwrap();
+    let import = library.import_playlist(&pls, Some("Old")).unwrap();
+    assert_eq!(playlist_titles(&library, import.playlist.id), ["So What", "Paranoid"]);
+    assert_eq!(import.unresolved[0].entry.location.as_deref(), Some("gone.mp3"));
+
+    let xspf = dir.join("lists").join("mix.xspf");
+    std::fs::write(
+        &xspf,
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track><location>../music/Black%20Sabbath/Paranoid.wav</location></track>
+    <track><title>freddie freeloader</title><creator>MILES DAVIS</creator></track>
+    <track><location>{}</location><title>Ignored</title></track>
+    <track><title>Rock &amp; Roll</title><creator>Led Zeppelin</creator></track>
+  </trackList>
+</playlist>"#,
+            url::Url::from_file_path(&so_what).unwrap()
+        ),
+    )
+    .unwrap();
+    let import = library.import_playlist(&xspf, None).unwrap();
+    assert_eq!(playlist_titles(&library, import.playlist.id), ["Paranoid", "Freddie Freeloader", "So What"]);
+    assert_eq!(import.unresolved.len(), 1);
+    assert_eq!(import.unresolved[0].entry.title.as_deref(), Some("Rock & Roll"));
+
+    assert!(library.import_playlist(&dir.join("lists").join("notes.txt"), None).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn exported_playlists_read_back_the_same() {
+    let (dir, library, [so_what, freddie, paranoid]) = setup("export");
+    let playlist = library.create_playlist("Mix & Match").unwrap();
+    library.add_to_playlist(playlist.id, &[paranoid, so_what.clone(), freddie], None).unwrap();
+    let expected = playlist_titles(&library, playlist.id);
+
+    for name in ["mix.m3u", "mix.m3u8", "mix.pls", "mix.xspf"] {
+        let file = dir.join("lists").join(name);
+        library.export_playlist(playlist.id, &file, true).unwrap();
+        let text = std::fs::read_to_string(&file).unwrap();
+        assert!(!text.contains(&dir.display().to_string()), "{name}: {text}");
+        let import = library.import_playlist(&file, None).unwrap();
+        assert!(import.unresolved.is_empty(), "{name}: {:?}", import.unresolved);
+        assert_eq!(playlist_titles(&library, import.playlist.id), expected, "{name}");
+    }
+
+    let m3u = dir.join("lists").join("absolute.m3u8");
+    library.export_playlist(playlist.id, &m3u, false).unwrap();
+    let text = std::fs::read_to_string(&m3u).unwrap();
+    assert!(text.starts_with("#EXTM3U\n#EXTINF:1,Black Sabbath - Paranoid\n"), "{text}");
+    assert!(text.contains(&format!("\n{}\n", so_what.display())), "{text}");
+
+    let text = std::fs::read_to_string(dir.join("lists").join("mix.xspf")).unwrap();
+    assert!(text.contains("<title>Mix &amp; Match</title>"), "{text}");
+    assert!(text.contains("<location>../music/Miles%20Davis/So%20What.wav</location>"), "{text}");
+
+    // A colon in a file name doesn't make a relative location look like a URI.
+    let folder = dir.join("music").join("Bon Iver");
+    let stacks = tagged(&folder, "Re: Stacks").artist("Bon Iver").album("Bon Iver").write();
+    library.index_directory(&dir.join("music")).unwrap();
+    let single = library.create_playlist("Stacks").unwrap();
+    library.add_to_playlist(single.id, &[stacks], None).unwrap();
+    for name in ["stacks.m3u8", "stacks.pls", "stacks.xspf"] {
+        let file = folder.join(name);
+        library.export_playlist(single.id, &file, true).unwrap();
+        let import = library.import_playlist(&file, None).unwrap();
+        assert!(import.unresolved.is_empty(), "{name}: {:?}", import.unresolved);
+        assert_eq!(playlist_titles(&library, import.playlist.id), ["Re: Stacks"], "{name}");
+    }
+    let text = std::fs::read_to_string(folder.join("stacks.xspf")).unwrap();
+    assert!(text.contains("<location>Re%3A%20Stacks.wav</location>"), "{text}");
+
+    // Relative paths keep working when the music and its playlists move together.
+    let moved = dir.with_extension("moved");
+    std::fs::remove_dir_all(&moved).ok();
+    std::fs::rename(&dir, &moved).unwrap();
+    let library = Library::open(&moved.join("library.db")).unwrap();
+    library.index_directory(&moved.join("music")).unwrap();
+    let import = library.import_playlist(&moved.join("lists").join("mix.pls"), None).unwrap();
+    let paths: Vec<_> = library.playlist_items(import.playlist.id).unwrap().into_iter().map(|item| item.path).collect();
+    assert!(paths.iter().all(|path| path.starts_with(&moved.display().to_string())), "{paths:?}");
+    std::fs::remove_dir_all(&moved).ok();
+}
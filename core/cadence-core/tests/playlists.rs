@@ -0,0 +1,53 @@
+use cadence_core::{Library, PlayContext};
+
+mod common;
+use common::{playlist_titles, tagged, temp_dir};
+
+#[test]
+fn playlists_are_built_reordered_and_played_in_order() {
+    let dir = temp_dir("edit");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    let [a, b, c, d] = ["A", "B", "C", "D"].map(|title| tagged(&music, title).write());
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+
+    let mix = library.create_playlist("  Road trip ").unwrap();
+    assert_eq!((mix.name.as_str(), mix.track_count), ("Road trip", 0));
+    assert!(library.create_playlist(" ").is_err());
+
+    library.add_to_playlist(mix.id, &[a.clone(), c.clone()], None).unwrap();
+    library.add_to_playlist(mix.id, std::slice::from_ref(&b), Some(1)).unwrap();
+    let mix = library.add_to_playlist(mix.id, &[a.clone(), d.clone()], Some(99)).unwrap();
+    assert_eq!(playlist_titles(&library, mix.id), ["A", "B", "C", "A", "D"]);
+    assert_eq!((mix.track_count, mix.duration_ms), (5, 5_000));
+
+    let items = library.playlist_items(mix.id).unwrap();
+    library.move_playlist_item(mix.id, items[4].id, 0).unwrap();
+    library.remove_from_playlist(mix.id, &[items[0].id, items[2].id]).unwrap();
+    assert_eq!(playlist_titles(&library, mix.id), ["D", "B", "A"]);
+    assert!(library.move_playlist_item(mix.id, items[0].id, 0).is_err());
+
+    // Only what is in the library goes in.
+    let outside = dir.join("elsewhere.wav");
+    assert!(library.add_to_playlist(mix.id, &[outside], None).is_err());
+    assert_eq!(playlist_titles(&library, mix.id).len(), 3);
+
+    let mix = library.rename_playlist(mix.id, "Commute").unwrap();
+    let other = library.create_playlist("Album").unwrap();
+    let names: Vec<_> = library.playlists().unwrap().into_iter().map(|p| p.name).collect();
+    assert_eq!(names, ["Album", "Commute"]);
+
+    // A file that leaves the library keeps its place but is skipped when played.
+    std::fs::remove_file(&b).unwrap();
+    library.index_directory(&music).unwrap();
+    assert_eq!(playlist_titles(&library, mix.id), ["D", format!("({})", b.display()).as_str(), "A"]);
+    let paths = library.context_tracks(&PlayContext::Playlist { id: mix.id }).unwrap();
+    assert_eq!(paths, [d, a]);
+
+    library.delete_playlist(other.id).unwrap();
+    assert!(library.playlist(other.id).unwrap().is_none());
+    assert!(library.playlist_items(other.id).is_err());
+    assert_eq!(library.playlists().unwrap().len(), 1);
+    std::fs::remove_dir_all(&dir).ok();
+}
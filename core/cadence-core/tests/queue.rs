@@ -0,0 +1,93 @@
+use cadence_core::{Library, PlayContext, PlayQueue};
+use std::path::PathBuf;
+
+mod common;
+use common::temp_dir;
+
+fn paths(queue: &[cadence_core::QueueItem]) -> Vec<&str> {
+    queue.iter().map(|item| item.path.to_str().unwrap()).collect()
+}
+
+#[test]
+fn edits_keep_history_apart_from_upcoming() {
+    let mut queue = PlayQueue::default();
+    queue.play_now(PathBuf::from("a"));
+    queue.enqueue(["c", "d"].map(PathBuf::from));
+    queue.play_next([PathBuf::from("b")]);
+    assert_eq!(paths(queue.upcoming()), ["b", "c", "d"]);
+
+    let d = queue.upcoming()[2].id;
+    queue.move_item(d, 0).unwrap();
+    assert_eq!(paths(queue.upcoming()), ["d", "b", "c"]);
+
+    assert_eq!(queue.advance().unwrap().path, PathBuf::from("d"));
+    assert_eq!(paths(queue.history()), ["a"]);
+
+    let c = queue.upcoming()[1].id;
+    queue.skip_to(c).unwrap();
+    assert_eq!(paths(queue.history()), ["a", "d", "b"]);
+    assert!(queue.upcoming().is_empty());
+
+    assert_eq!(queue.back().unwrap().path, PathBuf::from("b"));
+    assert_eq!(paths(queue.upcoming()), ["c"]);
+    assert!(queue.remove(queue.upcoming()[0].id));
+    assert!(queue.advance().is_none());
+}
+
+#[test]
+fn queue_survives_a_reopen() {
+    let dir = temp_dir("reopen");
+    let db = dir.join("library.db");
+    let mut queue = PlayQueue::default();
+    queue.play_now(PathBuf::from("played"));
+    queue.play_now(PathBuf::from("playing"));
+    queue.enqueue(["next", "later"].map(PathBuf::from));
+    let context = PlayContext::Search { query: "tone".into() };
+    queue.play_context(context.clone(), ["x", "y"].map(PathBuf::from).to_vec(), 0, false).unwrap();
+
+    Library::open(&db).unwrap().save_queue(&queue).unwrap();
+    let mut restored = Library::open(&db).unwrap().load_queue().unwrap();
+
+    assert_eq!(restored.history(), queue.history());
+    assert_eq!(restored.current(), queue.current());
+    assert_eq!(restored.upcoming(), queue.upcoming());
+    assert_eq!(restored.context(), Some(&context));
+    // Ids handed out after a restore don't collide with stored ones.
+    restored.enqueue([PathBuf::from("new")]);
+    let ids: Vec<i64> = restored.upcoming().iter().map(|item| item.id).collect();
+    assert_eq!(ids.len(), 4);
+    // Hand-queued tracks go ahead of the rest of the context.
+    assert_eq!(paths(restored.upcoming()), ["next", "later", "new", "y"]);
+    assert!(ids.iter().all(|&id| id <= ids[2]));
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn context_plays_through_after_hand_queued_tracks() {
+    let context = PlayContext::Artist { name: "Someone".into() };
+    let tracks: Vec<PathBuf> = ["a", "b", "c", "d", "e"].map(PathBuf::from).to_vec();
+    let mut queue = PlayQueue::default();
+
+    let current = queue.play_context(context.clone(), tracks.clone(), 1, false).unwrap();
+    assert_eq!(current.path, PathBuf::from("b"));
+    assert_eq!(paths(queue.upcoming()), ["c", "d", "e"]);
+
+    queue.enqueue([PathBuf::from("mine")]);
+    assert_eq!(paths(queue.upcoming()), ["mine", "c", "d", "e"]);
+
+    queue.set_shuffle(true);
+    let mut shuffled = paths(queue.upcoming());
+    assert_eq!(shuffled[0], "mine");
+    shuffled.sort();
+    assert_eq!(shuffled, ["a", "c", "d", "e", "mine"]);
+
+    // Unshuffling carries on in order after the current track.
+    queue.set_shuffle(false);
+    assert_eq!(paths(queue.upcoming()), ["mine", "c", "d", "e"]);
+    assert_eq!(queue.context(), Some(&context));
+
+    queue.play_now(PathBuf::from("other"));
+    assert_eq!(queue.context(), None);
+    assert_eq!(paths(queue.upcoming()), ["mine"]);
+}
@@ -0,0 +1,124 @@
+use cadence_core::{Library, QueryError};
+
+mod common;
+use common::{sorted_titles, tagged, temp_dir};
+
+/// Titles of what `query` finds, sorted.
+fn search(library: &Library, query: &str) -> Vec<String> {
+    sorted_titles(library.search(query).unwrap())
+}
+
+#[test]
+fn query_syntax_filters_by_field_range_and_format() {
+    let dir = temp_dir("syntax");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    let songs = [
+        ("Blue Monday", "New Order", "Power", "Electronic", 1983, 4),
+        ("Monday Morning", "Fleetwood Mac", "Rumours", "Rock", 1977, 2),
+        ("Blue in Green", "Miles Davis", "Kind of Blue", "Jazz", 1959, 3),
+        ("Green Onions", "Booker T", "Green Onions", "Soul", 1962, 1),
+    ];
+    for (title, artist, album, genre, year, seconds) in songs {
+        tagged(&music, title).artist(artist).album(album).genre(genre).year(year).seconds(seconds).write();
+    }
+    tagged(&music, "Re: Stacks").file_name("Re Stacks").artist("Bon Iver").write();
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+
+    assert_eq!(search(&library, "blue"), ["Blue Monday", "Blue in Green"]);
+    assert_eq!(search(&library, "album:blue"), ["Blue in Green"]);
+    assert_eq!(search(&library, "\"green onions\""), ["Green Onions"]);
+    assert_eq!(search(&library, "\"onions green\""), Vec::<String>::new());
+    assert_eq!(search(&library, "genre:jazz OR genre:soul"), ["Blue in Green", "Green Onions"]);
+    assert_eq!(search(&library, "monday -artist:\"new order\""), ["Monday Morning"]);
+    assert_eq!(search(&library, "year:1960..1979"), ["Green Onions", "Monday Morning"]);
+    assert_eq!(search(&library, "year:<1960 OR year:>=1980"), ["Blue Monday", "Blue in Green"]);
+    assert_eq!(search(&library, "duration:2..0:03"), ["Blue in Green", "Monday Morning"]);
+    assert_eq!(search(&library, "(blue OR green) NOT year:1959"), ["Blue Monday", "Green Onions"]);
+    assert_eq!(search(&library, "codec:pcm duration:>3").len(), 1);
+    assert!(search(&library, "format:lossy").is_empty());
+    // Only field names make a field; anything else before a colon is just text.
+    assert_eq!(search(&library, "Re: Stacks"), ["Re: Stacks"]);
+    assert_eq!(search(&library, "mood:happy"), Vec::<String>::new());
+
+    // The best match comes first.
+    let ranked = library.search("green").unwrap();
+    assert_eq!(ranked[0].title, "Green Onions");
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn malformed_queries_report_where_they_went_wrong() {
+    let dir = temp_dir("errors");
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    let error = |query: &str| {
+        let e = library.search(query).unwrap_err();
+        let e = e.downcast_ref::<QueryError>().unwrap_or_else(|| panic!("{query}: {e:#}"));
+        (e.position, e.message.clone())
+    };
+
+    assert_eq!(error("blue \"in green").0, 5);
+    assert_eq!(error("blue year:199x").0, 10);
+    assert_eq!(error("year:>9223372036854775807"), (5, "\">9223372036854775807\" is out of range".to_string()));
+    assert_eq!(error("duration:<=9223372036854775").0, 9);
+    assert_eq!(error("year:2000..1990").0, 5);
+    assert_eq!(error("(blue OR green").0, 0);
+    assert_eq!(error("blue)").0, 4);
+    assert_eq!(error("blue OR").0, 7);
+    assert_eq!(error("OR blue").0, 0);
+    // Nesting is capped rather than left to overflow the stack.
+    assert_eq!(error(&format!("{}blue", "(".repeat(200_000))).0, 65);
+    assert_eq!(error(&"NOT ".repeat(100)).0, 260);
+    assert!(library.search(&format!("{}blue{}", "(".repeat(64), ")".repeat(64))).is_ok());
+
+    // Punctuation on its own isn't an error, just nothing to look for.
+    assert!(library.search("- & !").unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn accents_case_and_typos_are_forgiven() {
+    let dir = temp_dir("fuzzy");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    let songs = [
+        ("Jóga", "Björk", "Homogenic"),
+        ("Hey Jude", "The Beatles", "Past Masters"),
+        ("Help!", "The Beatles", "Help!"),
+        ("Heroes", "David Bowie", "\"Heroes\""),
+        ("Café del Mar", "Energy 52", "Café del Mar"),
+        ("Lạc Trôi", "Sơn Tùng", "Lạc Trôi"),
+    ];
+    for (title, artist, album) in songs {
+        tagged(&music, title).artist(artist).album(album).write();
+    }
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+
+    assert_eq!(search(&library, "bjork joga"), ["Jóga"]);
+    assert_eq!(search(&library, "BJÖRK"), ["Jóga"]);
+    assert_eq!(search(&library, "artist:bjork"), ["Jóga"]);
+    // Decomposed accents (as macOS file names have them) fold the same way.
+    assert_eq!(search(&library, "cafe\u{301}"), ["Café del Mar"]);
+    assert_eq!(search(&library, "CAFE"), ["Café del Mar"]);
+
+    // Nothing is spelled "beatls", so words spelled like it are looked for instead.
+    assert_eq!(search(&library, "beatls"), ["Help!", "Hey Jude"]);
+    assert_eq!(search(&library, "beatls jdue"), ["Hey Jude"]);
+    assert_eq!(search(&library, "bowei"), ["Heroes"]);
+    // Accents don't count as typos: "cafés" is one letter off "cafe".
+    assert_eq!(search(&library, "cafés"), ["Café del Mar"]);
+    // Nor do accents the search index drops outside Latin-1: "sơm" is a letter off "son".
+    assert_eq!(search(&library, "sơm"), ["Lạc Trôi"]);
+    // The closest spelling ranks first: "hepl" is a swap away from "help", further from "hey".
+    let found = library.search("hepl").unwrap();
+    assert_eq!(found[0].title, "Help!");
+    // Exclusions stay exact, and short words aren't guessed at.
+    assert_eq!(search(&library, "beatls -jdue"), ["Help!", "Hey Jude"]);
+    assert!(search(&library, "xq").is_empty());
+    assert!(search(&library, "zzzzzz").is_empty());
+
+    std::fs::remove_dir_all(&dir).ok();
+}
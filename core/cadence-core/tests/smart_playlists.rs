@@ -0,0 +1,83 @@
+use cadence_core::{
+    DateField, Library, NumberField, NumberOp, PlayContext, Rule, SmartPlaylistRules, SmartSort, SortOrder, TextField,
+    TextOp,
+};
+
+mod common;
+use common::{sorted_titles, tagged, temp_dir};
+
+#[test]
+fn rules_pick_tracks_from_the_library_as_it_is() {
+    let dir = temp_dir("rules");
+    let music = dir.join("music");
+    std::fs::create_dir_all(&music).unwrap();
+    let so_what = tagged(&music, "So What").genre("Jazz").write();
+    tagged(&music, "Blue in Green").genre("jazz").write();
+    tagged(&music, "Paranoid").genre("Metal").write();
+    tagged(&music, "Untitled").write();
+    let library = Library::open(&dir.join("library.db")).unwrap();
+    library.index_directory(&music).unwrap();
+    for _ in 0..3 {
+        library.record_play(&so_what).unwrap();
+    }
+
+    // Genre is Jazz and added in the last 30 days and played fewer than 3 times.
+    let fresh_jazz = SmartPlaylistRules {
+        rule: Rule::All {
+            rules: vec![
+                Rule::Text { field: TextField::Genre, op: TextOp::Is, value: "Jazz".into() },
+                Rule::InLast { field: DateField::Added, days: 30 },
+                Rule::Number { field: NumberField::PlayCount, op: NumberOp::Lt, value: 3 },
+            ],
+        },
+        sort: SmartSort::Random,
+        order: SortOrder::Asc,
+        limit: Some(100),
+    };
+    let playlist = library.create_smart_playlist(" Fresh jazz ", &fresh_jazz).unwrap();
+    assert_eq!((playlist.name.as_str(), playlist.track_count), ("Fresh jazz", 1));
+    assert_eq!(sorted_titles(library.smart_playlist_tracks(playlist.id).unwrap()), ["Blue in Green"]);
+
+    // Stored as JSON and read back the same.
+    let stored = library.smart_playlist(playlist.id).unwrap().unwrap();
+    assert_eq!(stored.rules, fresh_jazz);
+    let json = serde_json::to_value(&stored.rules).unwrap();
+    assert_eq!(json["rule"]["rules"][1], serde_json::json!({ "type": "in_last", "field": "added", "days": 30 }));
+
+    // A missing genre fails the test, so negating it picks the untagged track up.
+    let not_jazz = SmartPlaylistRules {
+        rule: Rule::Not { rule: Box::new(Rule::Text { field: TextField::Genre, op: TextOp::Contains, value: "azz".into() }) },
+        sort: SmartSort::Title,
+        order: SortOrder::Desc,
+        limit: None,
+    };
+    let other = library.create_smart_playlist("Not jazz", &not_jazz).unwrap();
+    let listed: Vec<String> = library.smart_playlist_tracks(other.id).unwrap().into_iter().map(|t| t.title).collect();
+    assert_eq!(listed, ["Untitled", "Paranoid"]);
+
+    // Results follow the library as it changes.
+    tagged(&music, "Naima").genre("Jazz").write();
+    library.index_directory(&music).unwrap();
+    assert_eq!(sorted_titles(library.smart_playlist_tracks(playlist.id).unwrap()), ["Blue in Green", "Naima"]);
+    let paths = library.context_tracks(&PlayContext::SmartPlaylist { id: playlist.id }).unwrap();
+    assert_eq!(paths.len(), 2);
+    let count = |id| library.smart_playlists().unwrap().into_iter().find(|p| p.id == id).unwrap().track_count;
+    assert_eq!(count(playlist.id), 2);
+    for _ in 0..3 {
+        library.record_play(&music.join("Naima.wav")).unwrap();
+    }
+    assert_eq!(count(playlist.id), 1);
+
+    let limited = SmartPlaylistRules { limit: Some(1), sort: SmartSort::Title, ..fresh_jazz.clone() };
+    let playlist = library.update_smart_playlist(playlist.id, "One", &limited).unwrap();
+    assert_eq!((playlist.name.as_str(), playlist.track_count), ("One", 1));
+    assert_eq!(sorted_titles(library.smart_playlist_tracks(playlist.id).unwrap()), ["Blue in Green"]);
+    assert!(library.update_smart_playlist(playlist.id, "None", &SmartPlaylistRules { limit: Some(0), ..limited }).is_err());
+
+    let names: Vec<_> = library.smart_playlists().unwrap().into_iter().map(|p| p.name).collect();
+    assert_eq!(names, ["Not jazz", "One"]);
+    library.delete_smart_playlist(other.id).unwrap();
+    assert!(library.smart_playlist(other.id).unwrap().is_none());
+    assert!(library.smart_playlist_tracks(other.id).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}